@@ -1,6 +1,7 @@
 .autoimport +
 
 .word reset
+.include "version.inc"
 
 reset:
   lda #$0F
@@ -1,4 +1,5 @@
 .export xstuff, ystuff, readchar
+.include "keyboard.inc"
 
 xstuff:
   ldx #$00
@@ -8,15 +9,18 @@ ystuff:
   ldy #$00
   rts
 
+; waits for a key from the irq handler, clobbers X
 readchar:
-  lda $10
-  BEQ readchar
-  CMP #$E0
-  BEQ readchar_up
-  rts
-readchar_up:
-  lda $10
-  ora #$80 
+  ldx key_tail
+  cpx key_head
+  beq readchar
+  lda key_ring,X
+  pha
+  inx
+  txa
+  and #$0F
+  sta key_tail
+  pla
   rts
 
 test:
@@ -1,6 +1,20 @@
 .segment "KERNEL"
 .export version
+.include "keyboard.inc"
+
+; the vectors in machine.toml point at these, so they stay put when the
+; kernel changes
+  jmp reset
+  jmp nmi
+  jmp irq
+
 reset:
+  lda #$00
+  sta key_head
+  sta key_tail
+  lda #KEY_IRQ_ENABLE
+  sta KEY_CONTROL
+  cli
   ldx #$03
   lda version,X
   cmp $8002,X
@@ -38,6 +52,36 @@ err_loop:
   iny
   jmp err_loop
 
+nmi:
+  rti
+
+; the keyboard is the only thing on IRQ, a key goes into the ring unless it's
+; full. Releases are the scancode with bit 7 set.
+irq:
+  pha
+  txa
+  pha
+  lda KEY_DATA
+  cmp #$E0
+  bne @store
+  lda KEY_DATA
+  ora #$80
+@store:
+  ldx key_head
+  sta key_ring,X
+  inx
+  txa
+  and #$0F
+  cmp key_tail
+  beq @full
+  sta key_head
+@full:
+  sta KEY_STATUS
+  pla
+  tax
+  pla
+  rti
+
 err_str: .asciiz "Error, invalid executable version!"
 
 .include "version.inc"
\ No newline at end of file
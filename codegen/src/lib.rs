@@ -46,11 +46,15 @@ fn flag_name<'z>(f: StatusFlag) -> &'z str {
 }
 
 fn branch_name(f: StatusFlag, nf: bool) -> String {
-    match f {
-        StatusFlag::N => (if nf { "BPL" } else { "BMI" }),
-        StatusFlag::V => (if nf { "BVC" } else { "BVS" }),
-        StatusFlag::C => (if nf { "BCC" } else { "BCS" }),
-        StatusFlag::Z => (if nf { "BNE" } else { "BEQ" }),
+    match (f, nf) {
+        (StatusFlag::N, false) => "BPL",
+        (StatusFlag::N, true) => "BMI",
+        (StatusFlag::V, false) => "BVC",
+        (StatusFlag::V, true) => "BVS",
+        (StatusFlag::C, false) => "BCC",
+        (StatusFlag::C, true) => "BCS",
+        (StatusFlag::Z, false) => "BNE",
+        (StatusFlag::Z, true) => "BEQ",
         _ => panic!("Register {} does not have a branch operation!", f),
     }
     .to_owned()
@@ -68,6 +72,10 @@ enum AddressingMode {
     AbsY,
     IdX,
     IdY,
+    /// `(zp)`, 65C02 only
+    ZpInd,
+    /// `zp,rel` of the 65C02 BBR/BBS instructions
+    ZpRel,
     Jmp,
     Jsr,
     Invalid,
@@ -89,6 +97,8 @@ impl Display for AddressingMode {
                 AddressingMode::AbsY => "abs,Y",
                 AddressingMode::IdX => "(zp,X)",
                 AddressingMode::IdY => "(zp),Y",
+                AddressingMode::ZpInd => "(zp)",
+                AddressingMode::ZpRel => "zp,rel",
                 AddressingMode::Jmp => "",
                 AddressingMode::Jsr => "",
                 AddressingMode::Invalid => "INVALID",
@@ -441,17 +451,23 @@ struct Opcode {
     cmt: String,
     i: usize,
     src: [String; 8],
+    mode: AddressingMode,
+    /// generate the 65C02 version of the shared instructions
+    cmos: bool,
 }
 
 const STR_VAL: String = String::new();
 
 impl Opcode {
     fn new(op: usize) -> Self {
+        let (mode, _) = OPS[op & 3][(op >> 2) & 7][(op >> 5) & 7];
         Opcode {
             code: op,
             cmt: "".to_owned(),
             i: 0,
             src: [STR_VAL; 8],
+            mode,
+            cmos: false,
         }
     }
 
@@ -490,10 +506,7 @@ impl Opcode {
     }
 
     fn cmt(&mut self, cmd: &str) {
-        let cc = self.code & 3;
-        let bbb = (self.code >> 2) & 7;
-        let aaa = (self.code >> 5) & 7;
-        let (addr_mode, _) = OPS[cc][bbb][aaa];
+        let addr_mode = self.mode;
         if addr_mode != AddressingMode::None
             && addr_mode != AddressingMode::Jmp
             && addr_mode != AddressingMode::Jsr
@@ -510,11 +523,17 @@ impl Opcode {
     }
 
     fn invalid_opcode(&self) -> bool {
-        let cc = self.code & 3;
-        let bbb = (self.code >> 2) & 7;
-        let aaa = (self.code >> 5) & 7;
-        let (addr_mode, _) = OPS[cc][bbb][aaa];
-        addr_mode == AddressingMode::Invalid
+        self.mode == AddressingMode::Invalid
+    }
+
+    /// The dummy cycle of read-modify-write instructions, the NMOS 6502 writes
+    /// the unmodified value back while the 65C02 reads it a second time.
+    fn rmw_read(&mut self) {
+        if self.cmos {
+            self.t("self.adl_adh = gd(&pins) as u16;");
+        } else {
+            self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        }
     }
 
     fn enc_addr(&mut self, addr_mode: AddressingMode, mem_access: MemoryAccess) {
@@ -522,24 +541,26 @@ impl Opcode {
             // no addressing, this still puts the PC on the address bus without
             // incrementing the PC
             AddressingMode::None => self.t("sa(&mut pins, self.pc);"),
-            AddressingMode::Imm => self.t("sa(&mut pins, self.pc);self.pc+=1;"),
+            AddressingMode::Imm => {
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);")
+            }
             AddressingMode::Zp => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
                 self.t("let zz = gd(&pins) as u16;sa(&mut pins, zz);");
             }
             AddressingMode::ZpX => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
                 self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.adl_adh);");
-                self.t("sa(&mut pins, (self.adl_adh + (self.x as u16)) & 0x00FF);");
+                self.t("sa(&mut pins, (self.adl_adh.wrapping_add(self.x as u16)) & 0x00FF);");
             }
             AddressingMode::ZpY => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
                 self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.adl_adh);");
-                self.t("sa(&mut pins, (self.adl_adh+(self.y as u16))&0x00FF);");
+                self.t("sa(&mut pins, (self.adl_adh.wrapping_add(self.y as u16))&0x00FF);");
             }
             AddressingMode::Abs => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
-                self.t("sa(&mut pins, self.pc);self.pc+=1;self.adl_adh = gd(&pins) as u16;");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
                 self.t("let zz = gd(&pins);sa(&mut pins, ((zz as u16) << 8) | self.adl_adh);");
             }
             AddressingMode::AbsX => {
@@ -547,42 +568,50 @@ impl Opcode {
                 // this needs to check if a page boundary is crossed, which costs
                 // and additional cycle, but this early-out only happens when the
                 // instruction doesn"t need to write back to memory
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
-                self.t("sa(&mut pins, self.pc);self.pc+=1;self.adl_adh = gd(&pins) as u16;");
-                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh+(self.x as u16))&0xFF));");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
+                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh.wrapping_add(self.x as u16))&0xFF));");
                 if mem_access == MemoryAccess::R {
-                    self.ta("self.ir += (!((self.adl_adh >> 8) - ((self.adl_adh + (self.x as u16)) >> 8)))&1;");
+                    self.ta("self.ir += (!((self.adl_adh >> 8).wrapping_sub((self.adl_adh.wrapping_add(self.x as u16)) >> 8)))&1;");
                 }
-                self.t("sa(&mut pins, self.adl_adh+(self.x as u16));");
+                self.t("sa(&mut pins, self.adl_adh.wrapping_add(self.x as u16));");
             }
             AddressingMode::AbsY => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
-                self.t("sa(&mut pins, self.pc);self.pc+=1;self.adl_adh = gd(&pins) as u16;");
-                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh+(self.y as u16))&0xFF));");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
+                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh.wrapping_add(self.y as u16))&0xFF));");
                 if mem_access == MemoryAccess::R {
                     // skip next tick if read access and page not crossed
-                    self.ta("self.ir += (!((self.adl_adh >> 8) - ((self.adl_adh + (self.y as u16)) >> 8)))&1;");
+                    self.ta("self.ir += (!((self.adl_adh >> 8).wrapping_sub((self.adl_adh.wrapping_add(self.y as u16)) >> 8)))&1;");
                 }
-                self.t("sa(&mut pins, self.adl_adh+(self.y as u16));");
+                self.t("sa(&mut pins, self.adl_adh.wrapping_add(self.y as u16));");
             }
             AddressingMode::IdX => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
-                self.t("self.adl_adh = ga(&pins);sa(&mut pins, self.adl_adh);");
-                self.t("self.adl_adh = (self.adl_adh+(self.x as u16))&0xFF;sa(&mut pins, self.adl_adh);");
-                self.t("sa(&mut pins, (self.adl_adh+1) & 0xFF); self.adl_adh = ga(&pins);");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.adl_adh);");
+                self.t("self.adl_adh = (self.adl_adh.wrapping_add(self.x as u16))&0xFF;sa(&mut pins, self.adl_adh);");
+                self.t("sa(&mut pins, (self.adl_adh+1) & 0xFF); self.adl_adh = gd(&pins) as u16;");
                 self.t("let zz = gd(&pins);sa(&mut pins, ((zz as u16) << 8) | self.adl_adh);");
             }
             AddressingMode::IdY => {
-                self.t("sa(&mut pins, self.pc);self.pc+=1;");
-                self.t("self.adl_adh = ga(&pins);sa(&mut pins, self.adl_adh);");
-                self.t("sa(&mut pins, (self.adl_adh+1) & 0xFF); self.adl_adh = ga(&pins);");
-                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh+(self.y as u16))&0xFF));");
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.adl_adh);");
+                self.t("sa(&mut pins, (self.adl_adh+1) & 0xFF); self.adl_adh = gd(&pins) as u16;");
+                self.t("self.adl_adh|=(gd(&pins)as u16)<<8;sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh.wrapping_add(self.y as u16))&0xFF));");
                 if mem_access == MemoryAccess::R {
                     // skip next tick if read access and page not crossed
-                    self.ta("self.ir += (!((self.adl_adh >> 8) - ((self.adl_adh + (self.y as u16)) >> 8)))&1;");
+                    self.ta("self.ir += (!((self.adl_adh >> 8).wrapping_sub((self.adl_adh.wrapping_add(self.y as u16)) >> 8)))&1;");
                 }
-                self.t("sa(&mut pins, self.adl_adh+(self.y as u16));");
+                self.t("sa(&mut pins, self.adl_adh.wrapping_add(self.y as u16));");
+            }
+            AddressingMode::ZpInd => {
+                self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+                self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.adl_adh);");
+                self.t("sa(&mut pins, (self.adl_adh+1) & 0xFF); self.adl_adh = gd(&pins) as u16;");
+                self.t("let zz = gd(&pins);sa(&mut pins, ((zz as u16) << 8) | self.adl_adh);");
             }
+            // BBR/BBS do their own addressing
+            AddressingMode::ZpRel => {}
             AddressingMode::Jmp => {}
             AddressingMode::Jsr => {}
             AddressingMode::Invalid => {}
@@ -594,10 +623,16 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_brk(&mut self) {
         self.cmt("BRK");
-        self.t("if !self.brk_flags.contains(BreakFlags::NMI|BreakFlags::IRQ) { self.pc += 1; } sad(&mut pins, 0x0100 | self.sp as u16, (self.pc >> 8) as u8); self.sp = (Wrapping(self.sp) - Wrapping(1)).0; if !self.brk_flags.contains(BreakFlags::RESET) { wr(&mut pins)}");
+        self.t("if !self.brk_flags.intersects(BreakFlags::NMI|BreakFlags::IRQ) { self.pc = self.pc.wrapping_add(1); } sad(&mut pins, 0x0100 | self.sp as u16, (self.pc >> 8) as u8); self.sp = (Wrapping(self.sp) - Wrapping(1)).0; if !self.brk_flags.contains(BreakFlags::RESET) { wr(&mut pins)}");
         self.t("sad(&mut pins, 0x0100 | self.sp as u16, (self.pc) as u8);self.sp = (Wrapping(self.sp) - Wrapping(1)).0;if !self.brk_flags.contains(BreakFlags::RESET) {wr(&mut pins)}");
-        self.t("sad(&mut pins, 0x0100 | self.sp as u16, self.sr.bits | StatusRegister::X.bits);self.sp = (Wrapping(self.sp) - Wrapping(1)).0;if self.brk_flags.contains(BreakFlags::RESET) {self.adl_adh = 0xFFFC;} else {wr(&mut pins);if self.brk_flags.contains(BreakFlags::NMI) {self.adl_adh = 0xFFFA} else {self.adl_adh = 0xFFFE}}");
-        self.t("sa(&mut pins, self.adl_adh);self.adl_adh += 1;self.sr.set(StatusRegister::I | StatusRegister::B, true);self.brk_flags = BreakFlags::empty();");
+        self.t("let b = if self.brk_flags.intersects(BreakFlags::IRQ|BreakFlags::NMI) {0} else {StatusRegister::B.bits};sad(&mut pins, 0x0100 | self.sp as u16, self.sr.bits | StatusRegister::X.bits | b);self.sp = (Wrapping(self.sp) - Wrapping(1)).0;if self.brk_flags.contains(BreakFlags::RESET) {self.adl_adh = 0xFFFC;} else {wr(&mut pins);if self.brk_flags.contains(BreakFlags::NMI) {self.adl_adh = 0xFFFA} else {self.adl_adh = 0xFFFE}}");
+        // the 65C02 also clears the decimal flag
+        let cld = if self.cmos {
+            "self.sr.remove(StatusRegister::D);"
+        } else {
+            ""
+        };
+        self.t(&format!("sa(&mut pins, self.adl_adh);self.adl_adh += 1;self.sr.set(StatusRegister::I | StatusRegister::B, true);{}self.brk_flags = BreakFlags::empty();", cld));
         self.t("sa(&mut pins, self.adl_adh);self.adl_adh = gd(&pins) as u16; /* NMI \"half-hijacking\" not possible */");
         self.t("self.pc = ((gd(&pins) as u16) << 8) | self.adl_adh;");
     }
@@ -636,7 +671,7 @@ impl Opcode {
         //undocumented LXA
         //and immediate byte with A, then load X with A
         self.u_cmt("LXA");
-        self.t("let zz = (self.ac|0xEE)&gd(&pins);self.ac=zz;self.x=zz;self.nz(self.ac);");
+        self.t("let zz = (self.ac|self.magic)&gd(&pins);self.ac=zz;self.x=zz;self.nz(self.ac);");
     }
     //-------------------------------------------------------------------------------
     fn i_sta(&mut self) {
@@ -691,24 +726,25 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_php(&mut self) {
         self.cmt("PHP");
-        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.sr.bits|StatusRegister::X.bits);self.sp-=1;wr(&mut pins);");
+        // B only exists on the stack, PHP always pushes it set
+        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.sr.bits|StatusRegister::X.bits|StatusRegister::B.bits);self.sp=self.sp.wrapping_sub(1);wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
     fn i_plp(&mut self) {
         self.cmt("PLP");
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;"); //read junk byte from current SP
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);"); //read junk byte from current SP
         self.t("sa(&mut pins, 0x0100|(self.sp as u16));"); //read actual byte
         self.t("self.sr = StatusRegister::from_bits_truncate((gd(&pins)|StatusRegister::B.bits)&!StatusRegister::X.bits);");
     }
     //-------------------------------------------------------------------------------
     fn i_pha(&mut self) {
         self.cmt("PHA");
-        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.ac);self.sp-=1;wr(&mut pins);");
+        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.ac);self.sp=self.sp.wrapping_sub(1);wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
     fn i_pla(&mut self) {
         self.cmt("PLA");
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;"); //read junk byte from current SP
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);"); //read junk byte from current SP
         self.t("sa(&mut pins, 0x0100|(self.sp as u16));"); //read actual byte
         self.t("self.ac=gd(&pins);self.nz(self.ac);");
     }
@@ -725,8 +761,16 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_br(&mut self, f: StatusFlag, nf: bool) {
         self.cmt(branch_name(f, nf).as_str());
+        self.branch(&format!(
+            "self.sr.contains(StatusRegister::{}) == {}",
+            flag_name(f),
+            !nf
+        ));
+    }
+    //-------------------------------------------------------------------------------
+    fn branch(&mut self, not_taken: &str) {
         //if branch not taken?
-        self.t(("sa(&mut pins, self.pc);let zz = gd(&pins) as i8; self.adl_adh=if zz < 0 { self.pc - zz.abs() as u16 } else {self.pc + zz.abs() as u16 }; if self.sr.contains(StatusRegister::".to_owned() + flag_name(f) + ") == "+ if !nf { "true" } else { "false" } +" { fetch(&mut pins, self.pc) };").as_str());
+        self.t(&format!("sa(&mut pins, self.pc);let zz = gd(&pins) as i8; self.adl_adh=self.pc.wrapping_add(zz as i16 as u16); if {} {{ fetch(&mut pins, self.pc) }};", not_taken));
         //branch taken: shortcut if page not crossed, "branchquirk" interrupt fix
         self.t("sa(&mut pins, (self.pc & 0xFF00)|(self.adl_adh&0x00FF));if (self.adl_adh & 0xFF00) == (self.pc & 0xFF00) { self.pc = self.adl_adh; self.irq_pip>>=1; self.nmi_pip>>=1; fetch(&mut pins, self.pc) }");
         //page crossed extra cycle{
@@ -735,32 +779,46 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_jmp(&mut self) {
         self.cmt("JMP");
-        self.t("sa(&mut pins, self.pc);self.pc+=1;");
-        self.t("sa(&mut pins, self.pc);self.pc+=1;self.adl_adh = gd(&pins) as u16;");
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
         self.t("self.pc = ((gd(&pins) as u16)<<8)|self.adl_adh;");
     }
     //-------------------------------------------------------------------------------
     fn i_jmpi(&mut self) {
         self.cmt("JMPI");
-        self.t("sa(&mut pins, self.pc);self.pc+=1;");
-        self.t("sa(&mut pins, self.pc);self.pc+=1;self.adl_adh = gd(&pins) as u16;");
+        if self.cmos {
+            // the 65C02 fixed the page wrap bug at the cost of a cycle
+            self.jmp_indirect("");
+            return;
+        }
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
         self.t("self.adl_adh|=(gd(&pins) as u16)<<8;sa(&mut pins, self.adl_adh);");
-        self.t("sa(&mut pins, (self.adl_adh&0xFF00)|((self.adl_adh+1)&0x00FF));self.adl_adh = gd(&pins) as u16;");
+        self.t("sa(&mut pins, (self.adl_adh&0xFF00)|(self.adl_adh.wrapping_add(1)&0x00FF));self.adl_adh = gd(&pins) as u16;");
+        self.t("self.pc = ((gd(&pins) as u16)<<8)|self.adl_adh;");
+    }
+    //-------------------------------------------------------------------------------
+    fn jmp_indirect(&mut self, index: &str) {
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);self.adl_adh = gd(&pins) as u16;");
+        self.t("self.adl_adh|=(gd(&pins) as u16)<<8;sa(&mut pins, self.pc.wrapping_sub(1));");
+        self.t(&format!("{}sa(&mut pins, self.adl_adh);", index));
+        self.t("let zz = gd(&pins) as u16;sa(&mut pins, self.adl_adh.wrapping_add(1));self.adl_adh = zz;");
         self.t("self.pc = ((gd(&pins) as u16)<<8)|self.adl_adh;");
     }
     //-------------------------------------------------------------------------------
     fn i_jsr(&mut self) {
         self.cmt("JSR");
         //read low byte of target address
-        self.t("sa(&mut pins, self.pc);self.pc+=1;");
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
         //put SP on addr bus, next cycle is a junk read
         self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.adl_adh = gd(&pins) as u16;");
         //write PC high byte to stack
         self.t(
-            "sad(&mut pins, 0x0100|(self.sp as u16), (self.pc>>8) as u8);self.sp-=1;wr(&mut pins);",
+            "sad(&mut pins, 0x0100|(self.sp as u16), (self.pc>>8) as u8);self.sp=self.sp.wrapping_sub(1);wr(&mut pins);",
         );
         //write PC low byte to stack
-        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.pc as u8);self.sp-=1;wr(&mut pins);");
+        self.t("sad(&mut pins, 0x0100|(self.sp as u16), self.pc as u8);self.sp=self.sp.wrapping_sub(1);wr(&mut pins);");
         //load target address high byte
         self.t("sa(&mut pins, self.pc);");
         //load PC and done
@@ -770,13 +828,13 @@ impl Opcode {
     fn i_rts(&mut self) {
         self.cmt("RTS");
         //put SP on stack and do a junk read
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;");
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);");
         //load return address low byte from stack
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;");
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);");
         //load return address high byte from stack
         self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.adl_adh = gd(&pins) as u16;");
         //put return address in PC, this is one byte before next self, do junk read from PC
-        self.t("self.pc = ((gd(&pins) as u16)<<8)|self.adl_adh;sa(&mut pins, self.pc);self.pc+=1;");
+        self.t("self.pc = ((gd(&pins) as u16)<<8)|self.adl_adh;sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
         //next tick is selfcode fetch
         self.t("");
     }
@@ -784,11 +842,11 @@ impl Opcode {
     fn i_rti(&mut self) {
         self.cmt("RTI");
         //put SP on stack and do a junk read
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;");
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);");
         //load processor status flag from stack
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;");
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);");
         //load return address low byte from stack
-        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp+=1;self.sr = StatusRegister::from_bits_truncate((gd(&pins)|StatusRegister::B.bits)&!StatusRegister::X.bits);");
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);self.sr = StatusRegister::from_bits_truncate((gd(&pins)|StatusRegister::B.bits)&!StatusRegister::X.bits);");
         //load return address high byte from stack
         self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.adl_adh = gd(&pins) as u16;");
         //update PC (which is already placed on the right return-to instruction);
@@ -813,11 +871,21 @@ impl Opcode {
     fn i_adc(&mut self) {
         self.cmt("ADC");
         self.t("self.adc(gd(&pins));");
+        self.decimal_cycle();
     }
     //-------------------------------------------------------------------------------
     fn i_sbc(&mut self) {
         self.cmt("SBC");
         self.t("self.sbc(gd(&pins));");
+        self.decimal_cycle();
+    }
+    //-------------------------------------------------------------------------------
+    fn decimal_cycle(&mut self) {
+        // the 65C02 takes an extra cycle to fix up the flags in decimal mode
+        if self.cmos {
+            self.ta("if self.sr.contains(StatusRegister::D) { sa(&mut pins, self.pc); } else { fetch(&mut pins, self.pc); }");
+            self.t("");
+        }
     }
     //-------------------------------------------------------------------------------
     fn u_sbc(&mut self) {
@@ -854,13 +922,13 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_dec(&mut self) {
         self.cmt("DEC");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("self.adl_adh=(Wrapping(self.adl_adh)-Wrapping(1)).0;; self.nz(self.adl_adh as u8);sd(&mut pins, self.adl_adh as u8);wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
     fn i_inc(&mut self) {
         self.cmt("INC");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("self.adl_adh=(Wrapping(self.adl_adh)+Wrapping(1)).0;; self.nz(self.adl_adh as u8);sd(&mut pins, self.adl_adh as u8);wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
@@ -893,7 +961,7 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_asl(&mut self) {
         self.cmt("ASL");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("sd(&mut pins, self.asl(self.adl_adh as u8));wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
@@ -904,13 +972,13 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_lsr(&mut self) {
         self.cmt("LSR");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("sd(&mut pins, self.lsr(self.adl_adh as u8));wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
     fn i_lsra(&mut self) {
         self.cmt("LSRA");
-        self.t("self.ac = self.asl(self.ac);");
+        self.t("self.ac = self.lsr(self.ac);");
     }
     //-------------------------------------------------------------------------------
     fn u_slo(&mut self) {
@@ -923,7 +991,7 @@ impl Opcode {
     fn x_asr(&mut self) {
         //undocumented AND+LSR
         self.u_cmt("ASR");
-        self.t("self.ac=gd(&pins);self.ac = self.asl(self.ac);");
+        self.t("self.ac&=gd(&pins);self.ac = self.lsr(self.ac);");
     }
     //-------------------------------------------------------------------------------
     fn u_sre(&mut self) {
@@ -935,7 +1003,7 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_rol(&mut self) {
         self.cmt("ROL");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("sd(&mut pins, self.rol(self.adl_adh as u8));wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
@@ -953,7 +1021,7 @@ impl Opcode {
     //-------------------------------------------------------------------------------
     fn i_ror(&mut self) {
         self.cmt("ROR");
-        self.t("self.adl_adh = gd(&pins) as u16;wr(&mut pins);");
+        self.rmw_read();
         self.t("sd(&mut pins, self.ror(self.adl_adh as u8));wr(&mut pins);");
     }
     //-------------------------------------------------------------------------------
@@ -972,13 +1040,13 @@ impl Opcode {
     fn x_arr(&mut self) {
         //undocumented AND+ROR
         self.u_cmt("ARR");
-        self.t("self.ac=gd(&pins);self.arr();");
+        self.t("self.ac&=gd(&pins);self.arr();");
     }
     //-------------------------------------------------------------------------------
     fn x_ane(&mut self) {
         //undocumented ANE
         self.u_cmt("ANE");
-        self.t("self.ac = (self.ac|0xEE)&self.x&gd(&pins);self.nz(self.ac);");
+        self.t("self.ac = (self.ac|self.magic)&self.x&gd(&pins);self.nz(self.ac);");
     }
     //-------------------------------------------------------------------------------
     fn x_sha(&mut self) {
@@ -1018,7 +1086,7 @@ impl Opcode {
         //undocumented ANC
         //AND byte with accumulator. If result is negative then carry is set.
         self.u_cmt("ANC");
-        self.t("self.ac = gd(&pins);self.nz(self.ac);self.sr.set(StatusRegister::C, (self.ac&0x80)!=0);");
+        self.t("self.ac&=gd(&pins);self.nz(self.ac);self.sr.set(StatusRegister::C, (self.ac&0x80)!=0);");
     }
     //-------------------------------------------------------------------------------
     fn x_las(&mut self) {
@@ -1042,6 +1110,130 @@ impl Opcode {
     }
 }
 
+/// Instructions only the 65C02 has.
+impl Opcode {
+    //-------------------------------------------------------------------------------
+    fn c_nop(&mut self, cycles: usize) {
+        self.cmt("NOP");
+        for _ in 0..cycles {
+            self.t("");
+        }
+    }
+    //-------------------------------------------------------------------------------
+    fn c_nop1(&mut self) {
+        // single byte, single cycle NOP, the next opcode is fetched right away
+        self.cmt("NOP");
+        self.t("fetch(&mut pins, self.pc);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_bra(&mut self) {
+        self.cmt("BRA");
+        self.branch("false");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_bit_imm(&mut self) {
+        // BIT # only affects the zero flag
+        self.cmt("BIT");
+        self.t("self.sr.set(StatusRegister::Z, self.ac & gd(&pins) == 0);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_stz(&mut self) {
+        self.cmt("STZ");
+        self.ta("sd(&mut pins, 0); wr(&mut pins);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_ina(&mut self) {
+        self.cmt("INC A");
+        self.t("self.ac=self.ac.wrapping_add(1);self.nz(self.ac);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_dea(&mut self) {
+        self.cmt("DEC A");
+        self.t("self.ac=self.ac.wrapping_sub(1);self.nz(self.ac);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_push(&mut self, reg: &str) {
+        self.cmt(&format!("PH{}", reg.to_uppercase()));
+        self.t(&format!("sad(&mut pins, 0x0100|(self.sp as u16), self.{});self.sp=self.sp.wrapping_sub(1);wr(&mut pins);", reg));
+    }
+    //-------------------------------------------------------------------------------
+    fn c_pull(&mut self, reg: &str) {
+        self.cmt(&format!("PL{}", reg.to_uppercase()));
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));self.sp=self.sp.wrapping_add(1);"); //read junk byte from current SP
+        self.t("sa(&mut pins, 0x0100|(self.sp as u16));"); //read actual byte
+        self.t(&format!("self.{0}=gd(&pins);self.nz(self.{0});", reg));
+    }
+    //-------------------------------------------------------------------------------
+    fn c_tsb(&mut self) {
+        // Z is set from A AND memory, then the bits of A are set in memory
+        self.cmt("TSB");
+        self.rmw_read();
+        self.t("self.sr.set(StatusRegister::Z, self.ac & self.adl_adh as u8 == 0);sd(&mut pins, self.adl_adh as u8 | self.ac);wr(&mut pins);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_trb(&mut self) {
+        // Z is set from A AND memory, then the bits of A are cleared in memory
+        self.cmt("TRB");
+        self.rmw_read();
+        self.t("self.sr.set(StatusRegister::Z, self.ac & self.adl_adh as u8 == 0);sd(&mut pins, self.adl_adh as u8 & !self.ac);wr(&mut pins);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_rmb_smb(&mut self, bit: usize, set: bool) {
+        // Rockwell reset/set memory bit
+        self.cmt(&format!("{}{}", if set { "SMB" } else { "RMB" }, bit));
+        self.rmw_read();
+        if set {
+            self.t(&format!(
+                "sd(&mut pins, self.adl_adh as u8 | {:#04X});wr(&mut pins);",
+                1 << bit
+            ));
+        } else {
+            self.t(&format!(
+                "sd(&mut pins, self.adl_adh as u8 & !{:#04X});wr(&mut pins);",
+                1 << bit
+            ));
+        }
+    }
+    //-------------------------------------------------------------------------------
+    fn c_bbr_bbs(&mut self, bit: usize, set: bool) {
+        // Rockwell branch on bit reset/set, 5 cycles, +1 if the branch is taken
+        // and another +1 if it crosses a page
+        self.cmt(&format!("{}{}", if set { "BBS" } else { "BBR" }, bit));
+        self.t("sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+        self.t("let zz = gd(&pins) as u16;sa(&mut pins, zz);");
+        self.t("self.adl_adh = gd(&pins) as u16;sa(&mut pins, self.pc);self.pc=self.pc.wrapping_add(1);");
+        //taken branches skip the next tick
+        self.t(&format!("let zz = gd(&pins) as i8;sa(&mut pins, self.pc);if (self.adl_adh & {:#04X} != 0) == {} {{ self.adl_adh=self.pc.wrapping_add(zz as i16 as u16); self.ir += 1; }}", 1 << bit, set));
+        //branch not taken
+        self.t("fetch(&mut pins, self.pc);");
+        //branch taken
+        self.t("sa(&mut pins, self.pc);");
+        self.t("sa(&mut pins, (self.pc & 0xFF00)|(self.adl_adh&0x00FF));if (self.adl_adh & 0xFF00) == (self.pc & 0xFF00) { self.pc = self.adl_adh; fetch(&mut pins, self.pc) }");
+        //page crossed extra cycle
+        self.t("self.pc=self.adl_adh;");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_jmpix(&mut self) {
+        self.cmt("JMP (abs,X)");
+        self.jmp_indirect("self.adl_adh=self.adl_adh.wrapping_add(self.x as u16);");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_wai(&mut self) {
+        // wait until an interrupt line is pulled, the interrupt itself is only
+        // serviced if it's not masked
+        self.cmt("WAI");
+        self.t("sa(&mut pins, self.pc);");
+        self.t("if pins.irq || pins.nmi { fetch(&mut pins, self.pc); } else { self.ir-=1; }");
+    }
+    //-------------------------------------------------------------------------------
+    fn c_stp(&mut self) {
+        // stop the clock until the next reset
+        self.cmt("STP");
+        self.t("sa(&mut pins, self.pc);");
+        self.t("if pins.res { fetch(&mut pins, self.pc); } else { self.ir-=1; }");
+    }
+}
+
 fn l(string: &mut String, l: String) {
     string.push_str(&l);
 }
@@ -1058,7 +1250,12 @@ fn enc_op(op: usize) -> Opcode {
     let (addr_mode, mem_access) = OPS[cc][bbb][aaa];
     o.enc_addr(addr_mode, mem_access);
     // yeet
+    decode(&mut o, cc, bbb, aaa);
+    finish(&mut o, mem_access);
+    o
+}
 
+fn decode(o: &mut Opcode, cc: usize, bbb: usize, aaa: usize) {
     if cc == 0 {
         if aaa == 0 {
             if bbb == 0 {
@@ -1241,7 +1438,7 @@ fn enc_op(op: usize) -> Opcode {
         } else if aaa == 6 {
             if bbb == 2 {
                 o.i_dex();
-            } else if bbb != 7 {
+            } else if bbb == 0 || bbb == 6 {
                 o.u_nop();
             } else {
                 o.i_dec();
@@ -1249,7 +1446,7 @@ fn enc_op(op: usize) -> Opcode {
         } else if aaa == 7 {
             if bbb == 2 {
                 o.i_nop();
-            } else if bbb != 7 {
+            } else if bbb == 0 || bbb == 6 {
                 o.u_nop();
             } else {
                 o.i_inc();
@@ -1286,7 +1483,7 @@ fn enc_op(op: usize) -> Opcode {
                 o.x_ane();
             } else if bbb == 6 {
                 o.x_shs();
-            } else if bbb == 4 || bbb == 5 || bbb == 6 || bbb == 7 {
+            } else if bbb == 4 || bbb == 7 {
                 o.x_sha();
             } else {
                 o.u_sax();
@@ -1313,27 +1510,139 @@ fn enc_op(op: usize) -> Opcode {
             }
         }
     }
+}
 
+fn finish(o: &mut Opcode, mem_access: MemoryAccess) {
     if mem_access == MemoryAccess::R || mem_access == MemoryAccess::None {
         o.ta("fetch(&mut pins, self.pc);")
     } else {
         o.t("fetch(&mut pins, self.pc);")
     }
+}
+
+/// Addressing mode of the 65C02 opcodes that differ from the NMOS 6502.
+fn mode_65c02(op: usize) -> (AddressingMode, MemoryAccess) {
+    match op {
+        0x04 | 0x14 => (AddressingMode::Zp, MemoryAccess::RW),
+        0x44 => (AddressingMode::Zp, MemoryAccess::R),
+        0x0C | 0x1C => (AddressingMode::Abs, MemoryAccess::RW),
+        0x34 | 0x54 | 0xD4 | 0xF4 => (AddressingMode::ZpX, MemoryAccess::R),
+        0x3C => (AddressingMode::AbsX, MemoryAccess::R),
+        0x5C | 0xDC | 0xFC => (AddressingMode::Abs, MemoryAccess::R),
+        0x64 => (AddressingMode::Zp, MemoryAccess::W),
+        0x74 => (AddressingMode::ZpX, MemoryAccess::W),
+        0x9C => (AddressingMode::Abs, MemoryAccess::W),
+        0x9E => (AddressingMode::AbsX, MemoryAccess::W),
+        0x7C => (AddressingMode::Jmp, MemoryAccess::R),
+        0x80 | 0x89 => (AddressingMode::Imm, MemoryAccess::R),
+        0x1A | 0x3A | 0x7A | 0xFA => (AddressingMode::None, MemoryAccess::R),
+        0x5A | 0xDA => (AddressingMode::None, MemoryAccess::W),
+        0x92 => (AddressingMode::ZpInd, MemoryAccess::W),
+        _ if op & 0x1F == 0x12 => (AddressingMode::ZpInd, MemoryAccess::R),
+        _ if op & 0x1F == 0x02 => (AddressingMode::Imm, MemoryAccess::R),
+        _ if op & 0x0F == 0x07 => (AddressingMode::Zp, MemoryAccess::RW),
+        _ if op & 0x0F == 0x0F => (AddressingMode::ZpRel, MemoryAccess::R),
+        _ => OPS[op & 3][(op >> 2) & 7][(op >> 5) & 7],
+    }
+}
+
+fn enc_op_65c02(op: usize) -> Opcode {
+    let mut o = Opcode::new(op);
+    o.cmos = true;
+    let bit = (op >> 4) & 7;
+    if op & 0x07 == 0x03 {
+        o.mode = AddressingMode::None;
+    }
+    match op {
+        0xCB => {
+            o.enc_addr(AddressingMode::None, MemoryAccess::None);
+            o.c_wai();
+            return o;
+        }
+        0xDB => {
+            o.enc_addr(AddressingMode::None, MemoryAccess::None);
+            o.c_stp();
+            return o;
+        }
+        _ if op & 0x07 == 0x03 => {
+            o.c_nop1();
+            return o;
+        }
+        _ => {}
+    }
+    let (addr_mode, mem_access) = mode_65c02(op);
+    o.mode = addr_mode;
+    o.enc_addr(addr_mode, mem_access);
+    match op {
+        0x04 | 0x0C => o.c_tsb(),
+        0x14 | 0x1C => o.c_trb(),
+        0x34 | 0x3C => o.i_bit(),
+        0x44 | 0x54 | 0xD4 | 0xF4 | 0xDC | 0xFC => o.c_nop(1),
+        // this one reads its operand and then takes another 5 cycles
+        0x5C => o.c_nop(5),
+        0x64 | 0x74 | 0x9C | 0x9E => o.c_stz(),
+        0x7C => o.c_jmpix(),
+        0x80 => o.c_bra(),
+        0x89 => o.c_bit_imm(),
+        0x1A => o.c_ina(),
+        0x3A => o.c_dea(),
+        0x5A => o.c_push("y"),
+        0x7A => o.c_pull("y"),
+        0xDA => o.c_push("x"),
+        0xFA => o.c_pull("x"),
+        // (zp) versions of the accumulator instructions
+        _ if op & 0x1F == 0x12 => decode(&mut o, 1, 4, op >> 5),
+        _ if op & 0x1F == 0x02 && op != 0xA2 => o.c_nop(1),
+        _ if op & 0x0F == 0x07 => o.c_rmb_smb(bit, op & 0x80 != 0),
+        _ if op & 0x0F == 0x0F => o.c_bbr_bbs(bit, op & 0x80 != 0),
+        _ => decode(&mut o, op & 3, (op >> 2) & 7, (op >> 5) & 7),
+    }
+    if matches!(op, 0x1E | 0x3E | 0x5E | 0x7E) {
+        // shifts and rotates skip the fixup cycle if no page is crossed
+        o.src[2] += "self.ir += (!((self.adl_adh >> 8).wrapping_sub((self.adl_adh.wrapping_add(self.x as u16)) >> 8)))&1;";
+    }
+    finish(&mut o, mem_access);
     o
 }
 
+/// Reads the `variant = "..."` argument, without one the NMOS 6502 is
+/// generated.
+fn variant(args: TokenStream) -> Option<String> {
+    let args: Vec<_> = args.into_iter().map(|t| t.to_string()).collect();
+    match args.as_slice() {
+        [] => None,
+        [name, eq, value] if name == "variant" && eq == "=" => {
+            Some(value.trim_matches('"').to_lowercase())
+        }
+        _ => panic!("expected `variant = \"65c02\"`, got `{}`", args.join(" ")),
+    }
+}
+
+/// Generates the microcode for the NMOS 6502 as `CPU::the_match_statement`,
+/// or with `variant = "65c02"` for the WDC 65C02 as
+/// `CPU::the_match_statement_65c02`.
 #[proc_macro]
-pub fn codegen(_: TokenStream) -> TokenStream {
+pub fn codegen(args: TokenStream) -> TokenStream {
     let mut code = String::new();
-    for op in 0..256 {
-        enc_op(op).write_op(&mut code);
-    }
-    //.parse().unwrap()
+    let name = match variant(args).as_deref() {
+        None | Some("6502") => {
+            for op in 0..256 {
+                enc_op(op).write_op(&mut code);
+            }
+            "the_match_statement"
+        }
+        Some("65c02") => {
+            for op in 0..256 {
+                enc_op_65c02(op).write_op(&mut code);
+            }
+            "the_match_statement_65c02"
+        }
+        Some(v) => panic!("unknown variant `{}`", v),
+    };
     format!(
         r#"
-use std::ops::BitAnd;
 impl CPU {{
-    fn the_match_statement(&mut self, mut pins: &mut Pins){{
+    fn {}(&mut self, mut pins: &mut Pins){{
         match 0 {{
                 {}
                 _ => panic!(
@@ -1344,7 +1653,7 @@ impl CPU {{
         }}
     }}
 }}"#,
-        code
+        name, code
     )
     .parse()
     .unwrap()
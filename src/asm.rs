@@ -0,0 +1,817 @@
+use crate::asm::expr::{Expr, ExprParser};
+use crate::asm::lexer::{tokenize, Token};
+use crate::cpu::model::CpuModel;
+use crate::cpu::opcodes::{AddressingMode, Instruction, Opcode};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub use link::{link, LinkConfig, Linked};
+
+pub mod expr;
+pub mod lexer;
+pub mod link;
+
+#[derive(Debug, Error)]
+pub enum AsmError {
+    #[error("{file}: {source}")]
+    Io {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("{file}:{line}: {message}")]
+    Syntax {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    #[error("{0}")]
+    Link(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+}
+
+impl Location {
+    fn error(&self, message: String) -> AsmError {
+        AsmError::Syntax {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// A piece of section contents, values that refer to labels are only known
+/// after linking.
+#[derive(Debug, Clone)]
+pub enum Fragment {
+    Bytes(Vec<u8>),
+    Value {
+        expr: Expr,
+        size: u8,
+        /// section offset of the statement, for `*`
+        at: u32,
+        loc: Location,
+    },
+    Branch {
+        expr: Expr,
+        at: u32,
+        loc: Location,
+    },
+    Fill {
+        len: u32,
+        value: u8,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub fragments: Vec<Fragment>,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum Symbol {
+    Label { section: usize, offset: u32 },
+    Const(Expr),
+}
+
+/// The result of assembling one source file, the equivalent of a ca65 `.o`.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub name: String,
+    pub sections: Vec<Section>,
+    pub symbols: HashMap<String, Symbol>,
+    pub exports: Vec<String>,
+    pub imports: HashSet<String>,
+    pub autoimport: bool,
+}
+
+/// Assembles the subset of ca65 syntax used by the programs in `code/`.
+#[derive(Debug, Default, Clone)]
+pub struct Assembler {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include_dir<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    pub fn assemble_file<P: AsRef<Path>>(&self, path: P) -> Result<Object, AsmError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| AsmError::Io {
+            file: path.display().to_string(),
+            source,
+        })?;
+        let mut state = State::new(path.display().to_string());
+        self.process(&mut state, path, &source, 0)?;
+        Ok(state.object)
+    }
+
+    pub fn assemble_str(&self, name: &str, source: &str) -> Result<Object, AsmError> {
+        let mut state = State::new(name.to_owned());
+        self.process(&mut state, Path::new(name), source, 0)?;
+        Ok(state.object)
+    }
+
+    fn find_include(&self, from: &Path, name: &str) -> Option<PathBuf> {
+        let local = from.parent().map(|p| p.join(name));
+        local
+            .into_iter()
+            .chain(self.include_dirs.iter().map(|d| d.join(name)))
+            .find(|p| p.is_file())
+    }
+
+    fn process(
+        &self,
+        state: &mut State,
+        path: &Path,
+        source: &str,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (idx, line) in source.lines().enumerate() {
+            let loc = Location {
+                file: path.display().to_string(),
+                line: idx + 1,
+            };
+            let tokens = tokenize(line).map_err(|e| loc.error(e))?;
+            if let Some(include) = state.line(&tokens, &loc)? {
+                if depth > 16 {
+                    return Err(loc.error("includes nested too deeply".to_owned()));
+                }
+                let file = self
+                    .find_include(path, &include)
+                    .ok_or_else(|| loc.error(format!("include file `{}` not found", include)))?;
+                let source = fs::read_to_string(&file).map_err(|source| AsmError::Io {
+                    file: file.display().to_string(),
+                    source,
+                })?;
+                self.process(state, &file, &source, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mnemonic_modes(mnemonic: &str, model: CpuModel, illegal: bool) -> HashMap<AddressingMode, u8> {
+    let mut modes = HashMap::new();
+    // documented encodings win over the undocumented duplicates
+    for documented in [true, false] {
+        for op in 0..=0xFF {
+            let ins = Instruction::for_model(model, op);
+            if ins.opcode == Opcode::JAM
+                || Instruction::is_documented_on(model, op) != documented
+                || (!documented && !illegal)
+                || !ins.name(op).eq_ignore_ascii_case(mnemonic)
+            {
+                continue;
+            }
+            modes.entry(ins.mode).or_insert(op);
+        }
+    }
+    modes
+}
+
+enum Operand {
+    None,
+    Acc,
+    Imm(Expr),
+    /// expression, forced address size (`a` or `z`) and index register
+    Address(Expr, Option<char>, Option<char>),
+    Indirect(Expr),
+    XInd(Expr),
+    IndY(Expr),
+}
+
+struct State {
+    object: Object,
+    section: usize,
+    scope: String,
+    unnamed: usize,
+    /// the instruction set of `.setcpu`
+    model: CpuModel,
+    illegal: bool,
+}
+
+impl State {
+    fn new(name: String) -> Self {
+        let mut s = Self {
+            object: Object {
+                name,
+                sections: vec![],
+                symbols: HashMap::new(),
+                exports: vec![],
+                imports: HashSet::new(),
+                autoimport: false,
+            },
+            section: 0,
+            scope: String::new(),
+            unnamed: 0,
+            model: CpuModel::Nmos6502,
+            illegal: false,
+        };
+        s.switch("CODE");
+        s
+    }
+
+    fn switch(&mut self, name: &str) {
+        self.section = match self.object.sections.iter().position(|s| s.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.object.sections.push(Section {
+                    name: name.to_owned(),
+                    fragments: vec![],
+                    size: 0,
+                });
+                self.object.sections.len() - 1
+            }
+        };
+    }
+
+    fn offset(&self) -> u32 {
+        self.object.sections[self.section].size
+    }
+
+    fn emit(&mut self, fragment: Fragment) {
+        let len = match &fragment {
+            Fragment::Bytes(b) => b.len() as u32,
+            Fragment::Value { size, .. } => *size as u32,
+            Fragment::Branch { .. } => 1,
+            Fragment::Fill { len, .. } => *len,
+        };
+        let section = &mut self.object.sections[self.section];
+        section.size += len;
+        section.fragments.push(fragment);
+    }
+
+    fn define(&mut self, name: String, symbol: Symbol, loc: &Location) -> Result<(), AsmError> {
+        if self.object.symbols.contains_key(&name) {
+            return Err(loc.error(format!("symbol `{}` is already defined", name)));
+        }
+        self.object.symbols.insert(name, symbol);
+        Ok(())
+    }
+
+    fn label(&mut self, name: &str, loc: &Location) -> Result<(), AsmError> {
+        let name = if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            self.scope = name.to_owned();
+            name.to_owned()
+        };
+        let symbol = Symbol::Label {
+            section: self.section,
+            offset: self.offset(),
+        };
+        self.define(name, symbol, loc)
+    }
+
+    /// Tries to evaluate an expression while assembling, which only works if
+    /// it consists of numbers and constants.
+    fn constant(&self, expr: &Expr) -> Option<i64> {
+        fn lookup(symbols: &HashMap<String, Symbol>, name: &str, depth: usize) -> Option<i64> {
+            match symbols.get(name)? {
+                Symbol::Const(e) if depth < 32 => e.eval(None, &|n| lookup(symbols, n, depth + 1)),
+                _ => None,
+            }
+        }
+        expr.eval(None, &|n| lookup(&self.object.symbols, n, 0))
+    }
+
+    fn expr(&self, tokens: &[Token], loc: &Location) -> Result<(Expr, usize), AsmError> {
+        let unnamed = self.unnamed;
+        let resolve = move |offset: i32| {
+            let idx = if offset > 0 {
+                unnamed as i32 + offset - 1
+            } else {
+                unnamed as i32 + offset
+            };
+            format!(":{}", idx)
+        };
+        // cheap locals belong to the last normal label
+        let tokens: Vec<Token> = tokens
+            .iter()
+            .map(|t| match t {
+                Token::Ident(name) if name.starts_with('@') => {
+                    Token::Ident(format!("{}{}", self.scope, name))
+                }
+                t => t.clone(),
+            })
+            .collect();
+        let mut parser = ExprParser::new(&tokens, &resolve);
+        let expr = parser.parse().map_err(|e| loc.error(e))?;
+        Ok((expr, parser.position()))
+    }
+
+    fn full_expr(&self, tokens: &[Token], loc: &Location) -> Result<Expr, AsmError> {
+        let (expr, used) = self.expr(tokens, loc)?;
+        match tokens.get(used) {
+            None => Ok(expr),
+            Some(t) => Err(loc.error(format!("unexpected {}", t))),
+        }
+    }
+
+    /// Handles one line, returning the file name if it was an `.include`.
+    fn line(&mut self, tokens: &[Token], loc: &Location) -> Result<Option<String>, AsmError> {
+        let mut tokens = tokens;
+        loop {
+            match tokens {
+                // `bne :-` is a branch to an unnamed label, not a label `bne`
+                [Token::Ident(name), Token::Punct(':'), rest @ ..]
+                    if !name.starts_with('.')
+                        && !matches!(rest.first(), Some(Token::Punct('+' | '-'))) =>
+                {
+                    self.label(&name.clone(), loc)?;
+                    tokens = rest;
+                }
+                [Token::Punct(':'), rest @ ..]
+                    if !matches!(rest.first(), Some(Token::Punct('+' | '-'))) =>
+                {
+                    let name = format!(":{}", self.unnamed);
+                    self.unnamed += 1;
+                    let symbol = Symbol::Label {
+                        section: self.section,
+                        offset: self.offset(),
+                    };
+                    self.define(name, symbol, loc)?;
+                    tokens = rest;
+                }
+                _ => break,
+            }
+        }
+        match tokens {
+            [] => Ok(None),
+            [Token::Ident(name), Token::Punct('='), rest @ ..] => {
+                let expr = self.full_expr(rest, loc)?;
+                self.define(name.clone(), Symbol::Const(expr), loc)?;
+                Ok(None)
+            }
+            [Token::Ident(name), rest @ ..] if name.starts_with('.') => {
+                self.directive(&name.to_ascii_lowercase(), rest, loc)
+            }
+            [Token::Ident(name), rest @ ..] => {
+                self.instruction(name, rest, loc)?;
+                Ok(None)
+            }
+            [t, ..] => Err(loc.error(format!("unexpected {}", t))),
+        }
+    }
+
+    fn names(&self, tokens: &[Token], loc: &Location) -> Result<Vec<String>, AsmError> {
+        tokens
+            .split(|t| *t == Token::Punct(','))
+            .map(|part| match part {
+                [Token::Ident(name)] => Ok(name.clone()),
+                _ => Err(loc.error("symbol name expected".to_owned())),
+            })
+            .collect()
+    }
+
+    fn string(tokens: &[Token], loc: &Location) -> Result<String, AsmError> {
+        match tokens {
+            [Token::Str(s)] => Ok(s.clone()),
+            _ => Err(loc.error("string expected".to_owned())),
+        }
+    }
+
+    fn data(&mut self, tokens: &[Token], size: u8, loc: &Location) -> Result<(), AsmError> {
+        let at = self.offset();
+        for part in tokens.split(|t| *t == Token::Punct(',')) {
+            match part {
+                [Token::Str(s)] if size == 1 => self.emit(Fragment::Bytes(s.bytes().collect())),
+                _ => {
+                    let expr = self.full_expr(part, loc)?;
+                    self.emit(Fragment::Value {
+                        expr,
+                        size,
+                        at,
+                        loc: loc.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        name: &str,
+        args: &[Token],
+        loc: &Location,
+    ) -> Result<Option<String>, AsmError> {
+        match name {
+            ".segment" => self.switch(&Self::string(args, loc)?),
+            ".code" => self.switch("CODE"),
+            ".rodata" => self.switch("RODATA"),
+            ".data" => self.switch("DATA"),
+            ".bss" => self.switch("BSS"),
+            ".zeropage" => self.switch("ZEROPAGE"),
+            ".byte" | ".byt" => self.data(args, 1, loc)?,
+            ".word" | ".addr" => self.data(args, 2, loc)?,
+            ".dword" => self.data(args, 4, loc)?,
+            ".asciiz" => {
+                self.data(args, 1, loc)?;
+                self.emit(Fragment::Bytes(vec![0]));
+            }
+            ".res" => {
+                let mut parts = args.split(|t| *t == Token::Punct(','));
+                let len = parts.next().unwrap_or(&[]);
+                let len = self.full_expr(len, loc)?;
+                let len = self
+                    .constant(&len)
+                    .filter(|l| (0..=0x10000).contains(l))
+                    .ok_or_else(|| loc.error("`.res` needs a constant size".to_owned()))?;
+                let value = match parts.next() {
+                    Some(fill) => {
+                        let fill = self.full_expr(fill, loc)?;
+                        self.constant(&fill)
+                            .ok_or_else(|| loc.error("`.res` needs a constant fill".to_owned()))?
+                    }
+                    None => 0,
+                };
+                self.emit(Fragment::Fill {
+                    len: len as u32,
+                    value: value as u8,
+                });
+            }
+            ".export" | ".exportzp" => {
+                let names = self.names(args, loc)?;
+                self.object.exports.extend(names);
+            }
+            ".import" | ".importzp" => {
+                let names = self.names(args, loc)?;
+                self.object.imports.extend(names);
+            }
+            ".autoimport" => {
+                self.object.autoimport = match args {
+                    [] | [Token::Punct('+')] => true,
+                    [Token::Punct('-')] => false,
+                    [Token::Ident(v)] if v.eq_ignore_ascii_case("on") => true,
+                    [Token::Ident(v)] if v.eq_ignore_ascii_case("off") => false,
+                    _ => return Err(loc.error("`.autoimport` expects `+` or `-`".to_owned())),
+                }
+            }
+            ".setcpu" => {
+                (self.model, self.illegal) =
+                    match Self::string(args, loc)?.to_ascii_uppercase().as_str() {
+                        "6502" => (CpuModel::Nmos6502, false),
+                        "6502X" => (CpuModel::Nmos6502, true),
+                        "65C02" => (CpuModel::Wdc65C02, false),
+                        cpu => return Err(loc.error(format!("unsupported cpu `{}`", cpu))),
+                    }
+            }
+            ".include" => return Ok(Some(Self::string(args, loc)?)),
+            _ => return Err(loc.error(format!("unknown directive `{}`", name))),
+        }
+        Ok(None)
+    }
+
+    fn operand(&self, tokens: &[Token], loc: &Location) -> Result<Operand, AsmError> {
+        let is_reg = |t: Option<&Token>, r: &str| match t {
+            Some(Token::Ident(name)) => name.eq_ignore_ascii_case(r),
+            _ => false,
+        };
+        match tokens {
+            [] => return Ok(Operand::None),
+            [Token::Ident(a)] if a.eq_ignore_ascii_case("a") => return Ok(Operand::Acc),
+            [Token::Punct('#'), rest @ ..] => return Ok(Operand::Imm(self.full_expr(rest, loc)?)),
+            [Token::Punct('('), ..] => {
+                let mut depth = 0;
+                let close = tokens.iter().position(|t| {
+                    match t {
+                        Token::Punct('(') => depth += 1,
+                        Token::Punct(')') => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                });
+                if let Some(close) = close {
+                    let inner = &tokens[1..close];
+                    let after = &tokens[close + 1..];
+                    if after.len() == 2
+                        && after[0] == Token::Punct(',')
+                        && is_reg(after.get(1), "y")
+                    {
+                        return Ok(Operand::IndY(self.full_expr(inner, loc)?));
+                    }
+                    if after.is_empty() {
+                        let n = inner.len();
+                        if n > 2 && inner[n - 2] == Token::Punct(',') && is_reg(inner.last(), "x") {
+                            return Ok(Operand::XInd(self.full_expr(&inner[..n - 2], loc)?));
+                        }
+                        return Ok(Operand::Indirect(self.full_expr(inner, loc)?));
+                    }
+                }
+            }
+            _ => {}
+        }
+        let (size, tokens) = match tokens {
+            [Token::Ident(s), Token::Punct(':'), rest @ ..]
+                if s.eq_ignore_ascii_case("a") || s.eq_ignore_ascii_case("z") =>
+            {
+                (s.to_ascii_lowercase().chars().next(), rest)
+            }
+            _ => (None, tokens),
+        };
+        let (expr, used) = self.expr(tokens, loc)?;
+        let index = match &tokens[used..] {
+            [] => None,
+            [Token::Punct(','), r] if is_reg(Some(r), "x") => Some('x'),
+            [Token::Punct(','), r] if is_reg(Some(r), "y") => Some('y'),
+            [t, ..] => return Err(loc.error(format!("unexpected {}", t))),
+        };
+        Ok(Operand::Address(expr, size, index))
+    }
+
+    fn instruction(
+        &mut self,
+        mnemonic: &str,
+        args: &[Token],
+        loc: &Location,
+    ) -> Result<(), AsmError> {
+        let modes = mnemonic_modes(mnemonic, self.model, self.illegal);
+        if modes.is_empty() {
+            let cmos = self.model != CpuModel::Wdc65C02
+                && !mnemonic_modes(mnemonic, CpuModel::Wdc65C02, false).is_empty();
+            return Err(loc.error(if cmos {
+                format!("`{}` needs `.setcpu \"65C02\"`", mnemonic)
+            } else {
+                format!("unknown instruction `{}`", mnemonic)
+            }));
+        }
+        let unsupported = || loc.error(format!("addressing mode not supported by `{}`", mnemonic));
+        let at = self.offset();
+        if let Some(op) = modes.get(&AddressingMode::ZpgRel) {
+            // `BBR0 zp, target`
+            let comma = args
+                .iter()
+                .position(|t| *t == Token::Punct(','))
+                .ok_or_else(unsupported)?;
+            let zp = self.full_expr(&args[..comma], loc)?;
+            let target = self.full_expr(&args[comma + 1..], loc)?;
+            self.emit(Fragment::Bytes(vec![*op]));
+            self.emit(Fragment::Value {
+                expr: zp,
+                size: 1,
+                at,
+                loc: loc.clone(),
+            });
+            self.emit(Fragment::Branch {
+                expr: target,
+                at,
+                loc: loc.clone(),
+            });
+            return Ok(());
+        }
+        let operand = self.operand(args, loc)?;
+        let (mode, expr) = match operand {
+            Operand::None if modes.contains_key(&AddressingMode::Impl) => {
+                (AddressingMode::Impl, None)
+            }
+            Operand::None | Operand::Acc => (AddressingMode::Acc, None),
+            Operand::Imm(e) => (AddressingMode::Imm, Some(e)),
+            // `JMP (abs)` and `JMP (abs,X)` on the 65C02, the rest is zero page
+            Operand::Indirect(e) if !modes.contains_key(&AddressingMode::Ind) => {
+                (AddressingMode::ZpgInd, Some(e))
+            }
+            Operand::Indirect(e) => (AddressingMode::Ind, Some(e)),
+            Operand::XInd(e) if modes.contains_key(&AddressingMode::AbsXInd) => {
+                (AddressingMode::AbsXInd, Some(e))
+            }
+            Operand::XInd(e) => (AddressingMode::XInd, Some(e)),
+            Operand::IndY(e) => (AddressingMode::IndY, Some(e)),
+            Operand::Address(e, _, None) if modes.contains_key(&AddressingMode::Rel) => {
+                let op = modes[&AddressingMode::Rel];
+                self.emit(Fragment::Bytes(vec![op]));
+                self.emit(Fragment::Branch {
+                    expr: e,
+                    at,
+                    loc: loc.clone(),
+                });
+                return Ok(());
+            }
+            Operand::Address(e, size, index) => {
+                let (zp, abs) = match index {
+                    None => (AddressingMode::Zpg, AddressingMode::Abs),
+                    Some('x') => (AddressingMode::ZpgX, AddressingMode::AbsX),
+                    _ => (AddressingMode::ZpgY, AddressingMode::AbsY),
+                };
+                let small = self.constant(&e).is_some_and(|v| (0..0x100).contains(&v));
+                let mode = match size {
+                    Some('z') => zp,
+                    Some(_) => abs,
+                    // zero page if it fits, but e.g. `STA $10,Y` only exists as absolute
+                    None if small && modes.contains_key(&zp) => zp,
+                    None => abs,
+                };
+                (mode, Some(e))
+            }
+        };
+        let op = *modes.get(&mode).ok_or_else(unsupported)?;
+        self.emit(Fragment::Bytes(vec![op]));
+        if let Some(expr) = expr {
+            self.emit(Fragment::Value {
+                expr,
+                size: (mode.length() - 1) as u8,
+                at,
+                loc: loc.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{link, AsmError, Assembler, LinkConfig, Linked};
+    use std::fs;
+    use std::path::Path;
+
+    fn fixture(path: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/asm")
+            .join(path)
+            .display()
+            .to_string()
+    }
+
+    fn code(path: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("code")
+            .join(path)
+            .display()
+            .to_string()
+    }
+
+    /// Links the sources in `code` the way the Makefile does.
+    fn build(config: &str, sources: &[&str]) -> Linked {
+        let mut asm = Assembler::new();
+        asm.include_dir(code("include"));
+        let objects: Vec<_> = sources
+            .iter()
+            .map(|source| asm.assemble_file(code(source)).unwrap())
+            .collect();
+        let config = LinkConfig::parse(&fs::read_to_string(code(config)).unwrap()).unwrap();
+        link(&objects, &config).unwrap()
+    }
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+        let object = Assembler::new().assemble_str("test.s", source)?;
+        Ok(link(&[object], &LinkConfig::flat(0x8000))?.image)
+    }
+
+    #[test]
+    fn linked() {
+        let mut asm = Assembler::new();
+        asm.include_dir(fixture(""));
+        let objects = [
+            asm.assemble_file(fixture("main.s")).unwrap(),
+            asm.assemble_file(fixture("lib.s")).unwrap(),
+        ];
+        let config = LinkConfig::parse(&fs::read_to_string(fixture("link.ld")).unwrap()).unwrap();
+        let linked = link(&objects, &config).unwrap();
+
+        assert_eq!(
+            linked.image,
+            [
+                0x02, 0x80, // .word start
+                0xA9, 0x0F, 0x8D, 0x01, 0x05, // start: lda #COLOR / sta SCREEN+1
+                0xEE, 0x00, 0x02, 0x20, 0x00, 0xF0, // inc count / jsr print
+                0x4C, 0x0D, 0x80, // : jmp :-
+                0x48, 0x49, 0x00, // message, RODATA follows CODE
+                0xA2, 0x00, 0xBD, 0x10, 0x80, 0xF0, 0x06, // print: ldx / lda / beq @done
+                0x9D, 0x00, 0x05, 0xE8, 0xD0, 0xF5, 0x60, // sta / inx / bne @loop / rts
+            ]
+        );
+        assert_eq!(linked.symbols.address_of("count"), Some(0x0200));
+        assert_eq!(linked.symbols.get(0xF000), Some("print"));
+    }
+
+    #[test]
+    fn kernel() {
+        let linked = build("ld/kernel.ld", &["kernel/funcs.s", "kernel/kernel.s"]);
+        assert_eq!(linked.image, fs::read(fixture("kernel.bin")).unwrap());
+        assert_eq!(linked.symbols.address_of("readchar"), Some(0xE0AD));
+        assert_eq!(linked.symbols.get(0xE043), Some("err_loop"));
+    }
+
+    #[test]
+    fn example2() {
+        let sources = ["example2/example2.s", "kernel/funcs.s", "kernel/kernel.s"];
+        let linked = build("ld/link.ld", &sources);
+        assert_eq!(linked.image, fs::read(fixture("example2.bin")).unwrap());
+    }
+
+    #[test]
+    fn addressing() {
+        assert_eq!(
+            assemble("lda $10\nlda a:$10\nlda $1234,y\nsta $10,y\nldx $10,y\njmp ($1234)").unwrap(),
+            [
+                0xA5, 0x10, 0xAD, 0x10, 0x00, 0xB9, 0x34, 0x12, 0x99, 0x10, 0x00, 0xB6, 0x10, 0x6C,
+                0x34, 0x12
+            ]
+        );
+        assert_eq!(
+            assemble("zp = $20\nlda (zp,x)\nlda (zp),y\nlda #<label\nlda #>label\nlabel: rol")
+                .unwrap(),
+            [0xA1, 0x20, 0xB1, 0x20, 0xA9, 0x08, 0xA9, 0x80, 0x2A]
+        );
+    }
+
+    #[test]
+    fn locals() {
+        let source = "
+first:
+@loop: dex
+  bne @loop
+second:
+@loop: dey
+  bne @loop
+: inx
+  bne :-
+  beq :+
+  nop
+:";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0xCA, 0xD0, 0xFD, 0x88, 0xD0, 0xFD, 0xE8, 0xD0, 0xFD, 0xF0, 0x01, 0xEA]
+        );
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(
+            assemble(
+                ".byte 1, \"AB\"\n.word $1234, * \n.dword $01020304\n.res 2, $FF\n.asciiz \"x\""
+            )
+            .unwrap(),
+            [1, 0x41, 0x42, 0x34, 0x12, 0x03, 0x80, 4, 3, 2, 1, 0xFF, 0xFF, 0x78, 0]
+        );
+    }
+
+    #[test]
+    fn illegal() {
+        assert!(assemble("lax $10").is_err());
+        assert_eq!(
+            assemble(".setcpu \"6502X\"\nlax $10\nsbc #1\nnop #2").unwrap(),
+            [0xA7, 0x10, 0xE9, 0x01, 0x80, 0x02]
+        );
+    }
+
+    #[test]
+    fn wdc65c02() {
+        let source = r#"
+            .setcpu "65C02"
+            start: stz $10
+            lda ($20)
+            jmp ($8000,x)
+            phx
+            inc a
+            bit #$80
+            bbs7 $10, start
+            rmb3 $11
+            bra start
+            jmp ($1234)
+            wai
+        "#;
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x64, 0x10, 0xB2, 0x20, 0x7C, 0x00, 0x80, 0xDA, 0x1A, 0x89, 0x80, //
+                0xFF, 0x10, 0xF2, 0x37, 0x11, 0x80, 0xEE, 0x6C, 0x34, 0x12, 0xCB,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let msg = |s: &str| assemble(s).unwrap_err().to_string();
+        assert_eq!(msg("lda missing"), "test.s:1: unresolved symbol `missing`");
+        assert_eq!(msg("\n\nfoo bar"), "test.s:3: unknown instruction `foo`");
+        assert_eq!(
+            msg("x: nop\nx: nop"),
+            "test.s:2: symbol `x` is already defined"
+        );
+        assert_eq!(
+            msg("lda #$100"),
+            "test.s:1: value $100 doesn't fit in 1 bytes"
+        );
+        assert!(msg(".res 200\nl: beq l-200").contains("out of range"));
+        assert!(msg("jmp ($10),y").contains("not supported"));
+        assert_eq!(msg("stz $10"), "test.s:1: `stz` needs `.setcpu \"65C02\"`");
+        assert!(msg("lda ($10)").contains("not supported"));
+        assert!(msg(".setcpu \"65C02\"\nlax $10").contains("unknown instruction"));
+    }
+}
@@ -0,0 +1,236 @@
+use super::lexer::Token;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Lo,
+    Hi,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+/// An operand expression. Symbols are resolved when the expression is
+/// evaluated, which for labels only happens once the linker has placed the
+/// segments.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Sym(String),
+    /// `*`, the address of the current statement
+    Pc,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, pc: Option<i64>, lookup: &dyn Fn(&str) -> Option<i64>) -> Option<i64> {
+        Some(match self {
+            Expr::Num(n) => *n,
+            Expr::Sym(name) => lookup(name)?,
+            Expr::Pc => pc?,
+            Expr::Unary(op, e) => {
+                let v = e.eval(pc, lookup)?;
+                match op {
+                    UnaryOp::Neg => -v,
+                    UnaryOp::Not => !v,
+                    UnaryOp::Lo => v & 0xFF,
+                    UnaryOp::Hi => (v >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let l = l.eval(pc, lookup)?;
+                let r = r.eval(pc, lookup)?;
+                match op {
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Mul => l.wrapping_mul(r),
+                    BinaryOp::Div => l.checked_div(r)?,
+                    BinaryOp::And => l & r,
+                    BinaryOp::Or => l | r,
+                    BinaryOp::Xor => l ^ r,
+                    BinaryOp::Shl => l.checked_shl(r as u32).unwrap_or(0),
+                    BinaryOp::Shr => l.checked_shr(r as u32).unwrap_or(0),
+                }
+            }
+        })
+    }
+
+    /// Calls `f` for every symbol the expression refers to.
+    pub fn symbols<'e>(&'e self, f: &mut dyn FnMut(&'e str)) {
+        match self {
+            Expr::Sym(name) => f(name),
+            Expr::Unary(_, e) => e.symbols(f),
+            Expr::Binary(_, l, r) => {
+                l.symbols(f);
+                r.symbols(f);
+            }
+            Expr::Num(_) | Expr::Pc => {}
+        }
+    }
+}
+
+/// Recursive descent parser over a token slice, using the ca65 precedence
+/// levels: unary operators bind tightest, then `* / & ^ << >>`, then
+/// `+ - |`.
+pub struct ExprParser<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+    /// resolves `:+`/`:-` references to a symbol name
+    unnamed: &'t dyn Fn(i32) -> String,
+}
+
+impl<'t> ExprParser<'t> {
+    pub fn new(tokens: &'t [Token], unnamed: &'t dyn Fn(i32) -> String) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            unnamed,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let t = self.tokens.get(self.pos);
+        self.pos += 1;
+        t
+    }
+
+    pub fn parse(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct('+')) => BinaryOp::Add,
+                Some(Token::Punct('-')) => BinaryOp::Sub,
+                Some(Token::Punct('|')) => BinaryOp::Or,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct('*')) => BinaryOp::Mul,
+                Some(Token::Punct('/')) => BinaryOp::Div,
+                Some(Token::Punct('&')) => BinaryOp::And,
+                Some(Token::Punct('^')) => BinaryOp::Xor,
+                Some(Token::Shl) => BinaryOp::Shl,
+                Some(Token::Shr) => BinaryOp::Shr,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Punct('-')) => UnaryOp::Neg,
+            Some(Token::Punct('~')) => UnaryOp::Not,
+            Some(Token::Punct('<')) => UnaryOp::Lo,
+            Some(Token::Punct('>')) => UnaryOp::Hi,
+            Some(Token::Punct('+')) => {
+                self.pos += 1;
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next().cloned() {
+            Some(Token::Number(n)) => Ok(Expr::Num(n)),
+            Some(Token::Ident(name)) => Ok(Expr::Sym(name)),
+            Some(Token::Punct('*')) => Ok(Expr::Pc),
+            Some(Token::Punct('(')) => {
+                let e = self.parse()?;
+                match self.next() {
+                    Some(Token::Punct(')')) => Ok(e),
+                    _ => Err("missing `)`".to_owned()),
+                }
+            }
+            Some(Token::Punct(':')) => {
+                let mut offset = 0;
+                while let Some(Token::Punct(c @ '+')) | Some(Token::Punct(c @ '-')) = self.peek() {
+                    offset += if *c == '+' { 1 } else { -1 };
+                    self.pos += 1;
+                }
+                if offset == 0 {
+                    return Err("unnamed label reference needs `+` or `-`".to_owned());
+                }
+                Ok(Expr::Sym((self.unnamed)(offset)))
+            }
+            Some(t) => Err(format!("unexpected {} in expression", t)),
+            None => Err("expression expected".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::expr::{Expr, ExprParser};
+    use crate::asm::lexer::tokenize;
+
+    fn eval(text: &str) -> i64 {
+        let tokens = tokenize(text).unwrap();
+        let unnamed = |_| String::new();
+        let e = ExprParser::new(&tokens, &unnamed).parse().unwrap();
+        e.eval(Some(0x8000), &|s| {
+            if s == "foo" {
+                Some(0x1234)
+            } else {
+                None
+            }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("$10 | 1 << 4"), 0x10);
+        assert_eq!(eval("<foo"), 0x34);
+        assert_eq!(eval(">foo + 1"), 0x13);
+        assert_eq!(eval("* + 3"), 0x8003);
+        assert_eq!(eval("%1010 - 'A'"), 10 - 65);
+    }
+
+    #[test]
+    fn unresolved() {
+        let tokens = tokenize("bar + 1").unwrap();
+        let unnamed = |_| String::new();
+        let e = ExprParser::new(&tokens, &unnamed).parse().unwrap();
+        assert_eq!(e.eval(None, &|_| None), None);
+        let mut names = vec![];
+        e.symbols(&mut |s| names.push(s.to_owned()));
+        assert_eq!(names, ["bar"]);
+        assert_eq!(Expr::Num(1).eval(None, &|_| None), Some(1));
+    }
+}
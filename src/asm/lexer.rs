@@ -0,0 +1,150 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Token {
+    /// identifiers, directives (`.word`) and cheap locals (`@loop`)
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Shl,
+    Shr,
+    Punct(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "`{}`", s),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Shl => write!(f, "`<<`"),
+            Token::Shr => write!(f, "`>>`"),
+            Token::Punct(c) => write!(f, "`{}`", c),
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@'
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@'
+}
+
+fn number(digits: &str, radix: u32) -> Result<i64, String> {
+    i64::from_str_radix(digits, radix).map_err(|_| format!("invalid number `{}`", digits))
+}
+
+/// Splits one source line into tokens, stopping at a `;` comment.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {}
+            '$' => {
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let digits: String = chars[start + 1..i].iter().collect();
+                tokens.push(Token::Number(number(&digits, 16)?));
+            }
+            '%' if i < chars.len() && (chars[i] == '0' || chars[i] == '1') => {
+                while i < chars.len() && (chars[i] == '0' || chars[i] == '1') {
+                    i += 1;
+                }
+                let digits: String = chars[start + 1..i].iter().collect();
+                tokens.push(Token::Number(number(&digits, 2)?));
+            }
+            c if c.is_ascii_digit() => {
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let digits: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(number(&digits, 10)?));
+            }
+            c if is_ident_start(c) => {
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string".to_owned()),
+                        Some('"') => break,
+                        Some(c) => s.push(*c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                tokens.push(Token::Str(s));
+            }
+            '\'' => match (chars.get(i), chars.get(i + 1)) {
+                (Some(c), Some('\'')) => {
+                    tokens.push(Token::Number(*c as i64));
+                    i += 2;
+                }
+                _ => return Err("invalid character constant".to_owned()),
+            },
+            '<' if chars.get(i) == Some(&'<') => {
+                i += 1;
+                tokens.push(Token::Shl);
+            }
+            '>' if chars.get(i) == Some(&'>') => {
+                i += 1;
+                tokens.push(Token::Shr);
+            }
+            '#' | ',' | ':' | '(' | ')' | '+' | '-' | '*' | '/' | '&' | '|' | '^' | '~' | '<'
+            | '>' | '=' => tokens.push(Token::Punct(c)),
+            c => return Err(format!("unexpected character `{}`", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::lexer::{tokenize, Token};
+
+    #[test]
+    fn line() {
+        assert_eq!(
+            tokenize("err_loop: lda err_str,y ; comment").unwrap(),
+            vec![
+                Token::Ident("err_loop".to_owned()),
+                Token::Punct(':'),
+                Token::Ident("lda".to_owned()),
+                Token::Ident("err_str".to_owned()),
+                Token::Punct(','),
+                Token::Ident("y".to_owned()),
+            ]
+        );
+        assert_eq!(
+            tokenize(".asciiz \"Hi; there\" ").unwrap(),
+            vec![
+                Token::Ident(".asciiz".to_owned()),
+                Token::Str("Hi; there".to_owned())
+            ]
+        );
+        assert_eq!(
+            tokenize("$1F %101 12 'a' <<").unwrap(),
+            vec![
+                Token::Number(0x1F),
+                Token::Number(5),
+                Token::Number(12),
+                Token::Number(97),
+                Token::Shl
+            ]
+        );
+        assert!(tokenize("\"open").is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use super::{AsmError, Fragment, Object, Symbol};
+use crate::cpu::disasm::SymbolTable;
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SegmentKind {
+    ReadOnly,
+    ReadWrite,
+    Bss,
+    ZeroPage,
+    Overwrite,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MemoryArea {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub fill: bool,
+    pub fill_value: u8,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SegmentRule {
+    pub name: String,
+    pub load: String,
+    pub kind: SegmentKind,
+    pub start: Option<u32>,
+    pub align: Option<u32>,
+    pub define: bool,
+    pub optional: bool,
+}
+
+/// The memory layout of a program, as described by an ld65 config file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LinkConfig {
+    pub memory: Vec<MemoryArea>,
+    pub segments: Vec<SegmentRule>,
+    /// place segments the config doesn't mention in the first memory area
+    pub implicit: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum CfgToken {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+fn cfg_tokens(text: &str) -> Result<Vec<CfgToken>, AsmError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            '{' | '}' | ':' | ';' | ',' | '=' => tokens.push(CfgToken::Punct(c)),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(AsmError::Link("unterminated string".to_owned())),
+                    }
+                }
+                tokens.push(CfgToken::Str(s));
+            }
+            c => {
+                let mut s = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '$' || c == '%' {
+                        s.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(CfgToken::Word(s));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn cfg_number(value: &str) -> Result<u32, AsmError> {
+    let parsed = if let Some(hex) = value.strip_prefix('$') {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| AsmError::Link(format!("invalid number `{}` in config", value)))
+}
+
+type Entry = (String, HashMap<String, String>);
+
+fn cfg_entries(tokens: &[CfgToken], pos: &mut usize) -> Result<Vec<Entry>, AsmError> {
+    let err = |m: &str| AsmError::Link(format!("config: {}", m));
+    let mut entries = vec![];
+    loop {
+        match tokens.get(*pos) {
+            Some(CfgToken::Punct('}')) => {
+                *pos += 1;
+                return Ok(entries);
+            }
+            Some(CfgToken::Word(name)) => {
+                *pos += 1;
+                if tokens.get(*pos) != Some(&CfgToken::Punct(':')) {
+                    return Err(err(&format!("`:` expected after `{}`", name)));
+                }
+                *pos += 1;
+                let mut attrs = HashMap::new();
+                loop {
+                    match tokens.get(*pos) {
+                        Some(CfgToken::Punct(';')) => {
+                            *pos += 1;
+                            break;
+                        }
+                        Some(CfgToken::Punct(',')) => *pos += 1,
+                        Some(CfgToken::Word(key)) => {
+                            *pos += 1;
+                            if tokens.get(*pos) == Some(&CfgToken::Punct('=')) {
+                                *pos += 1;
+                            }
+                            let value = match tokens.get(*pos) {
+                                Some(CfgToken::Word(v)) | Some(CfgToken::Str(v)) => v.clone(),
+                                _ => return Err(err(&format!("value expected for `{}`", key))),
+                            };
+                            *pos += 1;
+                            attrs.insert(key.to_ascii_lowercase(), value);
+                        }
+                        _ => return Err(err(&format!("`;` expected after `{}`", name))),
+                    }
+                }
+                entries.push((name.clone(), attrs));
+            }
+            _ => return Err(err("`}` expected")),
+        }
+    }
+}
+
+impl LinkConfig {
+    /// A single memory area from `start` to the end of the address space,
+    /// with segments placed in the order they first appear.
+    pub fn flat(start: u16) -> Self {
+        Self {
+            memory: vec![MemoryArea {
+                name: "MAIN".to_owned(),
+                start: start as u32,
+                size: 0x10000 - start as u32,
+                fill: false,
+                fill_value: 0,
+            }],
+            segments: vec![],
+            implicit: true,
+        }
+    }
+
+    /// Parses the `MEMORY` and `SEGMENTS` blocks of an ld65 config file.
+    pub fn parse(text: &str) -> Result<Self, AsmError> {
+        let tokens = cfg_tokens(text)?;
+        let mut config = Self {
+            memory: vec![],
+            segments: vec![],
+            implicit: false,
+        };
+        let mut pos = 0;
+        while let Some(token) = tokens.get(pos) {
+            let block = match (token, tokens.get(pos + 1)) {
+                (CfgToken::Word(block), Some(CfgToken::Punct('{'))) => block.to_ascii_uppercase(),
+                _ => return Err(AsmError::Link("config: block expected".to_owned())),
+            };
+            pos += 2;
+            let entries = cfg_entries(&tokens, &mut pos)?;
+            match block.as_str() {
+                "MEMORY" => {
+                    for (name, attrs) in entries {
+                        let number = |key: &str| {
+                            attrs
+                                .get(key)
+                                .map(|v| cfg_number(v))
+                                .transpose()?
+                                .ok_or_else(|| {
+                                    AsmError::Link(format!(
+                                        "memory area `{}` needs `{}`",
+                                        name, key
+                                    ))
+                                })
+                        };
+                        config.memory.push(MemoryArea {
+                            start: number("start")?,
+                            size: number("size")?,
+                            fill: attrs.get("fill").is_some_and(|v| v == "yes"),
+                            fill_value: attrs
+                                .get("fillval")
+                                .map(|v| cfg_number(v))
+                                .transpose()?
+                                .unwrap_or(0) as u8,
+                            name,
+                        });
+                    }
+                }
+                "SEGMENTS" => {
+                    for (name, attrs) in entries {
+                        let load = attrs.get("load").cloned().ok_or_else(|| {
+                            AsmError::Link(format!("segment `{}` needs `load`", name))
+                        })?;
+                        let kind = match attrs.get("type").map(String::as_str) {
+                            None | Some("ro") => SegmentKind::ReadOnly,
+                            Some("rw") => SegmentKind::ReadWrite,
+                            Some("bss") => SegmentKind::Bss,
+                            Some("zp") => SegmentKind::ZeroPage,
+                            Some("overwrite") => SegmentKind::Overwrite,
+                            Some(t) => {
+                                return Err(AsmError::Link(format!("unknown segment type `{}`", t)))
+                            }
+                        };
+                        config.segments.push(SegmentRule {
+                            load,
+                            kind,
+                            start: attrs.get("start").map(|v| cfg_number(v)).transpose()?,
+                            align: attrs.get("align").map(|v| cfg_number(v)).transpose()?,
+                            define: attrs.get("define").is_some_and(|v| v == "yes"),
+                            optional: attrs.get("optional").is_some_and(|v| v == "yes"),
+                            name,
+                        });
+                    }
+                }
+                // FEATURES, SYMBOLS and FILES don't matter for flat binaries
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A placed segment of the output.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Placement {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+}
+
+/// The linked program: the flat output file and the addresses of all labels.
+#[derive(Debug, Clone)]
+pub struct Linked {
+    pub image: Vec<u8>,
+    pub symbols: SymbolTable,
+    pub segments: Vec<Placement>,
+}
+
+struct Context<'o> {
+    objects: &'o [Object],
+    /// address of every section of every object
+    bases: Vec<Vec<u32>>,
+    exports: HashMap<&'o str, usize>,
+    defines: HashMap<String, i64>,
+}
+
+impl<'o> Context<'o> {
+    fn lookup(&self, object: usize, name: &str, depth: usize) -> Option<i64> {
+        if depth > 32 {
+            return None;
+        }
+        let obj = &self.objects[object];
+        match obj.symbols.get(name) {
+            Some(Symbol::Label { section, offset }) => {
+                Some((self.bases[object][*section] + offset) as i64)
+            }
+            Some(Symbol::Const(expr)) => expr.eval(None, &|n| self.lookup(object, n, depth + 1)),
+            None if obj.imports.contains(name) || obj.autoimport => match self.exports.get(name) {
+                Some(&other) => self.lookup(other, name, depth + 1),
+                None => self.defines.get(name).copied(),
+            },
+            None => None,
+        }
+    }
+}
+
+/// Places the sections of all objects as described by `config` and resolves
+/// every value, producing the same flat binary ld65 would.
+pub fn link(objects: &[Object], config: &LinkConfig) -> Result<Linked, AsmError> {
+    let mut rules = config.segments.clone();
+    if config.implicit {
+        for obj in objects {
+            for section in &obj.sections {
+                if section.size > 0 && !rules.iter().any(|r| r.name == section.name) {
+                    rules.push(SegmentRule {
+                        name: section.name.clone(),
+                        load: config.memory[0].name.clone(),
+                        kind: SegmentKind::ReadOnly,
+                        start: None,
+                        align: None,
+                        define: false,
+                        optional: false,
+                    });
+                }
+            }
+        }
+    }
+    for obj in objects {
+        for section in &obj.sections {
+            if section.size > 0 && !rules.iter().any(|r| r.name == section.name) {
+                return Err(AsmError::Link(format!(
+                    "segment `{}` of {} is missing in the config",
+                    section.name, obj.name
+                )));
+            }
+        }
+    }
+
+    let mut bases: Vec<Vec<u32>> = objects.iter().map(|o| vec![0; o.sections.len()]).collect();
+    let mut segments = vec![];
+    let mut defines = HashMap::new();
+    for area in &config.memory {
+        let mut cursor = area.start;
+        for rule in rules.iter().filter(|r| r.load == area.name) {
+            let mut start = match (rule.start, rule.kind) {
+                (Some(s), SegmentKind::Overwrite) => s,
+                (Some(s), _) if s < cursor => {
+                    return Err(AsmError::Link(format!(
+                        "segment `{}` can't start at ${:04X}, memory area `{}` is already used up to ${:04X}",
+                        rule.name, s, area.name, cursor
+                    )))
+                }
+                (Some(s), _) => s,
+                (None, _) => cursor,
+            };
+            if let Some(align) = rule.align.filter(|a| *a > 1) {
+                start = start.div_ceil(align) * align;
+            }
+            let mut end = start;
+            for (o, obj) in objects.iter().enumerate() {
+                for (s, section) in obj.sections.iter().enumerate() {
+                    if section.name == rule.name {
+                        bases[o][s] = end;
+                        end += section.size;
+                    }
+                }
+            }
+            if end > area.start + area.size {
+                return Err(AsmError::Link(format!(
+                    "segment `{}` overflows memory area `{}` by {} bytes",
+                    rule.name,
+                    area.name,
+                    end - (area.start + area.size)
+                )));
+            }
+            if rule.define {
+                defines.insert(format!("__{}_LOAD__", rule.name), start as i64);
+                defines.insert(format!("__{}_RUN__", rule.name), start as i64);
+                defines.insert(format!("__{}_SIZE__", rule.name), (end - start) as i64);
+            }
+            cursor = cursor.max(end);
+            segments.push((
+                rule.clone(),
+                Placement {
+                    name: rule.name.clone(),
+                    start,
+                    size: end - start,
+                },
+            ));
+        }
+    }
+    for rule in &rules {
+        if !segments.iter().any(|(r, _)| r.name == rule.name) {
+            return Err(AsmError::Link(format!(
+                "segment `{}` is loaded into unknown memory area `{}`",
+                rule.name, rule.load
+            )));
+        }
+    }
+
+    let mut exports = HashMap::new();
+    for (o, obj) in objects.iter().enumerate() {
+        for name in &obj.exports {
+            if !obj.symbols.contains_key(name) {
+                return Err(AsmError::Link(format!(
+                    "{} exports undefined symbol `{}`",
+                    obj.name, name
+                )));
+            }
+            if exports.insert(name.as_str(), o).is_some() {
+                return Err(AsmError::Link(format!("duplicate export `{}`", name)));
+            }
+        }
+    }
+    let ctx = Context {
+        objects,
+        bases,
+        exports,
+        defines,
+    };
+
+    // write every section into the memory image of its area
+    let mut images: Vec<(Vec<u8>, u32)> = config
+        .memory
+        .iter()
+        .map(|a| (vec![a.fill_value; a.size as usize], a.start))
+        .collect();
+    for (o, obj) in objects.iter().enumerate() {
+        for (s, section) in obj.sections.iter().enumerate() {
+            let (rule, _) = match segments.iter().find(|(r, _)| r.name == section.name) {
+                Some(seg) => seg,
+                None => continue,
+            };
+            let area = config
+                .memory
+                .iter()
+                .position(|a| a.name == rule.load)
+                .unwrap();
+            let written = !matches!(rule.kind, SegmentKind::Bss | SegmentKind::ZeroPage);
+            let base = ctx.bases[o][s];
+            let mut bytes = Vec::with_capacity(section.size as usize);
+            for fragment in &section.fragments {
+                let here = base + bytes.len() as u32;
+                match fragment {
+                    Fragment::Bytes(b) => bytes.extend_from_slice(b),
+                    Fragment::Fill { len, value } => {
+                        bytes.extend(std::iter::repeat_n(*value, *len as usize))
+                    }
+                    Fragment::Value {
+                        expr,
+                        size,
+                        at,
+                        loc,
+                    } => {
+                        let value = eval(&ctx, o, expr, base + at, loc)?;
+                        let fits = match size {
+                            1 => (-0x80..0x100).contains(&value),
+                            2 => (-0x8000..0x10000).contains(&value),
+                            _ => true,
+                        };
+                        if !fits {
+                            return Err(loc.error(format!(
+                                "value ${:X} doesn't fit in {} bytes",
+                                value, size
+                            )));
+                        }
+                        bytes.extend_from_slice(&value.to_le_bytes()[..*size as usize]);
+                    }
+                    Fragment::Branch { expr, at, loc } => {
+                        let target = eval(&ctx, o, expr, base + at, loc)?;
+                        let offset = target - (here as i64 + 1);
+                        if !(-128..128).contains(&offset) {
+                            return Err(loc.error(format!(
+                                "branch target out of range by {} bytes",
+                                offset.abs() - if offset < 0 { 128 } else { 127 }
+                            )));
+                        }
+                        bytes.push(offset as u8);
+                    }
+                }
+            }
+            if written {
+                let (image, start) = &mut images[area];
+                let from = (base - *start) as usize;
+                image[from..from + bytes.len()].copy_from_slice(&bytes);
+            }
+        }
+    }
+
+    // like ld65, write every memory area up to its last used byte, unless it
+    // is filled
+    let mut image = vec![];
+    for (idx, area) in config.memory.iter().enumerate() {
+        let used = segments
+            .iter()
+            .filter(|(r, _)| {
+                r.load == area.name && !matches!(r.kind, SegmentKind::Bss | SegmentKind::ZeroPage)
+            })
+            .map(|(_, p)| p.start + p.size - area.start)
+            .max()
+            .unwrap_or(0);
+        let len = if area.fill { area.size } else { used };
+        image.extend_from_slice(&images[idx].0[..len as usize]);
+    }
+
+    let mut labels = vec![];
+    for (o, obj) in objects.iter().enumerate() {
+        for (name, symbol) in &obj.symbols {
+            // unnamed and cheap local labels don't make good names
+            if matches!(symbol, Symbol::Label { .. }) && !name.contains(['@', ':']) {
+                if let Some(value) = ctx.lookup(o, name, 0) {
+                    labels.push((value as u16, name.as_str()));
+                }
+            }
+        }
+    }
+    labels.sort_unstable();
+    let mut symbols = SymbolTable::new();
+    for (address, name) in labels {
+        if symbols.get(address).is_none() {
+            symbols.insert(address, name);
+        }
+    }
+
+    Ok(Linked {
+        image,
+        symbols,
+        segments: segments.into_iter().map(|(_, p)| p).collect(),
+    })
+}
+
+fn eval(
+    ctx: &Context,
+    object: usize,
+    expr: &super::expr::Expr,
+    pc: u32,
+    loc: &super::Location,
+) -> Result<i64, AsmError> {
+    expr.eval(Some(pc as i64), &|n| ctx.lookup(object, n, 0))
+        .ok_or_else(|| {
+            let mut missing = vec![];
+            expr.symbols(&mut |n| {
+                if ctx.lookup(object, n, 0).is_none() {
+                    missing.push(n.to_owned())
+                }
+            });
+            loc.error(format!("unresolved symbol `{}`", missing.join("`, `")))
+        })
+}
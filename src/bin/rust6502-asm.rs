@@ -0,0 +1,80 @@
+use rust6502::asm::{link, AsmError, Assembler, LinkConfig};
+use std::fs;
+use std::process::exit;
+
+const USAGE: &str =
+    "usage: rust6502-asm [-I dir]... [-C config] [-S start] [-Ln labels] -o output file.s...";
+
+fn parse_start(value: &str) -> Option<u16> {
+    match value.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut assembler = Assembler::new();
+    let mut config = None;
+    let mut start = 0;
+    let mut labels = None;
+    let mut output = None;
+    let mut sources = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-I" | "--include-dir" => {
+                assembler.include_dir(value()?);
+            }
+            "-C" | "--config" => config = Some(value()?),
+            "-S" | "--start-addr" => {
+                let v = value()?;
+                start = parse_start(&v).ok_or_else(|| format!("invalid start address `{}`", v))?;
+            }
+            "-Ln" => labels = Some(value()?),
+            "-o" => output = Some(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option `{}`\n{}", arg, USAGE))
+            }
+            _ => sources.push(arg),
+        }
+    }
+    let output = output.ok_or_else(|| USAGE.to_owned())?;
+    if sources.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+
+    let config = match config {
+        Some(file) => {
+            let text = fs::read_to_string(&file).map_err(|e| format!("{}: {}", file, e))?;
+            LinkConfig::parse(&text).map_err(|e| e.to_string())?
+        }
+        None => LinkConfig::flat(start),
+    };
+    let objects = sources
+        .iter()
+        .map(|s| assembler.assemble_file(s))
+        .collect::<Result<Vec<_>, AsmError>>()
+        .map_err(|e| e.to_string())?;
+    let linked = link(&objects, &config).map_err(|e| e.to_string())?;
+    fs::write(&output, &linked.image).map_err(|e| format!("{}: {}", output, e))?;
+    if let Some(file) = labels {
+        fs::write(&file, linked.symbols.to_vice()).map_err(|e| format!("{}: {}", file, e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("rust6502-asm: {}", e);
+        exit(1);
+    }
+}
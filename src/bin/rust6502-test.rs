@@ -0,0 +1,90 @@
+use rust6502::cpu::model::CpuModel;
+use rust6502::testsuite::klaus::TrapTest;
+use rust6502::testsuite::processor_tests::run_directory;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str =
+    "usage: rust6502-test [--functional | --decimal] [--model 6502|2a03|6510|65c02] \
+[--load addr] [--start addr] [--success addr] [--error-flag addr] [--max-cycles n] image.bin
+       rust6502-test [--model 6502|2a03|6510|65c02] processor-tests-dir";
+
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Runs a Klaus Dormann style test image, or a directory of single step
+/// tests, returns whether it passed.
+fn run() -> Result<bool, String> {
+    let mut test = TrapTest::functional();
+    let mut model = CpuModel::Nmos6502;
+    let mut image = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+        };
+        let mut address = || {
+            let v = value()?;
+            parse_address(&v).ok_or_else(|| format!("invalid address `{}`", v))
+        };
+        match arg.as_str() {
+            "--functional" => test = TrapTest::functional(),
+            "--decimal" => test = TrapTest::decimal(),
+            "--model" => model = value()?.parse().map_err(|e| format!("{}", e))?,
+            "--load" => test.load = address()?,
+            "--start" => test.start = address()?,
+            "--success" => test.success = Some(address()?),
+            "--error-flag" => test.error_flag = Some(address()?),
+            "--max-cycles" => {
+                let v = value()?;
+                test.max_cycles = v
+                    .parse()
+                    .map_err(|_| format!("invalid cycle count `{}`", v))?;
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(true);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option `{}`\n{}", arg, USAGE))
+            }
+            _ => image = Some(arg),
+        }
+    }
+    let file = image.ok_or_else(|| USAGE.to_owned())?;
+    if Path::new(&file).is_dir() {
+        let reports = run_directory(model, &file).map_err(|e| format!("{}: {}", file, e))?;
+        for report in &reports {
+            println!("{}", report);
+        }
+        let failed = reports.iter().filter(|r| !r.passed()).count();
+        println!(
+            "{} of {} opcodes passed",
+            reports.len() - failed,
+            reports.len()
+        );
+        return Ok(failed == 0);
+    }
+    let image = fs::read(&file).map_err(|e| format!("{}: {}", file, e))?;
+    let report = test.run(model, &image);
+    print!("{}", report);
+    Ok(report.passed)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("rust6502-test: {}", e);
+            exit(2);
+        }
+    }
+}
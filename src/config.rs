@@ -0,0 +1,464 @@
+//! Machine descriptions in TOML, so other boards and memory layouts don't need
+//! a recompile:
+//!
+//! ```toml
+//! [cpu]
+//! model = "65c02"
+//!
+//! [[device]]
+//! type = "ram"
+//! start = 0x0100
+//! size = 0x0400
+//!
+//! [[device]]
+//! name = "kernel"
+//! type = "rom"
+//! start = 0xE000
+//! file = "code/bin/kernel"
+//! ```
+//!
+//! The device types are `ram` (`start`, `size`), `rom` (`start`, `file` and
+//! optionally `size`, padded with $FF), `vga` (video RAM at `start`, `size`
+//! and the `font` for the window), `keyboard` (three registers at `start`, its
+//! interrupt goes to IRQ, `mode` is `raw`, `ps2` or `ascii` and held keys
+//! repeat after `repeat_delay` cycles every `repeat_interval` cycles in the
+//! ascii mode) and `vectors` (the `nmi`, `reset` and `irq` vectors at $FFFA).
+//! Files are relative to the config file. Devices may not overlap, unless one
+//! of them has a higher `priority` (0 by default), it then owns the addresses
+//! they share. Every device can have a `name` for the error messages.
+//!
+//! Reads from addresses without a device return the last value on the bus,
+//! unless `floating` in the `[bus]` table sets a value for them.
+
+use crate::cpu::model::CpuModel;
+use crate::cpu::CPU;
+use crate::device::device_map::{DeviceMap, OpenBus};
+use crate::device::interrupts::Interrupts;
+use crate::device::vga::{KeyScript, KeyUpdate, Keyboard, KeyboardMode, Typematic, Vga};
+use crate::device::{CreateError, Ram, Rom};
+use crate::machine::Machine;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    #[serde(default)]
+    pub cpu: CpuConfig,
+    #[serde(default)]
+    pub bus: BusConfig,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+    /// what the files are relative to
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuConfig {
+    #[serde(default)]
+    pub model: CpuModel,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BusConfig {
+    /// what reads from unmapped addresses return, instead of the last value
+    pub floating: Option<u8>,
+}
+
+/// `name` and `priority` are taken out by hand, serde's `flatten` would let
+/// misspelt fields of the kind through.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "toml::value::Table")]
+pub struct DeviceConfig {
+    /// used in error messages, defaults to the type and start address
+    pub name: Option<String>,
+    /// who owns the addresses that overlap with another device
+    pub priority: u8,
+    pub kind: DeviceKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DeviceKind {
+    Ram {
+        start: u16,
+        size: u32,
+    },
+    Rom {
+        start: u16,
+        file: PathBuf,
+        size: Option<u32>,
+    },
+    Vga {
+        start: u16,
+        size: u32,
+        font: PathBuf,
+    },
+    Keyboard {
+        start: u16,
+        #[serde(default)]
+        mode: KeyboardMode,
+        /// cycles before a held key repeats in the ascii mode
+        repeat_delay: Option<u64>,
+        /// cycles between the repeats
+        repeat_interval: Option<u64>,
+    },
+    Vectors {
+        nmi: u16,
+        reset: u16,
+        irq: u16,
+    },
+}
+
+impl TryFrom<toml::value::Table> for DeviceConfig {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::value::Table) -> Result<Self, Self::Error> {
+        let name = table.remove("name").map(|n| n.try_into()).transpose()?;
+        let priority = table.remove("priority").map(|p| p.try_into()).transpose()?;
+        Ok(Self {
+            name,
+            priority: priority.unwrap_or_default(),
+            kind: toml::Value::Table(table).try_into()?,
+        })
+    }
+}
+
+impl DeviceConfig {
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let (kind, start) = match self.kind {
+            DeviceKind::Ram { start, .. } => ("ram", start),
+            DeviceKind::Rom { start, .. } => ("rom", start),
+            DeviceKind::Vga { start, .. } => ("vga", start),
+            DeviceKind::Keyboard { start, .. } => ("keyboard", start),
+            DeviceKind::Vectors { .. } => ("vectors", 0xFFFA),
+        };
+        format!("{} at ${:04X}", kind, start)
+    }
+}
+
+/// The devices a config describes, and the parts of them the frontends need.
+pub struct Board {
+    pub model: CpuModel,
+    pub bus: DeviceMap,
+    /// `None` if there's no `vga` device
+    pub vga: Option<Vga>,
+    /// the queue the keyboards read from, the window pushes into it
+    pub keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
+    /// scheduled key presses for the same queue, it's attached to the bus
+    pub script: Arc<Mutex<KeyScript>>,
+    /// the interrupt lines the devices are connected to
+    pub interrupts: Interrupts,
+}
+
+impl Board {
+    pub fn cpu(&self) -> CPU {
+        CPU::with_model(self.model)
+    }
+
+    /// A machine with the CPU and devices of the board.
+    pub fn machine(self) -> Machine {
+        Machine::with_interrupts(self.cpu(), self.bus, self.interrupts)
+    }
+}
+
+/// The addresses of a device, if it fits in the address space.
+fn range(name: &str, start: u16, size: u32) -> Result<RangeInclusive<u16>, CreateError> {
+    let end = start as u32 + size;
+    if size == 0 || end > 0x10000 {
+        return Err(CreateError::RangeError {
+            name: name.to_owned(),
+        });
+    }
+    Ok(start..=(end - 1) as u16)
+}
+
+impl MachineConfig {
+    pub fn parse(text: &str) -> Result<Self, CreateError> {
+        toml::from_str(text).map_err(|e| CreateError::ConfigError(e.to_string()))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, CreateError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|source| CreateError::FileError {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut config = Self::parse(&text)?;
+        config.base = path.parent().map(Path::to_owned).unwrap_or_default();
+        Ok(config)
+    }
+
+    /// Loads the files and builds the bus, after checking that the devices
+    /// fit in the address space and don't overlap.
+    pub fn build(&self) -> Result<Board, CreateError> {
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let script = Arc::new(Mutex::new(KeyScript::new(Arc::clone(&keys))));
+        let interrupts = Interrupts::new();
+        let mut vga = None;
+        let mut bus = DeviceMap::new();
+        bus.attach(Arc::clone(&script));
+        bus.set_open_bus(match self.bus.floating {
+            Some(value) => OpenBus::Floating(value),
+            None => OpenBus::LastValue,
+        });
+
+        for device in &self.devices {
+            let name = device.name();
+            let priority = device.priority;
+            match &device.kind {
+                DeviceKind::Ram { start, size } => {
+                    let range = range(&name, *start, *size)?;
+                    bus.map_with_priority(
+                        name,
+                        range,
+                        priority,
+                        Ram::from_vec(*start, vec![0; *size as usize]),
+                    )?;
+                }
+                DeviceKind::Rom { start, file, size } => {
+                    let path = self.base.join(file);
+                    let mut data = fs::read(&path)
+                        .map_err(|source| CreateError::FileError { path, source })?;
+                    let size = match size {
+                        Some(size) if data.len() > *size as usize => {
+                            return Err(CreateError::ConfigError(format!(
+                                "{} is larger than its size of ${:X}",
+                                file.display(),
+                                size
+                            )))
+                        }
+                        Some(size) => *size,
+                        None => data.len() as u32,
+                    };
+                    data.resize(size as usize, 0xFF);
+                    let range = range(&name, *start, size)?;
+                    bus.map_with_priority(name, range, priority, Rom::from_vec(*start, data))?;
+                }
+                DeviceKind::Vga { start, size, font } => {
+                    let range = range(&name, *start, *size)?;
+                    let path = self.base.join(font);
+                    let font = psf::Font::new(&path).map_err(|e| CreateError::FontError {
+                        path,
+                        reason: format!("{:?}", e),
+                    })?;
+                    let mem = Arc::new(Mutex::new(Ram::from_vec(*start, vec![0; *size as usize])));
+                    let window = Vga::new(font, Arc::clone(&keys), Arc::clone(&mem))
+                        .with_script(Arc::clone(&script));
+                    vga = Some(window);
+                    bus.map_with_priority(name, range, priority, mem)?;
+                }
+                DeviceKind::Keyboard {
+                    start,
+                    mode,
+                    repeat_delay,
+                    repeat_interval,
+                } => {
+                    let range = range(&name, *start, 3)?;
+                    let default = Typematic::default();
+                    let typematic = Typematic {
+                        delay: repeat_delay.unwrap_or(default.delay),
+                        interval: repeat_interval.unwrap_or(default.interval),
+                    };
+                    if typematic.interval == 0 {
+                        return Err(CreateError::ConfigError(format!(
+                            "{} can't repeat every 0 cycles",
+                            name
+                        )));
+                    }
+                    let keyboard = Keyboard::new(*start, Arc::clone(&keys))
+                        .with_mode(*mode)
+                        .with_typematic(typematic)
+                        .with_irq(interrupts.irq_line()?);
+                    bus.map_with_priority(name, range, priority, keyboard)?;
+                }
+                DeviceKind::Vectors { nmi, reset, irq } => {
+                    bus.map_with_priority(
+                        name,
+                        0xFFFA..=0xFFFF,
+                        priority,
+                        Rom::interrupts(*nmi, *reset, *irq),
+                    )?;
+                }
+            }
+        }
+
+        Ok(Board {
+            model: self.cpu.model,
+            bus,
+            vga,
+            keys,
+            script,
+            interrupts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+
+    #[test]
+    fn build() {
+        let config = MachineConfig::parse(
+            r#"
+            [cpu]
+            model = "65c02"
+
+            [bus]
+            floating = 0xEA
+
+            [[device]]
+            type = "ram"
+            start = 0x0000
+            size = 0x0200
+
+            [[device]]
+            type = "keyboard"
+            start = 0x0300
+            mode = "ps2"
+
+            [[device]]
+            name = "io"
+            type = "ram"
+            start = 0x0010
+            size = 1
+            priority = 1
+
+            [[device]]
+            name = "kernel"
+            type = "rom"
+            start = 0xE000
+            file = "Cargo.toml"
+            size = 0x1000
+
+            [[device]]
+            type = "vectors"
+            nmi = 0x1234
+            reset = 0xE000
+            irq = 0x5678
+            "#,
+        )
+        .unwrap();
+        let mut board = MachineConfig {
+            base: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+            ..config
+        }
+        .build()
+        .unwrap();
+        assert_eq!(board.model, CpuModel::Wdc65C02);
+        assert!(board.vga.is_none());
+        assert_eq!(board.bus.read(0xE000), Some(b'['));
+        // padded up to the size
+        assert_eq!(board.bus.read(0xEFFF), Some(0xFF));
+        assert_eq!(board.bus.device_at(0xF000), None);
+        assert_eq!(board.bus.read(0xF000), Some(0xEA));
+        assert_eq!(board.bus.read(0xFFFD), Some(0xE0));
+        board.bus.write(0x01FF, 42).unwrap();
+        assert_eq!(board.bus.read(0x01FF), Some(42));
+        assert_eq!(board.bus.device_at(0x0010).unwrap().0, "io");
+        assert_eq!(board.bus.device_at(0x0011).unwrap().0, "ram at $0000");
+        let a = KeyUpdate::release(olc_pixel_game_engine::Key::A);
+        board.keys.lock().unwrap().push_back(a);
+        assert_eq!(board.bus.read(0x0300), Some(0xF0));
+        assert_eq!(board.bus.read(0x0300), Some(0x1C));
+        assert_eq!(board.bus.device_at(0x0302).unwrap().0, "keyboard at $0300");
+        // the script is ticked with the bus
+        board.script.lock().unwrap().type_text(10, "b").unwrap();
+        board.bus.tick(10);
+        assert_eq!(board.bus.read(0x0300), Some(0x32));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| {
+            let config = MachineConfig::parse(text).and_then(|c| c.build().map(|_| ()));
+            config.unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(
+                r#"
+                [[device]]
+                type = "ram"
+                start = 0x0100
+                size = 0x0400
+
+                [[device]]
+                name = "kernel"
+                type = "rom"
+                start = 0x0400
+                file = "Cargo.toml"
+                "#
+            ),
+            "ram at $0100 and kernel overlap at $0400"
+        );
+        assert_eq!(
+            error(
+                r#"
+                [[device]]
+                type = "ram"
+                start = 0xF000
+                size = 0x1001
+                "#
+            ),
+            "ram at $F000 doesn't fit below $10000"
+        );
+        assert_eq!(
+            error(
+                r#"
+                [[device]]
+                type = "keyboard"
+                start = 0x0010
+                mode = "ascii"
+                repeat_interval = 0
+                "#
+            ),
+            "invalid machine config: keyboard at $0010 can't repeat every 0 cycles"
+        );
+        assert!(error(
+            r#"
+            [[device]]
+            type = "rom"
+            start = 0x8000
+            file = "missing.bin"
+            "#
+        )
+        .starts_with("missing.bin: "));
+        assert!(
+            error("[[device]]\ntype = \"floppy\"\nstart = 0").starts_with("invalid machine config")
+        );
+        assert!(error("[cpu]\nmodel = \"z80\"").contains("unknown CPU model `z80`"));
+        // misspelt fields aren't ignored
+        let misspelt = error(
+            r#"
+            [[device]]
+            type = "keyboard"
+            start = 0x0010
+            repeat_dealy = 100
+            "#,
+        );
+        assert!(
+            misspelt.contains("unknown field `repeat_dealy`"),
+            "{}",
+            misspelt
+        );
+        let misspelt = error("[[device]]\ntype = \"ram\"\nstart = 0\nsise = 16");
+        assert!(misspelt.contains("unknown field `sise`"), "{}", misspelt);
+        assert!(
+            error("[[device]]\ntype = \"ram\"\nstart = 0\nsize = 1\npriority = -1")
+                .starts_with("invalid machine config")
+        );
+    }
+}
@@ -1,7 +1,17 @@
+use crate::cpu::model::{CpuModel, IoPort};
+use crate::cpu::opcodes::{Instruction, Opcode};
+use crate::device::Device;
 use bitflags::bitflags;
+use serde::Deserialize;
 use std::num::Wrapping;
 
+#[cfg(test)]
+mod bench;
+pub mod disasm;
 pub mod instructions;
+pub mod model;
+pub mod opcodes;
+pub mod trace;
 
 #[derive(Debug)]
 pub struct CPU {
@@ -15,12 +25,24 @@ pub struct CPU {
     ir: u16,
     nmi_pip: u16,
     irq_pip: u16,
+    /// the NMI input of the last tick, for the edge detection
+    nmi_line: bool,
     brk_flags: BreakFlags,
     bcd_enabled: bool,
     adl_adh: u16,
+    model: CpuModel,
+    /// ORed into the accumulator by the unstable ANE and LXA opcodes
+    magic: u8,
+    port: Option<IoPort>,
+    cycles: u64,
+    instructions: u64,
+    instruction_start: u64,
+    /// where the opcode of the current instruction was fetched from
+    instruction_address: u16,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ReadWrite {
     Read,
     Write,
@@ -92,12 +114,12 @@ bitflags! {
     }
 }
 
-fn fetch(mut pins: &mut Pins, pc: u16) {
-    sa(&mut pins, pc);
-    on(&mut pins, PinFlags::Sync);
+fn fetch(pins: &mut Pins, pc: u16) {
+    sa(pins, pc);
+    on(pins, PinFlags::Sync);
 }
 
-fn sa(mut pins: &mut Pins, addr: u16) {
+fn sa(pins: &mut Pins, addr: u16) {
     pins.address = addr;
 }
 
@@ -105,12 +127,12 @@ fn ga(pins: &Pins) -> u16 {
     pins.address
 }
 
-fn sad(mut pins: &mut Pins, addr: u16, data: u8) {
+fn sad(pins: &mut Pins, addr: u16, data: u8) {
     pins.address = addr;
     pins.data = data;
 }
 
-fn sd(mut pins: &mut Pins, data: u8) {
+fn sd(pins: &mut Pins, data: u8) {
     pins.data = data;
 }
 
@@ -118,7 +140,7 @@ fn gd(pins: &Pins) -> u8 {
     pins.data
 }
 
-fn on(mut pins: &mut Pins, x: PinFlags) {
+fn on(pins: &mut Pins, x: PinFlags) {
     match x {
         PinFlags::Sync => pins.sync = true,
         PinFlags::Irq => pins.irq = true,
@@ -128,7 +150,7 @@ fn on(mut pins: &mut Pins, x: PinFlags) {
     }
 }
 
-fn off(mut pins: &mut Pins, x: PinFlags) {
+fn off(pins: &mut Pins, x: PinFlags) {
     match x {
         PinFlags::Sync => pins.sync = false,
         PinFlags::Irq => pins.irq = false,
@@ -138,14 +160,27 @@ fn off(mut pins: &mut Pins, x: PinFlags) {
     }
 }
 
-fn rd(mut pins: &mut Pins) {
+fn rd(pins: &mut Pins) {
     pins.rw = ReadWrite::Read;
 }
 
-fn wr(mut pins: &mut Pins) {
+fn wr(pins: &mut Pins) {
     pins.rw = ReadWrite::Write;
 }
 
+/// What [`CPU::step_instruction`] executed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StepResult {
+    /// where the opcode was fetched from
+    pub address: u16,
+    /// the opcode that ran, $00 for interrupts and the reset
+    pub opcode: u8,
+    pub cycles: u64,
+    /// set when an interrupt or reset sequence ran instead of the
+    /// instruction at `address`, which runs when it returns
+    pub interrupt: bool,
+}
+
 impl Default for CPU {
     fn default() -> Self {
         Self::new()
@@ -155,22 +190,185 @@ impl Default for CPU {
 impl CPU {
     #[must_use]
     pub fn new() -> CPU {
+        Self::with_model(CpuModel::default())
+    }
+
+    #[must_use]
+    pub fn with_model(model: CpuModel) -> CPU {
         CPU {
             pc: 0,
             ac: 0,
             x: 0,
             y: 0,
-            sr: StatusRegister::Z,
+            sr: StatusRegister::empty(),
             sp: 0,
             ir: 0,
             pins: Pins::new(),
             nmi_pip: 0,
             irq_pip: 0,
+            nmi_line: false,
             brk_flags: BreakFlags::empty(),
-            bcd_enabled: false,
+            bcd_enabled: model.has_decimal_mode(),
             adl_adh: 0,
+            model,
+            magic: model.magic_constant(),
+            port: if model.has_io_port() {
+                Some(IoPort::default())
+            } else {
+                None
+            },
+            cycles: 0,
+            instructions: 0,
+            instruction_start: 0,
+            instruction_address: 0,
+        }
+    }
+
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    /// The on-chip I/O port, only the 6510 has one.
+    pub fn io_port(&self) -> Option<&IoPort> {
+        self.port.as_ref()
+    }
+
+    pub fn io_port_mut(&mut self) -> Option<&mut IoPort> {
+        self.port.as_mut()
+    }
+
+    /// The number of ticks so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The number of instructions that have been started so far, interrupts
+    /// and the reset sequence don't count.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The cycle in which the opcode of the current instruction (or
+    /// interrupt) was fetched, so `cycles() - instruction_start()` is how
+    /// long it has been running.
+    pub fn instruction_start(&self) -> u64 {
+        self.instruction_start
+    }
+
+    /// Where the opcode of the current instruction was fetched from.
+    pub fn instruction_address(&self) -> u16 {
+        self.instruction_address
+    }
+
+    /// Triggers a non-maskable interrupt, as if the NMI line had an edge in
+    /// this cycle.
+    pub fn nmi(&mut self) {
+        self.nmi_pip |= 1;
+    }
+
+    /// Runs a single cycle and does its bus access on `bus`. When no device
+    /// answers a read, the data pins keep their previous value.
+    pub fn cycle(&mut self, bus: &mut dyn Device) -> Pins {
+        let mut pins = self.tick(self.pins);
+        if pins.rw == ReadWrite::Read {
+            if let Some(data) = bus.read(pins.address) {
+                pins.data = data;
+            }
+        } else {
+            let _ = bus.write(pins.address, pins.data);
+        }
+        self.pins = pins;
+        pins
+    }
+
+    /// Whether the CPU is stuck on a `JAM` or, on the 65C02, an `STP`, which
+    /// only a reset gets it out of.
+    pub fn is_halted(&self) -> bool {
+        let opcode = (self.ir >> 3) as u8;
+        let halting = match self.model {
+            CpuModel::Wdc65C02 => opcode == 0xDB,
+            _ => Instruction::from_byte(opcode).opcode == Opcode::JAM,
+        };
+        halting && !self.pins.sync && self.brk_flags.is_empty()
+    }
+
+    /// Whether a 65C02 is in a `WAI`, waiting for an interrupt line to be
+    /// pulled.
+    pub fn is_waiting(&self) -> bool {
+        let opcode = (self.ir >> 3) as u8;
+        self.model == CpuModel::Wdc65C02
+            && opcode == 0xCB
+            && !self.pins.sync
+            && self.brk_flags.is_empty()
+    }
+
+    /// Runs cycles until the next opcode fetch. Called in the middle of an
+    /// instruction, it finishes that instruction. If the CPU halts it
+    /// returns right away, with the cycles up to that point. A `WAI` returns
+    /// after every cycle until it's woken up, so the caller can tick the
+    /// devices that pull the interrupt lines.
+    pub fn step_instruction(&mut self, bus: &mut dyn Device) -> StepResult {
+        self.step_instruction_with(bus, |_, _| {})
+    }
+
+    /// [`step_instruction`](Self::step_instruction), calling `on_cycle`
+    /// after the bus access of every cycle, for tracing. It may set the
+    /// input pins of the next cycle, like the interrupt lines.
+    pub fn step_instruction_with(
+        &mut self,
+        bus: &mut dyn Device,
+        mut on_cycle: impl FnMut(&mut CPU, &Pins),
+    ) -> StepResult {
+        let start = self.instruction_start;
+        let address = if self.pins.sync {
+            self.pins.address
+        } else {
+            self.instruction_address
+        };
+        let mut interrupt = !self.brk_flags.is_empty();
+        let mut opcode = (self.ir >> 3) as u8;
+        if self.pins.sync {
+            let pins = self.cycle(bus);
+            on_cycle(self, &pins);
+            interrupt = !self.brk_flags.is_empty();
+            opcode = (self.ir >> 3) as u8;
+        }
+        while !self.pins.sync && !self.is_halted() {
+            let pins = self.cycle(bus);
+            on_cycle(self, &pins);
+            if self.is_waiting() {
+                break;
+            }
+        }
+        StepResult {
+            address,
+            opcode,
+            cycles: if self.pins.sync {
+                self.instruction_start - start
+            } else {
+                self.cycles - start
+            },
+            interrupt,
+        }
+    }
+
+    pub fn run_cycles(&mut self, bus: &mut dyn Device, cycles: u64) {
+        for _ in 0..cycles {
+            self.cycle(bus);
+        }
+    }
+
+    /// Runs whole instructions until `done` returns true or the CPU halts,
+    /// `done` is checked between instructions. Returns the number of cycles
+    /// that ran.
+    pub fn run_until(&mut self, bus: &mut dyn Device, mut done: impl FnMut(&CPU) -> bool) -> u64 {
+        let start = self.cycles;
+        while !self.is_halted() && !done(self) {
+            self.step_instruction(bus);
         }
+        self.cycles - start
     }
+
     fn nz(&mut self, value: u8) {
         let x = if value == 0 {
             StatusRegister::Z.bits
@@ -184,14 +382,26 @@ impl CPU {
     }
 
     pub fn tick(&mut self, mut pins: Pins) -> Pins {
+        // the I/O port registers answer reads instead of the bus
+        if let Some(port) = &self.port {
+            if self.pins.rw == ReadWrite::Read && self.pins.address < 2 {
+                pins.data = port.read(self.pins.address);
+            }
+        }
+        // compared with the last input rather than `self.pins`, `cycle` passes
+        // those back in
+        let nmi_edge = !self.nmi_line && pins.nmi;
+        self.nmi_line = pins.nmi;
         if pins.sync | pins.irq | pins.nmi | pins.rdy | pins.res {
-            if !self.pins.nmi && pins.nmi {
+            if nmi_edge {
                 self.nmi_pip |= 1;
             }
             if pins.irq && !self.sr.contains(StatusRegister::I) {
                 self.irq_pip |= 1;
             }
             if pins.rw == ReadWrite::Read && pins.rdy {
+                // a stalled cycle still takes time
+                self.cycles += 1;
                 self.pins = pins;
                 self.irq_pip <<= 1;
                 return pins;
@@ -211,7 +421,8 @@ impl CPU {
                 self.irq_pip &= 3;
                 self.nmi_pip &= 3;
                 if self.brk_flags.is_empty() {
-                    self.pc += 1;
+                    self.pc = self.pc.wrapping_add(1);
+                    self.instructions += 1;
                 } else {
                     self.ir = 0;
                     self.sr.remove(StatusRegister::B);
@@ -221,19 +432,26 @@ impl CPU {
         }
         rd(&mut pins);
 
-        // println!(
-        //     "going to execute {:#04X} step {}",
-        //     self.ir >> 3,
-        //     self.ir & 7
-        // );
-        // println!("new ad: {:#06X}, {:#04X}", pins.address, pins.data);
-        self.the_match_statement(&mut pins);
-        // println!("new ad: {:#06X}, {:#04X}", pins.address, pins.data);
-
-        // println!("self={:?}, pins={:?}", self, pins);
+        match self.model {
+            CpuModel::Wdc65C02 => self.the_match_statement_65c02(&mut pins),
+            _ => self.the_match_statement(&mut pins),
+        }
 
         self.ir += 1;
 
+        // writes to the port go to the bus as well
+        if let Some(port) = &mut self.port {
+            if pins.rw == ReadWrite::Write && pins.address < 2 {
+                port.write(pins.address, pins.data);
+            }
+        }
+
+        if pins.sync {
+            self.instruction_start = self.cycles;
+            self.instruction_address = pins.address;
+        }
+        self.cycles += 1;
+
         self.pins = pins;
         self.irq_pip <<= 1;
         self.nmi_pip <<= 1;
@@ -241,23 +459,25 @@ impl CPU {
     }
 
     fn adc(&mut self, val: u8) {
+        let c = if self.sr.contains(StatusRegister::C) {
+            1_u8
+        } else {
+            0
+        };
         if self.bcd_enabled && self.sr.contains(StatusRegister::D) {
-            let c = if self.sr.contains(StatusRegister::C) {
-                1_u8
-            } else {
-                0
-            };
             self.sr.remove(
-                StatusRegister::N | StatusRegister::V | StatusRegister::Z | StatusRegister::V,
+                StatusRegister::N | StatusRegister::V | StatusRegister::Z | StatusRegister::C,
             );
             let mut al = (self.ac & 0x0F) + (val & 0x0F) + c;
             if al > 9 {
                 al += 6;
             }
             let mut ah = (self.ac >> 4) + (val >> 4) + if al > 0x0F { 1 } else { 0 };
-            if 0 == ((u16::from(self.ac) + u16::from(val) + u16::from(c)) & 0xFF) {
+            // the NMOS 6502 sets Z from the binary sum and N and V from the sum
+            // before the high nibble is adjusted
+            if 0 == self.ac.wrapping_add(val).wrapping_add(c) {
                 self.sr.insert(StatusRegister::Z);
-            } else if ah & 0x80 != 0 {
+            } else if ah & 0x08 != 0 {
                 self.sr.insert(StatusRegister::N);
             }
             if (!(self.ac ^ val) & (self.ac ^ (ah << 4)) & 0x80) != 0 {
@@ -270,14 +490,11 @@ impl CPU {
                 self.sr.insert(StatusRegister::C);
             }
             self.ac = (ah << 4) | (al & 0xF);
+            if self.model == CpuModel::Wdc65C02 {
+                self.nz(self.ac);
+            }
         } else {
-            let sum = self.ac as u16
-                + val as u16
-                + if self.sr.contains(StatusRegister::C) {
-                    1
-                } else {
-                    0
-                };
+            let sum = self.ac as u16 + val as u16 + c as u16;
             self.sr.remove(StatusRegister::V | StatusRegister::C);
             self.nz(sum as u8);
             if (!(self.ac ^ val) & (self.ac ^ (sum as u8)) & 0x80) != 0 {
@@ -291,62 +508,69 @@ impl CPU {
     }
 
     fn sbc(&mut self, val: u8) {
-        let c = if self.sr.contains(StatusRegister::C) {
-            1_u16
+        // the carry flag is an inverted borrow
+        let borrow = if self.sr.contains(StatusRegister::C) {
+            0_u16
         } else {
-            0
+            1
         };
-        if self.bcd_enabled && self.sr.contains(StatusRegister::D) {
-            self.sr.remove(
-                StatusRegister::N | StatusRegister::V | StatusRegister::Z | StatusRegister::C,
-            );
-            let diff = (Wrapping(self.ac as u16) - Wrapping(val as u16) - Wrapping(c)).0;
-            let mut al = Wrapping(self.ac & 0x0F) - Wrapping(val & 0x0F) - Wrapping(c as u8);
-            if (al.0 as i8) < 0 {
-                al -= Wrapping(6);
-            }
-            let mut ah = Wrapping(self.ac >> 4)
-                - Wrapping(val >> 4)
-                - Wrapping(if (al.0 as i8) < 0 { 1 } else { 0 });
-            if (diff as u8) == 0 {
-                self.sr.insert(StatusRegister::Z);
-            } else if ah.0 & 0x80 != 0 {
-                self.sr.insert(StatusRegister::N);
+        let diff = (self.ac as u16)
+            .wrapping_sub(val as u16)
+            .wrapping_sub(borrow);
+        self.sr.remove(StatusRegister::C | StatusRegister::V);
+        // the flags are the same as in binary mode on the NMOS 6502
+        self.nz(diff as u8);
+        if ((self.ac ^ val) & (self.ac ^ (diff as u8)) & 0x80) != 0 {
+            self.sr.insert(StatusRegister::V);
+        }
+        if diff & 0xFF00 == 0 {
+            self.sr.insert(StatusRegister::C);
+        }
+        if self.bcd_enabled
+            && self.sr.contains(StatusRegister::D)
+            && self.model == CpuModel::Wdc65C02
+        {
+            // the 65C02 adjusts the binary difference instead, that only gives
+            // a different result for invalid BCD, and sets N and Z from it
+            let al = (self.ac & 0x0F) as i16 - (val & 0x0F) as i16 - borrow as i16;
+            let mut r = self.ac as i16 - val as i16 - borrow as i16;
+            if r < 0 {
+                r -= 0x60;
             }
-            if (!(self.ac ^ val) & (self.ac ^ (diff as u8)) & 0x80) != 0 {
-                self.sr.insert(StatusRegister::V);
+            if al < 0 {
+                r -= 0x06;
             }
-            if !(diff & 0xFF00) != 0 {
-                self.sr.insert(StatusRegister::C);
+            self.ac = r as u8;
+            self.nz(self.ac);
+        } else if self.bcd_enabled && self.sr.contains(StatusRegister::D) {
+            let mut al = (self.ac & 0x0F)
+                .wrapping_sub(val & 0x0F)
+                .wrapping_sub(borrow as u8);
+            if (al as i8) < 0 {
+                al = al.wrapping_sub(6);
             }
-            if ah.0 & 0x80 != 0 {
-                ah -= Wrapping(6);
+            let mut ah = (self.ac >> 4)
+                .wrapping_sub(val >> 4)
+                .wrapping_sub(if (al as i8) < 0 { 1 } else { 0 });
+            if ah & 0x80 != 0 {
+                ah = ah.wrapping_sub(6);
             }
-            self.ac = (ah.0 << 4) | (al.0 | 0xF);
+            self.ac = (ah << 4) | (al & 0x0F);
         } else {
-            let diff = (Wrapping(self.ac as u16) - Wrapping(val as u16) - Wrapping(c)).0;
-            self.sr.remove(StatusRegister::C | StatusRegister::V);
-            self.nz(diff as u8);
-            if (!(self.ac ^ val) & (self.ac ^ (diff as u8)) & 0x80) != 0 {
-                self.sr.insert(StatusRegister::V);
-            }
-            if !(diff & 0xFF00) != 0 {
-                self.sr.insert(StatusRegister::C);
-            }
-            self.ac = (diff & 0xFF) as u8;
+            self.ac = diff as u8;
         }
     }
 
     fn cmp(&mut self, r: u8, v: u8) {
         let diff = (Wrapping(r as u16) - Wrapping(v as u16)).0;
         self.nz(diff as u8);
-        self.sr.set(StatusRegister::C, (diff & 0xFF00) != 0);
+        self.sr.set(StatusRegister::C, (diff & 0xFF00) == 0);
     }
 
     fn sbx(&mut self, v: u8) {
         let x = (Wrapping((self.ac & self.x) as u16) - Wrapping(v as u16)).0;
         self.nz(x as u8);
-        self.sr.set(StatusRegister::C, (x & 0xFF00) != 0);
+        self.sr.set(StatusRegister::C, (x & 0xFF00) == 0);
         self.x = x as u8;
     }
 
@@ -405,10 +629,10 @@ impl CPU {
                 self.sr.insert(StatusRegister::V);
             }
             if (self.ac & 0xF) >= 5 {
-                a = ((a + 6) & 0xF) | (a & 0xF0);
+                a = (a.wrapping_add(6) & 0xF) | (a & 0xF0);
             }
             if (self.ac & 0xF0) >= 0x50 {
-                a += 0x60;
+                a = a.wrapping_add(0x60);
                 self.sr.insert(StatusRegister::C);
             }
             self.ac = a;
@@ -441,10 +665,482 @@ impl CPU {
 
 use codegen::codegen;
 codegen!();
+codegen!(variant = "65c02");
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::{StatusRegister, CPU};
+    use crate::cpu::bench::Bench;
+    use crate::cpu::model::CpuModel;
+    use crate::cpu::{StatusRegister, StepResult, CPU};
+
+    #[test]
+    fn stack_and_stz() {
+        let mut bench = Bench::new(
+            CpuModel::Wdc65C02,
+            &[
+                0xA2, 0x42, // LDX #$42
+                0xDA, // PHX
+                0x7A, // PLY
+                0xA9, 0xFF, // LDA #$FF
+                0x85, 0x10, // STA $10
+                0x64, 0x10, // STZ $10
+                0x3A, // DEC A
+            ],
+        );
+        bench.steps(7);
+        assert_eq!(bench.cpu.y, 0x42);
+        assert_eq!(bench.mem[0x10], 0x00);
+        assert_eq!(bench.cpu.ac, 0xFE);
+        assert_eq!(bench.cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn test_and_reset_bits() {
+        let mut bench = Bench::new(
+            CpuModel::Wdc65C02,
+            &[
+                0xA9, 0x0F, // LDA #$0F
+                0x04, 0x10, // TSB $10
+                0xA9, 0x03, // LDA #$03
+                0x14, 0x10, // TRB $10
+                0x89, 0x00, // BIT #$00
+            ],
+        );
+        bench.steps(2);
+        assert_eq!(bench.mem[0x10], 0x0F);
+        assert!(bench.cpu.sr.contains(StatusRegister::Z));
+        bench.steps(2);
+        assert_eq!(bench.mem[0x10], 0x0C);
+        assert!(!bench.cpu.sr.contains(StatusRegister::Z));
+        bench.cpu.sr.insert(StatusRegister::N);
+        bench.step();
+        assert!(bench.cpu.sr.contains(StatusRegister::Z | StatusRegister::N));
+    }
+
+    #[test]
+    fn zero_page_indirect() {
+        let mut bench = Bench::new(
+            CpuModel::Wdc65C02,
+            &[
+                0xB2, 0x20, // LDA ($20)
+                0xA9, 0x77, // LDA #$77
+                0x92, 0x22, // STA ($22)
+            ],
+        );
+        bench.mem[0x20..0x24].copy_from_slice(&[0x00, 0x03, 0x01, 0x03]);
+        bench.mem[0x0300] = 0x5A;
+        assert_eq!(bench.step(), 5);
+        assert_eq!(bench.cpu.ac, 0x5A);
+        bench.step();
+        assert_eq!(bench.step(), 5);
+        assert_eq!(bench.mem[0x0301], 0x77);
+    }
+
+    #[test]
+    fn memory_bits() {
+        let mut bench = Bench::new(
+            CpuModel::Wdc65C02,
+            &[
+                0x87, 0x11, // SMB0 $11
+                0x8F, 0x11, 0x02, // BBS0 $11, +2
+                0xA9, 0xEE, // LDA #$EE
+                0x07, 0x11, // RMB0 $11
+                0x8F, 0x11, 0x02, // BBS0 $11, +2
+            ],
+        );
+        bench.mem[0x11] = 0x80;
+        assert_eq!(bench.step(), 5);
+        assert_eq!(bench.mem[0x11], 0x81);
+        assert_eq!(bench.step(), 6);
+        assert_eq!(bench.cpu.pc, 0x0207);
+        bench.step();
+        assert_eq!(bench.mem[0x11], 0x80);
+        assert_eq!(bench.step(), 5);
+        assert_eq!(bench.cpu.pc, 0x020C);
+        assert_eq!(bench.cpu.ac, 0x00);
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // the pointer at $02FF has its high byte at $0300, the NMOS 6502 reads
+        // it from $0200 instead
+        let program = [0x6C, 0xFF, 0x02];
+        for (model, cycles, target) in [
+            (CpuModel::Nmos6502, 5, 0x6C00),
+            (CpuModel::Wdc65C02, 6, 0x0400),
+        ] {
+            let mut bench = Bench::new(model, &program);
+            bench.mem[0x0300] = 0x04;
+            assert_eq!(bench.step(), cycles);
+            assert_eq!(bench.cpu.pc, target);
+        }
+
+        let mut bench = Bench::new(CpuModel::Wdc65C02, &[0xA2, 0x02, 0x7C, 0xFE, 0x02]);
+        bench.mem[0x0300..0x0302].copy_from_slice(&[0x34, 0x12]);
+        bench.step();
+        assert_eq!(bench.step(), 6);
+        assert_eq!(bench.cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn decimal_flags() {
+        let program = [
+            0xF8, // SED
+            0xA9, 0x99, // LDA #$99
+            0x69, 0x01, // ADC #$01
+            0xE9, 0x01, // SBC #$01
+        ];
+        let mut bench = Bench::new(CpuModel::Wdc65C02, &program);
+        bench.steps(2);
+        // one extra cycle to set N and Z from the decimal result
+        assert_eq!(bench.step(), 3);
+        assert_eq!(bench.cpu.ac, 0x00);
+        assert!(bench.cpu.sr.contains(StatusRegister::Z | StatusRegister::C));
+        assert!(!bench.cpu.sr.contains(StatusRegister::N));
+        assert_eq!(bench.step(), 3);
+        assert_eq!(bench.cpu.ac, 0x99);
+        assert!(bench.cpu.sr.contains(StatusRegister::N));
+        assert!(!bench
+            .cpu
+            .sr
+            .intersects(StatusRegister::Z | StatusRegister::C));
+    }
+
+    #[test]
+    fn brk_clears_decimal() {
+        for (model, decimal) in [(CpuModel::Nmos6502, true), (CpuModel::Wdc65C02, false)] {
+            let mut bench = Bench::new(model, &[0xF8, 0x00]);
+            bench.mem[0xFFFF] = 0x03;
+            bench.steps(2);
+            assert_eq!(bench.cpu.pc, 0x0300);
+            assert_eq!(bench.cpu.sr.contains(StatusRegister::D), decimal);
+        }
+    }
+
+    #[test]
+    fn wait_for_interrupt() {
+        let mut bench = Bench::new(CpuModel::Wdc65C02, &[0xCB, 0xE8]);
+        for _ in 0..20 {
+            bench.tick();
+            assert!(!bench.pins.sync);
+        }
+        // interrupts are disabled after the reset, so WAI just continues
+        bench.pins.irq = true;
+        bench.step();
+        bench.step();
+        assert_eq!(bench.cpu.x, 1);
+    }
+
+    #[test]
+    fn step_while_waiting() {
+        // WAI, INX
+        let Bench {
+            mut cpu, mut mem, ..
+        } = Bench::at(CpuModel::Wdc65C02, 0x0200, &[0xCB, 0xE8]);
+        cpu.step_instruction(&mut *mem);
+        // a cycle at a time, so the caller gets to pull a line
+        let start = cpu.cycles();
+        for _ in 0..10 {
+            let wai = cpu.step_instruction(&mut *mem);
+            assert_eq!((wai.address, wai.opcode), (0x0200, 0xCB));
+            assert!(cpu.is_waiting());
+        }
+        // the first one fetched the opcode as well
+        assert_eq!(cpu.cycles() - start, 11);
+        cpu.pins.irq = true;
+        cpu.step_instruction(&mut *mem);
+        assert!(!cpu.is_waiting());
+        assert_eq!(cpu.step_instruction(&mut *mem).opcode, 0xE8);
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn decimal_mode() {
+        // SED; LDA #$09; ADC #$01
+        let program = [0xF8, 0xA9, 0x09, 0x69, 0x01];
+        for (model, result) in [
+            (CpuModel::Nmos6502, 0x10),
+            (CpuModel::Mos6510, 0x10),
+            (CpuModel::Ricoh2A03, 0x0A),
+        ] {
+            let mut bench = Bench::new(model, &program);
+            bench.steps(3);
+            assert_eq!(bench.cpu.ac, result, "{:?}", model);
+            assert!(bench.cpu.sr.contains(StatusRegister::D));
+        }
+    }
+
+    #[test]
+    fn io_port() {
+        let mut bench = Bench::new(
+            CpuModel::Mos6510,
+            &[
+                0xA9, 0x0F, // LDA #$0F
+                0x85, 0x00, // STA $00
+                0xA9, 0x37, // LDA #$37
+                0x85, 0x01, // STA $01
+                0xA5, 0x01, // LDA $01
+                0xA6, 0x00, // LDX $00
+            ],
+        );
+        bench.cpu.io_port_mut().unwrap().input = 0xA0;
+        bench.steps(6);
+        let port = bench.cpu.io_port().unwrap();
+        assert_eq!(port.direction, 0x0F);
+        assert_eq!(port.pins(), 0xA7);
+        assert_eq!(bench.cpu.ac, 0xA7);
+        assert_eq!(bench.cpu.x, 0x0F);
+        // the RAM below the port is written as well
+        assert_eq!(bench.mem[0x01], 0x37);
+        assert!(CPU::new().io_port().is_none());
+    }
+
+    #[test]
+    fn unstable_opcodes() {
+        // LDX #$FF; LDA #$00; ANE #$FF
+        let program = [0xA2, 0xFF, 0xA9, 0x00, 0x8B, 0xFF];
+        for (model, result) in [
+            (CpuModel::Nmos6502, 0xEE),
+            (CpuModel::Mos6510, 0xEF),
+            (CpuModel::Ricoh2A03, 0xFF),
+        ] {
+            let mut bench = Bench::new(model, &program);
+            bench.steps(3);
+            assert_eq!(bench.cpu.ac, result, "{:?}", model);
+        }
+    }
+
+    /// Expected accumulator, N, V, Z and C after an ADC or SBC.
+    type Flags = (u8, bool, bool, bool, bool);
+
+    fn run_arithmetic(cpu: &mut CPU, sbc: bool, a: u8, b: u8, c: bool) -> Flags {
+        cpu.ac = a;
+        cpu.sr.set(StatusRegister::C, c);
+        if sbc {
+            cpu.sbc(b);
+        } else {
+            cpu.adc(b);
+        }
+        (
+            cpu.ac,
+            cpu.sr.contains(StatusRegister::N),
+            cpu.sr.contains(StatusRegister::V),
+            cpu.sr.contains(StatusRegister::Z),
+            cpu.sr.contains(StatusRegister::C),
+        )
+    }
+
+    fn binary_reference(sbc: bool, a: u8, b: u8, c: bool) -> Flags {
+        let b = if sbc { !b } else { b };
+        let sum = a as u16 + b as u16 + c as u16;
+        let r = sum as u8;
+        let v = (a ^ r) & (b ^ r) & 0x80 != 0;
+        (r, r & 0x80 != 0, v, r == 0, sum > 0xFF)
+    }
+
+    /// The NMOS decimal mode as described in appendix B of Bruce Clark's
+    /// "Decimal Mode" tutorial, including the results for invalid BCD.
+    fn decimal_reference(sbc: bool, a: u8, b: u8, c: bool) -> Flags {
+        let (ai, bi, ci) = (a as i16, b as i16, c as i16);
+        let (binary, n, v, z, carry) = binary_reference(sbc, a, b, c);
+        if sbc {
+            let mut al = (ai & 0x0F) - (bi & 0x0F) + ci - 1;
+            if al < 0 {
+                al = ((al - 0x06) & 0x0F) - 0x10;
+            }
+            let mut r = (ai & 0xF0) - (bi & 0xF0) + al;
+            if r < 0 {
+                r -= 0x60;
+            }
+            return (r as u8, n, v, z, carry);
+        }
+        let mut al = (ai & 0x0F) + (bi & 0x0F) + ci;
+        if al >= 0x0A {
+            al = ((al + 0x06) & 0x0F) + 0x10;
+        }
+        let signed = ((ai & 0xF0) as u8 as i8) as i16 + ((bi & 0xF0) as u8 as i8) as i16 + al;
+        let mut r = (ai & 0xF0) + (bi & 0xF0) + al;
+        if r >= 0xA0 {
+            r += 0x60;
+        }
+        (
+            r as u8,
+            signed & 0x80 != 0,
+            !(-128..=127).contains(&signed),
+            binary == 0,
+            r >= 0x100,
+        )
+    }
+
+    #[test]
+    fn binary_arithmetic() {
+        let mut cpu = CPU::new();
+        for sbc in [false, true] {
+            for a in 0..=255 {
+                for b in 0..=255 {
+                    for c in [false, true] {
+                        assert_eq!(
+                            run_arithmetic(&mut cpu, sbc, a, b, c),
+                            binary_reference(sbc, a, b, c),
+                            "sbc={} a={:#04X} b={:#04X} c={}",
+                            sbc,
+                            a,
+                            b,
+                            c
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_arithmetic() {
+        let mut cpu = CPU::new();
+        cpu.sr.insert(StatusRegister::D);
+        for sbc in [false, true] {
+            for a in 0..=255 {
+                for b in 0..=255 {
+                    for c in [false, true] {
+                        assert_eq!(
+                            run_arithmetic(&mut cpu, sbc, a, b, c),
+                            decimal_reference(sbc, a, b, c),
+                            "sbc={} a={:#04X} b={:#04X} c={}",
+                            sbc,
+                            a,
+                            b,
+                            c
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// The 65C02 decimal mode from the same tutorial, N and Z are valid and
+    /// SBC gives other results for invalid BCD.
+    fn decimal_reference_65c02(sbc: bool, a: u8, b: u8, c: bool) -> Flags {
+        let (mut r, _, v, _, carry) = decimal_reference(sbc, a, b, c);
+        if sbc {
+            let (ai, bi, ci) = (a as i16, b as i16, c as i16);
+            let al = (ai & 0x0F) - (bi & 0x0F) + ci - 1;
+            let mut diff = ai - bi + ci - 1;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            if al < 0 {
+                diff -= 0x06;
+            }
+            r = diff as u8;
+        }
+        (r, r & 0x80 != 0, v, r == 0, carry)
+    }
+
+    #[test]
+    fn decimal_arithmetic_65c02() {
+        let mut cpu = CPU::with_model(CpuModel::Wdc65C02);
+        cpu.sr.insert(StatusRegister::D);
+        for sbc in [false, true] {
+            for a in 0..=255 {
+                for b in 0..=255 {
+                    for c in [false, true] {
+                        assert_eq!(
+                            run_arithmetic(&mut cpu, sbc, a, b, c),
+                            decimal_reference_65c02(sbc, a, b, c),
+                            "sbc={} a={:#04X} b={:#04X} c={}",
+                            sbc,
+                            a,
+                            b,
+                            c
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let mut cpu = CPU::new();
+        cpu.cmp(0x10, 0x0F);
+        assert_eq!(cpu.sr, StatusRegister::C);
+        cpu.cmp(0x10, 0x10);
+        assert_eq!(cpu.sr, StatusRegister::C | StatusRegister::Z);
+        cpu.cmp(0x10, 0x11);
+        assert_eq!(cpu.sr, StatusRegister::N);
+    }
+
+    #[test]
+    fn undocumented_opcodes_are_nops() {
+        let mut bench = Bench::new(CpuModel::Wdc65C02, &[0x03, 0x02, 0xFF, 0xDC, 0x00, 0x10]);
+        assert_eq!(bench.step(), 1);
+        assert_eq!(bench.step(), 2);
+        assert_eq!(bench.step(), 4);
+        assert_eq!(bench.cpu.pc, 0x0206);
+        assert_eq!((bench.cpu.ac, bench.cpu.x, bench.cpu.y), (0, 0, 0));
+    }
+
+    #[test]
+    fn counters() {
+        // LDA #$01, STA $10, NOP
+        let mut bench = Bench::new(CpuModel::Nmos6502, &[0xA9, 0x01, 0x85, 0x10, 0xEA]);
+        // the reset sequence isn't an instruction, its last cycle is the
+        // first fetch
+        assert_eq!(bench.cpu.cycles(), 7);
+        assert_eq!(bench.cpu.instruction_start(), 6);
+        assert_eq!(bench.cpu.instructions(), 0);
+        bench.steps(2);
+        assert_eq!(bench.cpu.cycles(), 12);
+        assert_eq!(bench.cpu.instruction_start(), 11);
+        assert_eq!(bench.cpu.instructions(), 2);
+        bench.tick();
+        assert_eq!(bench.cpu.cycles() - bench.cpu.instruction_start(), 2);
+        assert_eq!(bench.cpu.instructions(), 3);
+    }
+
+    #[test]
+    fn step_instruction() {
+        // LDX #$03, DEX, BNE -3, JAM
+        let program = [0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x02];
+        let Bench {
+            mut cpu, mut mem, ..
+        } = Bench::at(CpuModel::Nmos6502, 0x0200, &program);
+        let reset = cpu.step_instruction(&mut *mem);
+        assert!(reset.interrupt);
+        assert_eq!(cpu.pc, 0x0200);
+
+        let ldx = cpu.step_instruction(&mut *mem);
+        assert_eq!(
+            ldx,
+            StepResult {
+                address: 0x0200,
+                opcode: 0xA2,
+                cycles: 2,
+                interrupt: false
+            }
+        );
+        assert_eq!(cpu.x, 3);
+        assert_eq!(cpu.step_instruction(&mut *mem).opcode, 0xCA);
+        let bne = cpu.step_instruction(&mut *mem);
+        assert_eq!((bne.address, bne.cycles), (0x0203, 3));
+
+        // finishing an instruction that has already started
+        cpu.cycle(&mut *mem);
+        let dex = cpu.step_instruction(&mut *mem);
+        assert_eq!((dex.address, dex.opcode, dex.cycles), (0x0202, 0xCA, 2));
+
+        let cycles = cpu.run_until(&mut *mem, |cpu| cpu.x == 0 && cpu.pc == 0x0205);
+        assert_eq!(cycles, 3 + 2 + 2);
+        assert!(!cpu.is_halted());
+        // stops at the JAM instead of hanging
+        let before = cpu.instructions();
+        cpu.run_until(&mut *mem, |_| false);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.instructions(), before + 1);
+
+        cpu.run_cycles(&mut *mem, 10);
+        assert!(cpu.is_halted());
+    }
 
     #[test]
     fn memes() {
@@ -460,4 +1156,42 @@ mod tests {
         sr.toggle(StatusRegister::N);
         assert_eq!(c.sr, sr);
     }
+
+    #[test]
+    fn indirect_pointers() {
+        let mut bench = Bench::new(
+            CpuModel::Nmos6502,
+            &[
+                0xA2, 0x02, // LDX #$02
+                0xA0, 0x01, // LDY #$01
+                0xA1, 0x20, // LDA ($20,X)
+                0xAA, // TAX
+                0xB1, 0x30, // LDA ($30),Y
+            ],
+        );
+        // the pointers come from the zero page, not from the operand address
+        bench.mem[0x22..0x24].copy_from_slice(&[0x00, 0x04]);
+        bench.mem[0x30..0x32].copy_from_slice(&[0xFF, 0x04]);
+        bench.mem[0x0400] = 0x11;
+        bench.mem[0x0500] = 0x22;
+        bench.steps(3);
+        assert_eq!(bench.cpu.ac, 0x11);
+        // one more cycle for crossing the page
+        bench.step();
+        assert_eq!(bench.step(), 6);
+        assert_eq!(bench.cpu.ac, 0x22);
+    }
+
+    #[test]
+    fn shift_right_accumulator() {
+        // LDA #$81; LSR A
+        let mut bench = Bench::new(CpuModel::Nmos6502, &[0xA9, 0x81, 0x4A]);
+        bench.steps(2);
+        assert_eq!(bench.cpu.ac, 0x40);
+        assert!(bench.cpu.sr.contains(StatusRegister::C));
+        assert!(!bench
+            .cpu
+            .sr
+            .intersects(StatusRegister::N | StatusRegister::Z));
+    }
 }
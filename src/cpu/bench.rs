@@ -0,0 +1,62 @@
+//! A CPU with 64K of RAM for the tests of the CPU modules.
+
+use crate::cpu::model::CpuModel;
+use crate::cpu::{Pins, ReadWrite, CPU};
+use crate::device::Device;
+
+pub(crate) struct Bench {
+    pub(crate) cpu: CPU,
+    pub(crate) pins: Pins,
+    pub(crate) mem: Box<[u8; 0x10000]>,
+}
+
+impl Bench {
+    /// The program is placed at `origin` and the reset vector points to it,
+    /// the CPU hasn't run yet.
+    pub(crate) fn at(model: CpuModel, origin: u16, program: &[u8]) -> Self {
+        let mut mem = Box::new([0_u8; 0x10000]);
+        mem[0xFFFC..0xFFFE].copy_from_slice(&origin.to_le_bytes());
+        let start = usize::from(origin);
+        mem[start..start + program.len()].copy_from_slice(program);
+        let cpu = CPU::with_model(model);
+        Bench {
+            pins: cpu.pins,
+            cpu,
+            mem,
+        }
+    }
+
+    /// The program is placed at $0200 and the reset sequence has already run.
+    pub(crate) fn new(model: CpuModel, program: &[u8]) -> Self {
+        let mut bench = Bench::at(model, 0x0200, program);
+        bench.step();
+        bench
+    }
+
+    pub(crate) fn tick(&mut self) {
+        self.pins = self.cpu.tick(self.pins);
+        if self.pins.rw == ReadWrite::Read {
+            self.pins.data = self.mem.read(self.pins.address).unwrap();
+        } else {
+            self.mem.write(self.pins.address, self.pins.data).unwrap();
+        }
+    }
+
+    /// Runs one instruction and returns the number of cycles it took.
+    pub(crate) fn step(&mut self) -> u16 {
+        let mut cycles = 0;
+        loop {
+            self.tick();
+            cycles += 1;
+            if self.pins.sync {
+                return cycles;
+            }
+        }
+    }
+
+    pub(crate) fn steps(&mut self, n: usize) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+}
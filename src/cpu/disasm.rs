@@ -0,0 +1,387 @@
+use crate::cpu::model::CpuModel;
+use crate::cpu::opcodes::{AddressingMode, Instruction};
+use crate::device::Device;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+/// Maps addresses to label names, so they can be shown instead of raw
+/// addresses.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_owned());
+    }
+
+    pub fn get(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(a, _)| *a)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(a, n)| (*a, n.as_str()))
+    }
+
+    /// Parses a VICE label file, as written by `ld65 -Ln`. Lines look like
+    /// `al 00E00C .readchar`, anything that doesn't is skipped.
+    pub fn parse_vice(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            if parts.next() != Some("al") {
+                continue;
+            }
+            let address = parts.next().and_then(|a| u32::from_str_radix(a, 16).ok());
+            let name = parts.next().map(|n| n.trim_start_matches('.'));
+            if let (Some(address), Some(name)) = (address, name) {
+                table.insert(address as u16, name);
+            }
+        }
+        table
+    }
+
+    /// Writes the table in the VICE label format read by `parse_vice`.
+    pub fn to_vice(&self) -> String {
+        self.iter()
+            .map(|(address, name)| format!("al {:06X} .{}\n", address, name))
+            .collect()
+    }
+}
+
+/// A single disassembled instruction.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Option<Instruction>,
+    pub documented: bool,
+    pub text: String,
+}
+
+impl Disassembly {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:<8}  {}", self.address, bytes, self.text)?;
+        if !self.documented {
+            write!(f, " ; undocumented")?;
+        }
+        Ok(())
+    }
+}
+
+fn name_or_address(value: u16, zero_page: bool, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|s| s.get(value)) {
+        Some(name) => name.to_owned(),
+        None if zero_page => format!("${:02X}", value),
+        None => format!("${:04X}", value),
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which is located at
+/// `address`. If `bytes` ends before the operand does, the available bytes
+/// are emitted as `.byte` data instead.
+pub fn decode(bytes: &[u8], address: u16, symbols: Option<&SymbolTable>) -> Disassembly {
+    decode_for(CpuModel::Nmos6502, bytes, address, symbols)
+}
+
+/// [`decode`] with the instruction set of `model`.
+pub fn decode_for(
+    model: CpuModel,
+    bytes: &[u8],
+    address: u16,
+    symbols: Option<&SymbolTable>,
+) -> Disassembly {
+    let op = match bytes.first() {
+        Some(op) => *op,
+        None => {
+            return Disassembly {
+                address,
+                bytes: vec![],
+                instruction: None,
+                documented: true,
+                text: String::new(),
+            }
+        }
+    };
+    let instruction = Instruction::for_model(model, op);
+    let len = instruction.length as usize;
+    if bytes.len() < len {
+        return Disassembly {
+            address,
+            bytes: bytes.to_vec(),
+            instruction: None,
+            documented: true,
+            text: format!(
+                ".byte {}",
+                bytes
+                    .iter()
+                    .map(|b| format!("${:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+    }
+    let lo = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from(lo) | (u16::from(bytes.get(2).copied().unwrap_or(0)) << 8);
+    // ca65 picks zero page addressing for any value below $100, so absolute
+    // operands in the zero page need an explicit size override
+    let abs = |value: u16| {
+        let text = name_or_address(value, false, symbols);
+        if value < 0x100 {
+            format!("a:{}", text)
+        } else {
+            text
+        }
+    };
+    let operand = match instruction.mode {
+        AddressingMode::Acc => "A".to_owned(),
+        AddressingMode::Impl => String::new(),
+        AddressingMode::Imm => format!("#${:02X}", lo),
+        AddressingMode::Zpg => name_or_address(lo.into(), true, symbols),
+        AddressingMode::ZpgX => format!("{},X", name_or_address(lo.into(), true, symbols)),
+        AddressingMode::ZpgY => format!("{},Y", name_or_address(lo.into(), true, symbols)),
+        AddressingMode::Abs => abs(word),
+        AddressingMode::AbsX => format!("{},X", abs(word)),
+        AddressingMode::AbsY => format!("{},Y", abs(word)),
+        AddressingMode::Ind => format!("({})", name_or_address(word, false, symbols)),
+        AddressingMode::XInd => format!("({},X)", name_or_address(lo.into(), true, symbols)),
+        AddressingMode::IndY => format!("({}),Y", name_or_address(lo.into(), true, symbols)),
+        AddressingMode::Rel => {
+            let target = address.wrapping_add(2).wrapping_add(lo as i8 as i16 as u16);
+            name_or_address(target, false, symbols)
+        }
+        AddressingMode::ZpgInd => format!("({})", name_or_address(lo.into(), true, symbols)),
+        AddressingMode::AbsXInd => format!("({},X)", name_or_address(word, false, symbols)),
+        AddressingMode::ZpgRel => {
+            let offset = bytes[2] as i8 as i16 as u16;
+            let target = address.wrapping_add(3).wrapping_add(offset);
+            format!(
+                "{},{}",
+                name_or_address(lo.into(), true, symbols),
+                name_or_address(target, false, symbols)
+            )
+        }
+    };
+    let mnemonic = instruction.name(op);
+    Disassembly {
+        address,
+        bytes: bytes[..len].to_vec(),
+        instruction: Some(instruction),
+        documented: Instruction::is_documented_on(model, op),
+        text: if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operand)
+        },
+    }
+}
+
+/// Disassembles `bytes` linearly, starting at `origin`.
+pub fn disassemble(bytes: &[u8], origin: u16, symbols: Option<&SymbolTable>) -> Vec<Disassembly> {
+    disassemble_for(CpuModel::Nmos6502, bytes, origin, symbols)
+}
+
+/// [`disassemble`] with the instruction set of `model`.
+pub fn disassemble_for(
+    model: CpuModel,
+    bytes: &[u8],
+    origin: u16,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Disassembly> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let line = decode_for(
+            model,
+            &bytes[offset..],
+            origin.wrapping_add(offset as u16),
+            symbols,
+        );
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Disassembles the instructions starting inside `range` of a live device.
+/// The bytes are peeked, so devices with side effects on reads aren't
+/// disturbed. The last instruction may read past the end of the range to get
+/// its operands; bytes that can't be peeked read as zero.
+pub fn disassemble_device(
+    device: &dyn Device,
+    range: RangeInclusive<u16>,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Disassembly> {
+    disassemble_device_for(CpuModel::Nmos6502, device, range, symbols)
+}
+
+/// [`disassemble_device`] with the instruction set of `model`.
+pub fn disassemble_device_for(
+    model: CpuModel,
+    device: &dyn Device,
+    range: RangeInclusive<u16>,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Disassembly> {
+    let start = *range.start() as usize;
+    let end = *range.end() as usize;
+    let bytes: Vec<u8> = (start..=(end + 2).min(0xFFFF))
+        .map(|a| device.peek(a as u16).unwrap_or(0))
+        .collect();
+    let mut lines = vec![];
+    let mut offset = 0;
+    while start + offset <= end {
+        let line = decode_for(model, &bytes[offset..], (start + offset) as u16, symbols);
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Formats disassembled lines as a listing, with a label line in front of
+/// every instruction that has a symbol.
+pub fn listing(lines: &[Disassembly], symbols: Option<&SymbolTable>) -> String {
+    let mut out = String::new();
+    for line in lines {
+        if let Some(name) = symbols.and_then(|s| s.get(line.address)) {
+            out.push_str(name);
+            out.push_str(":\n");
+        }
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::disasm::{
+        decode, decode_for, disassemble, disassemble_device, disassemble_device_for,
+        disassemble_for, listing, Disassembly, SymbolTable,
+    };
+    use crate::cpu::model::CpuModel;
+    use crate::device::device_map::DeviceMap;
+    use crate::device::Rom;
+
+    // `readchar` from code/kernel/funcs.s, placed at $E00C
+    const READCHAR: [u8; 13] = [
+        0xA5, 0x10, 0xF0, 0xFC, 0xC9, 0xE0, 0xF0, 0x01, 0x60, 0xA5, 0x10, 0x09, 0x80,
+    ];
+
+    #[test]
+    fn addressing_modes() {
+        let t = |b: &[u8]| decode(b, 0x0200, None).text;
+        assert_eq!(t(&[0xBD, 0x00, 0x05]), "LDA $0500,X");
+        assert_eq!(t(&[0xA9, 0x48]), "LDA #$48");
+        assert_eq!(t(&[0xB6, 0x10]), "LDX $10,Y");
+        assert_eq!(t(&[0x6C, 0x00, 0x80]), "JMP ($8000)");
+        assert_eq!(t(&[0xA1, 0x20]), "LDA ($20,X)");
+        assert_eq!(t(&[0x91, 0x20]), "STA ($20),Y");
+        assert_eq!(t(&[0x0A]), "ASL A");
+        assert_eq!(t(&[0x60]), "RTS");
+        assert_eq!(t(&[0xAD, 0x10, 0x00]), "LDA a:$0010");
+        assert_eq!(t(&[0xD0, 0x80]), "BNE $0182");
+        assert_eq!(t(&[0xAD, 0x10]), ".byte $AD, $10");
+    }
+
+    #[test]
+    fn undocumented() {
+        let l = decode(&[0x0B, 0x12], 0, None);
+        assert_eq!(l.text, "ANC #$12");
+        assert!(!l.documented);
+        assert_eq!(l.to_string(), "0000  0B 12     ANC #$12 ; undocumented");
+        assert!(!decode(&[0x04, 0x00], 0, None).documented);
+        assert!(decode(&[0xEA], 0, None).documented);
+    }
+
+    #[test]
+    fn wdc65c02() {
+        let t = |b: &[u8]| decode_for(CpuModel::Wdc65C02, b, 0x0200, None).to_string();
+        assert_eq!(t(&[0xB2, 0x20]), "0200  B2 20     LDA ($20)");
+        assert_eq!(t(&[0x7C, 0x00, 0x80]), "0200  7C 00 80  JMP ($8000,X)");
+        assert_eq!(t(&[0x8F, 0x10, 0xFD]), "0200  8F 10 FD  BBS0 $10,$0200");
+        assert_eq!(t(&[0x80, 0x02]), "0200  80 02     BRA $0204");
+        assert_eq!(
+            t(&[0x5C, 0x00, 0x00]),
+            "0200  5C 00 00  NOP a:$0000 ; undocumented"
+        );
+        assert_eq!(decode(&[0xB2, 0x20], 0, None).text, "JAM");
+
+        let program = [0xDA, 0x64, 0x10, 0x7A];
+        let text = |lines: Vec<Disassembly>| -> Vec<String> {
+            lines.into_iter().map(|l| l.text).collect()
+        };
+        let lines = disassemble_for(CpuModel::Wdc65C02, &program, 0x0200, None);
+        assert_eq!(text(lines), ["PHX", "STZ $10", "PLY"]);
+        let rom = Rom::from_vec(0x0200, program.to_vec());
+        let lines = disassemble_device_for(CpuModel::Wdc65C02, &rom, 0x0200..=0x0203, None);
+        assert_eq!(text(lines), ["PHX", "STZ $10", "PLY"]);
+    }
+
+    #[test]
+    fn symbols() {
+        let symbols = SymbolTable::parse_vice(
+            "al 00E00C .readchar\nal 00E015 .readchar_up\nal 000010 .keyboard\n",
+        );
+        let lines = disassemble(&READCHAR, 0xE00C, Some(&symbols));
+        let text: Vec<_> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(
+            text,
+            [
+                "LDA keyboard",
+                "BEQ readchar",
+                "CMP #$E0",
+                "BEQ readchar_up",
+                "RTS",
+                "LDA keyboard",
+                "ORA #$80"
+            ]
+        );
+        assert!(listing(&lines, Some(&symbols))
+            .starts_with("readchar:\nE00C  A5 10     LDA keyboard\n"));
+        assert_eq!(symbols.address_of("readchar_up"), Some(0xE015));
+    }
+
+    #[test]
+    fn device_range() {
+        let rom = Rom::from_vec(0xE00C, READCHAR.to_vec());
+        let lines = disassemble_device(&rom, 0xE00C..=0xE014, None);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[1].text, "BEQ $E00C");
+        assert_eq!(lines[4].text, "RTS");
+
+        // reading past the ROM would be an unmapped read fault
+        let mut bus = DeviceMap::new();
+        bus.map("rom", 0xE00C..=0xE018, rom).unwrap();
+        assert_eq!(disassemble_device(&bus, 0xE014..=0xE018, None).len(), 3);
+        assert!(bus.take_faults().is_empty());
+    }
+}
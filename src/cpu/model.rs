@@ -0,0 +1,124 @@
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The chip a [`CPU`](crate::cpu::CPU) emulates, chosen when it's
+/// constructed.
+///
+/// The NMOS models share their microcode, they only differ in the decimal
+/// mode, the I/O port and the [`magic_constant`](CpuModel::magic_constant) of
+/// `ANE` and `LXA`. The other unstable opcodes (`SHA`, `SHX`, `SHY` and `TAS`)
+/// behave the same on all of them, and none of them drops the high byte of
+/// the target address when the indexing crosses a page like the real chips
+/// do.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum CpuModel {
+    /// The original NMOS 6502 with decimal mode and the undocumented opcodes.
+    #[default]
+    Nmos6502,
+    /// The NES CPU, an NMOS 6502 with the decimal mode cut out. The `D` flag
+    /// can still be set, but `ADC` and `SBC` ignore it.
+    Ricoh2A03,
+    /// The C64 CPU, an NMOS 6502 with an 8 bit I/O port, its data direction
+    /// register is at $0000 and the data register at $0001.
+    Mos6510,
+    /// The WDC 65C02 with the Rockwell bit instructions. All undocumented
+    /// opcodes are NOPs, `JMP ($xxFF)` doesn't wrap within the page and
+    /// `ADC` and `SBC` take a cycle longer in decimal mode to set N and Z from
+    /// the result.
+    Wdc65C02,
+}
+
+impl CpuModel {
+    pub fn has_decimal_mode(self) -> bool {
+        self != CpuModel::Ricoh2A03
+    }
+
+    pub fn has_io_port(self) -> bool {
+        self == CpuModel::Mos6510
+    }
+
+    /// The unstable `ANE` ($8B) and `LXA` ($AB) opcodes OR the accumulator
+    /// with a constant that depends on the chip (and on temperature, but we
+    /// ignore that). These are the values the common test suites expect.
+    pub fn magic_constant(self) -> u8 {
+        match self {
+            CpuModel::Nmos6502 => 0xEE,
+            CpuModel::Ricoh2A03 => 0xFF,
+            CpuModel::Mos6510 => 0xEF,
+            CpuModel::Wdc65C02 => 0x00,
+        }
+    }
+}
+
+/// The on-chip I/O port of the 6510.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IoPort {
+    /// set bits are outputs
+    pub direction: u8,
+    pub output: u8,
+    /// the level of the pins that are configured as inputs
+    pub input: u8,
+}
+
+impl Default for IoPort {
+    fn default() -> Self {
+        // all pins are inputs after a reset, and pulled up
+        Self {
+            direction: 0,
+            output: 0,
+            input: 0xFF,
+        }
+    }
+}
+
+impl IoPort {
+    /// The level of every pin of the port.
+    pub fn pins(&self) -> u8 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+
+    pub(crate) fn read(&self, address: u16) -> u8 {
+        if address == 0 {
+            self.direction
+        } else {
+            self.pins()
+        }
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        if address == 0 {
+            self.direction = value;
+        } else {
+            self.output = value;
+        }
+    }
+}
+
+#[derive(Debug, Error, Eq, PartialEq, Clone)]
+#[error("unknown CPU model `{0}`, expected 6502, 2a03, 6510 or 65c02")]
+pub struct UnknownModel(pub String);
+
+impl FromStr for CpuModel {
+    type Err = UnknownModel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "6502" | "nmos" | "nmos6502" => Ok(CpuModel::Nmos6502),
+            "2a03" | "ricoh2a03" => Ok(CpuModel::Ricoh2A03),
+            "6510" | "mos6510" => Ok(CpuModel::Mos6510),
+            "65c02" | "wdc65c02" => Ok(CpuModel::Wdc65C02),
+            _ => Err(UnknownModel(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for CpuModel {
+    type Error = UnknownModel;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
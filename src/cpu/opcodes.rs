@@ -1,7 +1,8 @@
+use crate::cpu::model::CpuModel;
 use crate::cpu::opcodes::AddressingMode::*;
 use crate::cpu::opcodes::Opcode::*;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum AddressingMode {
     Acc,
     Abs,
@@ -16,9 +17,15 @@ pub enum AddressingMode {
     Zpg,
     ZpgX,
     ZpgY,
+    /// `(zp)`, 65C02 only
+    ZpgInd,
+    /// `(abs,X)` of the 65C02 `JMP`
+    AbsXInd,
+    /// `zp,rel` of the 65C02 `BBR` and `BBS`
+    ZpgRel,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Opcode {
     // add with carry
     ADC,
@@ -177,25 +184,810 @@ pub enum Opcode {
     USBC,
     // These instructions freeze the CPU.
     JAM,
+    //The 65C02 additions
+    // branch always
+    BRA,
+    // push X
+    PHX,
+    // push Y
+    PHY,
+    // pull X
+    PLX,
+    // pull Y
+    PLY,
+    // store zero
+    STZ,
+    // test and reset bits
+    TRB,
+    // test and set bits
+    TSB,
+    // reset memory bit, the bit number is bits 4-6 of the opcode byte
+    RMB,
+    // set memory bit
+    SMB,
+    // branch on bit reset
+    BBR,
+    // branch on bit set
+    BBS,
+    // wait for interrupt
+    WAI,
+    // stop the clock until a reset
+    STP,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+impl AddressingMode {
+    /// Number of bytes an instruction in this mode occupies, opcode included.
+    pub const fn length(self) -> u16 {
+        match self {
+            Acc | Impl => 1,
+            Imm | XInd | IndY | Rel | Zpg | ZpgX | ZpgY | ZpgInd => 2,
+            Abs | AbsX | AbsY | Ind | AbsXInd | ZpgRel => 3,
+        }
+    }
+}
+
+impl Opcode {
+    /// Whether this opcode is one of the undocumented NMOS instructions.
+    pub fn is_illegal(self) -> bool {
+        matches!(
+            self,
+            ALR | ANC
+                | ANC2
+                | ANE
+                | ARR
+                | DCP
+                | ISC
+                | LAS
+                | LAX
+                | LXA
+                | RLA
+                | RRA
+                | SAX
+                | SBX
+                | SHA
+                | SHX
+                | SHY
+                | SLO
+                | SRE
+                | TAS
+                | USBC
+                | JAM
+        )
+    }
+
+    /// The assembler mnemonic, duplicate encodings like `ANC2` and `USBC`
+    /// share the name of the instruction they behave like.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ADC => "ADC",
+            AND => "AND",
+            ASL => "ASL",
+            BCC => "BCC",
+            BCS => "BCS",
+            BEQ => "BEQ",
+            BIT => "BIT",
+            BMI => "BMI",
+            BNE => "BNE",
+            BPL => "BPL",
+            BRK => "BRK",
+            BVC => "BVC",
+            BVS => "BVS",
+            CLC => "CLC",
+            CLD => "CLD",
+            CLI => "CLI",
+            CLV => "CLV",
+            CMP => "CMP",
+            CPX => "CPX",
+            CPY => "CPY",
+            DEC => "DEC",
+            DEX => "DEX",
+            DEY => "DEY",
+            EOR => "EOR",
+            INC => "INC",
+            INX => "INX",
+            INY => "INY",
+            JMP => "JMP",
+            JSR => "JSR",
+            LDA => "LDA",
+            LDX => "LDX",
+            LDY => "LDY",
+            LSR => "LSR",
+            NOP => "NOP",
+            ORA => "ORA",
+            PHA => "PHA",
+            PHP => "PHP",
+            PLA => "PLA",
+            PLP => "PLP",
+            ROL => "ROL",
+            ROR => "ROR",
+            RTI => "RTI",
+            RTS => "RTS",
+            SBC => "SBC",
+            SEC => "SEC",
+            SED => "SED",
+            SEI => "SEI",
+            STA => "STA",
+            STX => "STX",
+            STY => "STY",
+            TAX => "TAX",
+            TAY => "TAY",
+            TSX => "TSX",
+            TXA => "TXA",
+            TXS => "TXS",
+            TYA => "TYA",
+            ALR => "ALR",
+            ANC => "ANC",
+            ANC2 => "ANC",
+            ANE => "ANE",
+            ARR => "ARR",
+            DCP => "DCP",
+            ISC => "ISC",
+            LAS => "LAS",
+            LAX => "LAX",
+            LXA => "LXA",
+            RLA => "RLA",
+            RRA => "RRA",
+            SAX => "SAX",
+            SBX => "SBX",
+            SHA => "SHA",
+            SHX => "SHX",
+            SHY => "SHY",
+            SLO => "SLO",
+            SRE => "SRE",
+            TAS => "TAS",
+            USBC => "SBC",
+            JAM => "JAM",
+            BRA => "BRA",
+            PHX => "PHX",
+            PHY => "PHY",
+            PLX => "PLX",
+            PLY => "PLY",
+            STZ => "STZ",
+            TRB => "TRB",
+            TSB => "TSB",
+            RMB => "RMB",
+            SMB => "SMB",
+            BBR => "BBR",
+            BBS => "BBS",
+            WAI => "WAI",
+            STP => "STP",
+        }
+    }
+}
+
+/// Static metadata of a single opcode byte.
+///
+/// `cycles` is the base cycle count. When `page_penalty` is set, one more
+/// cycle is spent if the indexed address crosses a page boundary; for the
+/// branches it means one cycle if the branch is taken and another one if the
+/// target lies on a different page. `JAM` never finishes, its count is the
+/// number of cycles before the bus locks up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub mode: AddressingMode,
     pub cycles: u16,
+    pub length: u16,
+    pub page_penalty: bool,
 }
 
-fn ins(opcode: Opcode, mode: AddressingMode, cycles: u16) -> Instruction {
+const fn ins(opcode: Opcode, mode: AddressingMode, cycles: u16) -> Instruction {
     Instruction {
         opcode,
         mode,
         cycles,
+        length: mode.length(),
+        page_penalty: false,
+    }
+}
+
+const fn inp(opcode: Opcode, mode: AddressingMode, cycles: u16) -> Instruction {
+    Instruction {
+        page_penalty: true,
+        ..ins(opcode, mode, cycles)
+    }
+}
+
+/// The NMOS 6502 decode table, indexed by opcode byte.
+pub const INSTRUCTIONS: [Instruction; 256] = [
+    // 0x00
+    ins(BRK, Impl, 7),
+    ins(ORA, XInd, 6),
+    ins(JAM, Impl, 2),
+    ins(SLO, XInd, 8),
+    ins(NOP, Zpg, 3),
+    ins(ORA, Zpg, 3),
+    ins(ASL, Zpg, 5),
+    ins(SLO, Zpg, 5),
+    ins(PHP, Impl, 3),
+    ins(ORA, Imm, 2),
+    ins(ASL, Acc, 2),
+    ins(ANC, Imm, 2),
+    ins(NOP, Abs, 4),
+    ins(ORA, Abs, 4),
+    ins(ASL, Abs, 6),
+    ins(SLO, Abs, 6),
+    // 0x10
+    inp(BPL, Rel, 2),
+    inp(ORA, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(SLO, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(ORA, ZpgX, 4),
+    ins(ASL, ZpgX, 6),
+    ins(SLO, ZpgX, 6),
+    ins(CLC, Impl, 2),
+    inp(ORA, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(SLO, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(ORA, AbsX, 4),
+    ins(ASL, AbsX, 7),
+    ins(SLO, AbsX, 7),
+    // 0x20
+    ins(JSR, Abs, 6),
+    ins(AND, XInd, 6),
+    ins(JAM, Impl, 2),
+    ins(RLA, XInd, 8),
+    ins(BIT, Zpg, 3),
+    ins(AND, Zpg, 3),
+    ins(ROL, Zpg, 5),
+    ins(RLA, Zpg, 5),
+    ins(PLP, Impl, 4),
+    ins(AND, Imm, 2),
+    ins(ROL, Acc, 2),
+    ins(ANC2, Imm, 2),
+    ins(BIT, Abs, 4),
+    ins(AND, Abs, 4),
+    ins(ROL, Abs, 6),
+    ins(RLA, Abs, 6),
+    // 0x30
+    inp(BMI, Rel, 2),
+    inp(AND, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(RLA, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(AND, ZpgX, 4),
+    ins(ROL, ZpgX, 6),
+    ins(RLA, ZpgX, 6),
+    ins(SEC, Impl, 2),
+    inp(AND, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(RLA, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(AND, AbsX, 4),
+    ins(ROL, AbsX, 7),
+    ins(RLA, AbsX, 7),
+    // 0x40
+    ins(RTI, Impl, 6),
+    ins(EOR, XInd, 6),
+    ins(JAM, Impl, 2),
+    ins(SRE, XInd, 8),
+    ins(NOP, Zpg, 3),
+    ins(EOR, Zpg, 3),
+    ins(LSR, Zpg, 5),
+    ins(SRE, Zpg, 5),
+    ins(PHA, Impl, 3),
+    ins(EOR, Imm, 2),
+    ins(LSR, Acc, 2),
+    ins(ALR, Imm, 2),
+    ins(JMP, Abs, 3),
+    ins(EOR, Abs, 4),
+    ins(LSR, Abs, 6),
+    ins(SRE, Abs, 6),
+    // 0x50
+    inp(BVC, Rel, 2),
+    inp(EOR, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(SRE, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(EOR, ZpgX, 4),
+    ins(LSR, ZpgX, 6),
+    ins(SRE, ZpgX, 6),
+    ins(CLI, Impl, 2),
+    inp(EOR, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(SRE, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(EOR, AbsX, 4),
+    ins(LSR, AbsX, 7),
+    ins(SRE, AbsX, 7),
+    // 0x60
+    ins(RTS, Impl, 6),
+    ins(ADC, XInd, 6),
+    ins(JAM, Impl, 2),
+    ins(RRA, XInd, 8),
+    ins(NOP, Zpg, 3),
+    ins(ADC, Zpg, 3),
+    ins(ROR, Zpg, 5),
+    ins(RRA, Zpg, 5),
+    ins(PLA, Impl, 4),
+    ins(ADC, Imm, 2),
+    ins(ROR, Acc, 2),
+    ins(ARR, Imm, 2),
+    ins(JMP, Ind, 5),
+    ins(ADC, Abs, 4),
+    ins(ROR, Abs, 6),
+    ins(RRA, Abs, 6),
+    // 0x70
+    inp(BVS, Rel, 2),
+    inp(ADC, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(RRA, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(ADC, ZpgX, 4),
+    ins(ROR, ZpgX, 6),
+    ins(RRA, ZpgX, 6),
+    ins(SEI, Impl, 2),
+    inp(ADC, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(RRA, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(ADC, AbsX, 4),
+    ins(ROR, AbsX, 7),
+    ins(RRA, AbsX, 7),
+    // 0x80
+    ins(NOP, Imm, 2),
+    ins(STA, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(SAX, XInd, 6),
+    ins(STY, Zpg, 3),
+    ins(STA, Zpg, 3),
+    ins(STX, Zpg, 3),
+    ins(SAX, Zpg, 3),
+    ins(DEY, Impl, 2),
+    ins(NOP, Imm, 2),
+    ins(TXA, Impl, 2),
+    ins(ANE, Imm, 2),
+    ins(STY, Abs, 4),
+    ins(STA, Abs, 4),
+    ins(STX, Abs, 4),
+    ins(SAX, Abs, 4),
+    // 0x90
+    inp(BCC, Rel, 2),
+    ins(STA, IndY, 6),
+    ins(JAM, Impl, 2),
+    ins(SHA, IndY, 6),
+    ins(STY, ZpgX, 4),
+    ins(STA, ZpgX, 4),
+    ins(STX, ZpgY, 4),
+    ins(SAX, ZpgY, 4),
+    ins(TYA, Impl, 2),
+    ins(STA, AbsY, 5),
+    ins(TXS, Impl, 2),
+    ins(TAS, AbsY, 5),
+    ins(SHY, AbsX, 5),
+    ins(STA, AbsX, 5),
+    ins(SHX, AbsY, 5),
+    ins(SHA, AbsY, 5),
+    // 0xA0
+    ins(LDY, Imm, 2),
+    ins(LDA, XInd, 6),
+    ins(LDX, Imm, 2),
+    ins(LAX, XInd, 6),
+    ins(LDY, Zpg, 3),
+    ins(LDA, Zpg, 3),
+    ins(LDX, Zpg, 3),
+    ins(LAX, Zpg, 3),
+    ins(TAY, Impl, 2),
+    ins(LDA, Imm, 2),
+    ins(TAX, Impl, 2),
+    ins(LXA, Imm, 2),
+    ins(LDY, Abs, 4),
+    ins(LDA, Abs, 4),
+    ins(LDX, Abs, 4),
+    ins(LAX, Abs, 4),
+    // 0xB0
+    inp(BCS, Rel, 2),
+    inp(LDA, IndY, 5),
+    ins(JAM, Impl, 2),
+    inp(LAX, IndY, 5),
+    ins(LDY, ZpgX, 4),
+    ins(LDA, ZpgX, 4),
+    ins(LDX, ZpgY, 4),
+    ins(LAX, ZpgY, 4),
+    ins(CLV, Impl, 2),
+    inp(LDA, AbsY, 4),
+    ins(TSX, Impl, 2),
+    inp(LAS, AbsY, 4),
+    inp(LDY, AbsX, 4),
+    inp(LDA, AbsX, 4),
+    inp(LDX, AbsY, 4),
+    inp(LAX, AbsY, 4),
+    // 0xC0
+    ins(CPY, Imm, 2),
+    ins(CMP, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(DCP, XInd, 8),
+    ins(CPY, Zpg, 3),
+    ins(CMP, Zpg, 3),
+    ins(DEC, Zpg, 5),
+    ins(DCP, Zpg, 5),
+    ins(INY, Impl, 2),
+    ins(CMP, Imm, 2),
+    ins(DEX, Impl, 2),
+    ins(SBX, Imm, 2),
+    ins(CPY, Abs, 4),
+    ins(CMP, Abs, 4),
+    ins(DEC, Abs, 6),
+    ins(DCP, Abs, 6),
+    // 0xD0
+    inp(BNE, Rel, 2),
+    inp(CMP, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(DCP, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(CMP, ZpgX, 4),
+    ins(DEC, ZpgX, 6),
+    ins(DCP, ZpgX, 6),
+    ins(CLD, Impl, 2),
+    inp(CMP, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(DCP, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(CMP, AbsX, 4),
+    ins(DEC, AbsX, 7),
+    ins(DCP, AbsX, 7),
+    // 0xE0
+    ins(CPX, Imm, 2),
+    ins(SBC, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(ISC, XInd, 8),
+    ins(CPX, Zpg, 3),
+    ins(SBC, Zpg, 3),
+    ins(INC, Zpg, 5),
+    ins(ISC, Zpg, 5),
+    ins(INX, Impl, 2),
+    ins(SBC, Imm, 2),
+    ins(NOP, Impl, 2),
+    ins(USBC, Imm, 2),
+    ins(CPX, Abs, 4),
+    ins(SBC, Abs, 4),
+    ins(INC, Abs, 6),
+    ins(ISC, Abs, 6),
+    // 0xF0
+    inp(BEQ, Rel, 2),
+    inp(SBC, IndY, 5),
+    ins(JAM, Impl, 2),
+    ins(ISC, IndY, 8),
+    ins(NOP, ZpgX, 4),
+    ins(SBC, ZpgX, 4),
+    ins(INC, ZpgX, 6),
+    ins(ISC, ZpgX, 6),
+    ins(SED, Impl, 2),
+    inp(SBC, AbsY, 4),
+    ins(NOP, Impl, 2),
+    ins(ISC, AbsY, 7),
+    inp(NOP, AbsX, 4),
+    inp(SBC, AbsX, 4),
+    ins(INC, AbsX, 7),
+    ins(ISC, AbsX, 7),
+];
+
+/// The WDC 65C02 decode table. The opcodes the NMOS 6502 has undocumented
+/// instructions for are `NOP`s of different lengths and cycle counts, and
+/// `BBR` and `BBS` count the cycles like the other branches. `ADC` and `SBC`
+/// take another cycle in decimal mode.
+pub const INSTRUCTIONS_65C02: [Instruction; 256] = [
+    // 0x00
+    ins(BRK, Impl, 7),
+    ins(ORA, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(TSB, Zpg, 5),
+    ins(ORA, Zpg, 3),
+    ins(ASL, Zpg, 5),
+    ins(RMB, Zpg, 5),
+    ins(PHP, Impl, 3),
+    ins(ORA, Imm, 2),
+    ins(ASL, Acc, 2),
+    ins(NOP, Impl, 1),
+    ins(TSB, Abs, 6),
+    ins(ORA, Abs, 4),
+    ins(ASL, Abs, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x10
+    inp(BPL, Rel, 2),
+    inp(ORA, IndY, 5),
+    ins(ORA, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(TRB, Zpg, 5),
+    ins(ORA, ZpgX, 4),
+    ins(ASL, ZpgX, 6),
+    ins(RMB, Zpg, 5),
+    ins(CLC, Impl, 2),
+    inp(ORA, AbsY, 4),
+    ins(INC, Acc, 2),
+    ins(NOP, Impl, 1),
+    ins(TRB, Abs, 6),
+    inp(ORA, AbsX, 4),
+    inp(ASL, AbsX, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x20
+    ins(JSR, Abs, 6),
+    ins(AND, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(BIT, Zpg, 3),
+    ins(AND, Zpg, 3),
+    ins(ROL, Zpg, 5),
+    ins(RMB, Zpg, 5),
+    ins(PLP, Impl, 4),
+    ins(AND, Imm, 2),
+    ins(ROL, Acc, 2),
+    ins(NOP, Impl, 1),
+    ins(BIT, Abs, 4),
+    ins(AND, Abs, 4),
+    ins(ROL, Abs, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x30
+    inp(BMI, Rel, 2),
+    inp(AND, IndY, 5),
+    ins(AND, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(BIT, ZpgX, 4),
+    ins(AND, ZpgX, 4),
+    ins(ROL, ZpgX, 6),
+    ins(RMB, Zpg, 5),
+    ins(SEC, Impl, 2),
+    inp(AND, AbsY, 4),
+    ins(DEC, Acc, 2),
+    ins(NOP, Impl, 1),
+    inp(BIT, AbsX, 4),
+    inp(AND, AbsX, 4),
+    inp(ROL, AbsX, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x40
+    ins(RTI, Impl, 6),
+    ins(EOR, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(NOP, Zpg, 3),
+    ins(EOR, Zpg, 3),
+    ins(LSR, Zpg, 5),
+    ins(RMB, Zpg, 5),
+    ins(PHA, Impl, 3),
+    ins(EOR, Imm, 2),
+    ins(LSR, Acc, 2),
+    ins(NOP, Impl, 1),
+    ins(JMP, Abs, 3),
+    ins(EOR, Abs, 4),
+    ins(LSR, Abs, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x50
+    inp(BVC, Rel, 2),
+    inp(EOR, IndY, 5),
+    ins(EOR, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(NOP, ZpgX, 4),
+    ins(EOR, ZpgX, 4),
+    ins(LSR, ZpgX, 6),
+    ins(RMB, Zpg, 5),
+    ins(CLI, Impl, 2),
+    inp(EOR, AbsY, 4),
+    ins(PHY, Impl, 3),
+    ins(NOP, Impl, 1),
+    ins(NOP, Abs, 8),
+    inp(EOR, AbsX, 4),
+    inp(LSR, AbsX, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x60
+    ins(RTS, Impl, 6),
+    ins(ADC, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(STZ, Zpg, 3),
+    ins(ADC, Zpg, 3),
+    ins(ROR, Zpg, 5),
+    ins(RMB, Zpg, 5),
+    ins(PLA, Impl, 4),
+    ins(ADC, Imm, 2),
+    ins(ROR, Acc, 2),
+    ins(NOP, Impl, 1),
+    ins(JMP, Ind, 6),
+    ins(ADC, Abs, 4),
+    ins(ROR, Abs, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x70
+    inp(BVS, Rel, 2),
+    inp(ADC, IndY, 5),
+    ins(ADC, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(STZ, ZpgX, 4),
+    ins(ADC, ZpgX, 4),
+    ins(ROR, ZpgX, 6),
+    ins(RMB, Zpg, 5),
+    ins(SEI, Impl, 2),
+    inp(ADC, AbsY, 4),
+    ins(PLY, Impl, 4),
+    ins(NOP, Impl, 1),
+    ins(JMP, AbsXInd, 6),
+    inp(ADC, AbsX, 4),
+    inp(ROR, AbsX, 6),
+    inp(BBR, ZpgRel, 5),
+    // 0x80
+    inp(BRA, Rel, 2),
+    ins(STA, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(STY, Zpg, 3),
+    ins(STA, Zpg, 3),
+    ins(STX, Zpg, 3),
+    ins(SMB, Zpg, 5),
+    ins(DEY, Impl, 2),
+    ins(BIT, Imm, 2),
+    ins(TXA, Impl, 2),
+    ins(NOP, Impl, 1),
+    ins(STY, Abs, 4),
+    ins(STA, Abs, 4),
+    ins(STX, Abs, 4),
+    inp(BBS, ZpgRel, 5),
+    // 0x90
+    inp(BCC, Rel, 2),
+    ins(STA, IndY, 6),
+    ins(STA, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(STY, ZpgX, 4),
+    ins(STA, ZpgX, 4),
+    ins(STX, ZpgY, 4),
+    ins(SMB, Zpg, 5),
+    ins(TYA, Impl, 2),
+    ins(STA, AbsY, 5),
+    ins(TXS, Impl, 2),
+    ins(NOP, Impl, 1),
+    ins(STZ, Abs, 4),
+    ins(STA, AbsX, 5),
+    ins(STZ, AbsX, 5),
+    inp(BBS, ZpgRel, 5),
+    // 0xa0
+    ins(LDY, Imm, 2),
+    ins(LDA, XInd, 6),
+    ins(LDX, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(LDY, Zpg, 3),
+    ins(LDA, Zpg, 3),
+    ins(LDX, Zpg, 3),
+    ins(SMB, Zpg, 5),
+    ins(TAY, Impl, 2),
+    ins(LDA, Imm, 2),
+    ins(TAX, Impl, 2),
+    ins(NOP, Impl, 1),
+    ins(LDY, Abs, 4),
+    ins(LDA, Abs, 4),
+    ins(LDX, Abs, 4),
+    inp(BBS, ZpgRel, 5),
+    // 0xb0
+    inp(BCS, Rel, 2),
+    inp(LDA, IndY, 5),
+    ins(LDA, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(LDY, ZpgX, 4),
+    ins(LDA, ZpgX, 4),
+    ins(LDX, ZpgY, 4),
+    ins(SMB, Zpg, 5),
+    ins(CLV, Impl, 2),
+    inp(LDA, AbsY, 4),
+    ins(TSX, Impl, 2),
+    ins(NOP, Impl, 1),
+    inp(LDY, AbsX, 4),
+    inp(LDA, AbsX, 4),
+    inp(LDX, AbsY, 4),
+    inp(BBS, ZpgRel, 5),
+    // 0xc0
+    ins(CPY, Imm, 2),
+    ins(CMP, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(CPY, Zpg, 3),
+    ins(CMP, Zpg, 3),
+    ins(DEC, Zpg, 5),
+    ins(SMB, Zpg, 5),
+    ins(INY, Impl, 2),
+    ins(CMP, Imm, 2),
+    ins(DEX, Impl, 2),
+    ins(WAI, Impl, 3),
+    ins(CPY, Abs, 4),
+    ins(CMP, Abs, 4),
+    ins(DEC, Abs, 6),
+    inp(BBS, ZpgRel, 5),
+    // 0xd0
+    inp(BNE, Rel, 2),
+    inp(CMP, IndY, 5),
+    ins(CMP, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(NOP, ZpgX, 4),
+    ins(CMP, ZpgX, 4),
+    ins(DEC, ZpgX, 6),
+    ins(SMB, Zpg, 5),
+    ins(CLD, Impl, 2),
+    inp(CMP, AbsY, 4),
+    ins(PHX, Impl, 3),
+    ins(STP, Impl, 3),
+    ins(NOP, Abs, 4),
+    inp(CMP, AbsX, 4),
+    ins(DEC, AbsX, 7),
+    inp(BBS, ZpgRel, 5),
+    // 0xe0
+    ins(CPX, Imm, 2),
+    ins(SBC, XInd, 6),
+    ins(NOP, Imm, 2),
+    ins(NOP, Impl, 1),
+    ins(CPX, Zpg, 3),
+    ins(SBC, Zpg, 3),
+    ins(INC, Zpg, 5),
+    ins(SMB, Zpg, 5),
+    ins(INX, Impl, 2),
+    ins(SBC, Imm, 2),
+    ins(NOP, Impl, 2),
+    ins(NOP, Impl, 1),
+    ins(CPX, Abs, 4),
+    ins(SBC, Abs, 4),
+    ins(INC, Abs, 6),
+    inp(BBS, ZpgRel, 5),
+    // 0xf0
+    inp(BEQ, Rel, 2),
+    inp(SBC, IndY, 5),
+    ins(SBC, ZpgInd, 5),
+    ins(NOP, Impl, 1),
+    ins(NOP, ZpgX, 4),
+    ins(SBC, ZpgX, 4),
+    ins(INC, ZpgX, 6),
+    ins(SMB, Zpg, 5),
+    ins(SED, Impl, 2),
+    inp(SBC, AbsY, 4),
+    ins(PLX, Impl, 4),
+    ins(NOP, Impl, 1),
+    ins(NOP, Abs, 4),
+    inp(SBC, AbsX, 4),
+    ins(INC, AbsX, 7),
+    inp(BBS, ZpgRel, 5),
+];
+
+impl Instruction {
+    pub fn from_byte(data: u8) -> Instruction {
+        INSTRUCTIONS[data as usize]
+    }
+
+    /// The instruction `data` decodes to on `model`, the NMOS models share
+    /// their table.
+    pub fn for_model(model: CpuModel, data: u8) -> Instruction {
+        match model {
+            CpuModel::Wdc65C02 => INSTRUCTIONS_65C02[data as usize],
+            _ => Self::from_byte(data),
+        }
+    }
+
+    /// The mnemonic of opcode byte `data`, with the bit number for `RMB`,
+    /// `SMB`, `BBR` and `BBS`.
+    pub fn name(&self, data: u8) -> String {
+        let mnemonic = self.opcode.mnemonic();
+        match self.opcode {
+            RMB | SMB | BBR | BBS => format!("{}{}", mnemonic, (data >> 4) & 7),
+            _ => mnemonic.to_owned(),
+        }
+    }
+
+    /// Whether the opcode byte is part of the documented instruction set, the
+    /// only documented `NOP` is $EA.
+    pub fn is_documented(data: u8) -> bool {
+        Self::is_documented_on(CpuModel::Nmos6502, data)
+    }
+
+    /// [`is_documented`](Self::is_documented) for `model`, the unused opcodes
+    /// of the 65C02 are `NOP`s as well.
+    pub fn is_documented_on(model: CpuModel, data: u8) -> bool {
+        match Self::for_model(model, data).opcode {
+            NOP => data == 0xEA,
+            op => !op.is_illegal(),
+        }
     }
 }
+
 #[cfg(test)]
 mod test {
-    use crate::cpu::opcodes::Instruction;
+    use crate::cpu::bench::Bench;
+    use crate::cpu::model::CpuModel;
+    use crate::cpu::opcodes::{Instruction, Opcode, INSTRUCTIONS, INSTRUCTIONS_65C02};
 
     #[test]
     #[cfg(test)]
@@ -206,13 +998,87 @@ mod test {
         );
         assert_eq!(
             Instruction::from_byte(0x64),
-            super::ins(super::NOP, super::Zpg, 7)
+            super::ins(super::NOP, super::Zpg, 3)
         );
     }
-}
 
-impl Instruction {
-    pub fn from_byte(data: u8) -> Instruction {
-        panic!("help?")
+    /// Runs the instruction at $0200 after a reset and counts its cycles.
+    fn microcode_cycles(model: CpuModel, code: &[u8]) -> u16 {
+        Bench::new(model, code).step()
+    }
+
+    #[test]
+    fn cycles_match_microcode() {
+        for (op, instruction) in INSTRUCTIONS.iter().enumerate() {
+            if instruction.opcode == Opcode::JAM {
+                continue;
+            }
+            // all operands are zero, so no page is crossed and no branch is taken
+            // except BPL/BVC/BCC/BNE, which take the branch to the next instruction
+            let expected = if instruction.mode == super::Rel
+                && matches!(
+                    instruction.opcode,
+                    Opcode::BPL | Opcode::BVC | Opcode::BCC | Opcode::BNE
+                ) {
+                instruction.cycles + 1
+            } else {
+                instruction.cycles
+            };
+            assert_eq!(
+                microcode_cycles(CpuModel::Nmos6502, &[op as u8, 0x00, 0x00]),
+                expected,
+                "{:#04X} {:?}",
+                op,
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn cycles_match_microcode_65c02() {
+        for (op, instruction) in INSTRUCTIONS_65C02.iter().enumerate() {
+            if matches!(instruction.opcode, Opcode::WAI | Opcode::STP) {
+                continue;
+            }
+            // the operands are zero again, BRA and BBR take their branch too
+            let taken = matches!(
+                instruction.opcode,
+                Opcode::BPL | Opcode::BVC | Opcode::BCC | Opcode::BNE | Opcode::BRA | Opcode::BBR
+            );
+            assert_eq!(
+                microcode_cycles(CpuModel::Wdc65C02, &[op as u8, 0x00, 0x00]),
+                instruction.cycles + taken as u16,
+                "{:#04X} {:?}",
+                op,
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn decode_65c02() {
+        let b2 = Instruction::for_model(CpuModel::Wdc65C02, 0xB2);
+        assert_eq!((b2.opcode, b2.length), (Opcode::LDA, 2));
+        assert_eq!(
+            Instruction::for_model(CpuModel::Mos6510, 0xB2).opcode,
+            Opcode::JAM
+        );
+        assert_eq!(
+            Instruction::for_model(CpuModel::Wdc65C02, 0xD7).name(0xD7),
+            "SMB5"
+        );
+        assert!(Instruction::is_documented_on(CpuModel::Wdc65C02, 0x7C));
+        assert!(!Instruction::is_documented_on(CpuModel::Wdc65C02, 0x03));
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(Instruction::from_byte(0x20).length, 3);
+        assert_eq!(Instruction::from_byte(0x6C).length, 3);
+        assert_eq!(Instruction::from_byte(0xD0).length, 2);
+        assert_eq!(Instruction::from_byte(0x0A).length, 1);
+        assert!(Instruction::from_byte(0xBD).page_penalty);
+        assert!(!Instruction::from_byte(0x9D).page_penalty);
+        assert!(Instruction::from_byte(0x02).opcode.is_illegal());
     }
 }
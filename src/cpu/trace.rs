@@ -0,0 +1,268 @@
+//! An instruction trace in the layout of the nestest log, so it can be diffed
+//! against the logs of other emulators:
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+//! ```
+//!
+//! Undocumented opcodes are marked with a `*` in front of the mnemonic. The
+//! PPU column of the original log is left out, and the `= xx` memory
+//! annotations as well, since reading memory just for the log could trigger
+//! side effects in devices.
+
+use crate::cpu::disasm::decode_for;
+use crate::cpu::model::CpuModel;
+use crate::cpu::opcodes::Instruction;
+use crate::cpu::{Pins, ReadWrite, StatusRegister, CPU};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// An instruction whose bytes are still being read from the bus.
+struct Pending {
+    model: CpuModel,
+    address: u16,
+    bytes: Vec<u8>,
+    length: usize,
+    /// false until a cycle after the fetch has run
+    started: bool,
+    ac: u8,
+    x: u8,
+    y: u8,
+    sr: u8,
+    sp: u8,
+    cycle: u64,
+}
+
+/// Writes a line for every instruction the CPU executes. The instruction
+/// bytes are taken from the bus cycles as the CPU reads them, so a line is
+/// written once the next instruction is fetched.
+pub struct Tracer<W: Write> {
+    out: W,
+    range: Option<RangeInclusive<u16>>,
+    cycle_offset: u64,
+    pending: Option<Pending>,
+}
+
+impl Tracer<BufWriter<File>> {
+    pub fn to_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            range: None,
+            cycle_offset: 0,
+            pending: None,
+        }
+    }
+
+    /// Only trace the instructions that start within `range`.
+    pub fn with_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Added to the cycle count of the CPU. The first instruction of nestest
+    /// is at cycle 7, one more than ours because the CPU starts in the
+    /// middle of the reset sequence, after a fetch that isn't counted.
+    pub fn with_cycle_offset(mut self, offset: u64) -> Self {
+        self.cycle_offset = offset;
+        self
+    }
+
+    /// Call after every tick, once the bus access for it has been done.
+    pub fn trace(&mut self, cpu: &CPU, pins: &Pins) -> io::Result<()> {
+        if pins.sync {
+            self.write_pending()?;
+            // a fetch with the reset line pulled is thrown away for the reset
+            // sequence
+            let in_range = self
+                .range
+                .as_ref()
+                .is_none_or(|r| r.contains(&pins.address));
+            if in_range && !pins.res {
+                self.pending = Some(Pending {
+                    model: cpu.model(),
+                    address: pins.address,
+                    bytes: vec![pins.data],
+                    length: Instruction::for_model(cpu.model(), pins.data).length as usize,
+                    started: false,
+                    ac: cpu.ac,
+                    x: cpu.x,
+                    y: cpu.y,
+                    sr: cpu.sr.bits(),
+                    sp: cpu.sp,
+                    cycle: cpu.instruction_start() + self.cycle_offset,
+                });
+            }
+        } else if let Some(p) = &mut self.pending {
+            p.started = true;
+            let next = p.address.wrapping_add(p.bytes.len() as u16);
+            if pins.rw == ReadWrite::Read && pins.address == next && p.bytes.len() < p.length {
+                p.bytes.push(pins.data);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let p = match self.pending.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let d = decode_for(p.model, &p.bytes, p.address, None);
+        let bytes = p
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        // the unused bit always reads as set and B doesn't exist outside the stack
+        let sr = (p.sr | StatusRegister::X.bits()) & !StatusRegister::B.bits();
+        writeln!(
+            self.out,
+            "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            p.address,
+            bytes,
+            if d.documented { ' ' } else { '*' },
+            d.text,
+            p.ac,
+            p.x,
+            p.y,
+            sr,
+            p.sp,
+            p.cycle
+        )
+    }
+
+    /// Writes the instruction that's still executing and flushes the output.
+    /// An instruction that has only been fetched so far isn't written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending.as_ref().is_some_and(|p| p.started) {
+            self.write_pending()?;
+        }
+        self.pending = None;
+        self.out.flush()
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::bench::Bench;
+
+    fn run(
+        model: CpuModel,
+        program: &[u8],
+        instructions: usize,
+        tracer: Tracer<Vec<u8>>,
+    ) -> String {
+        let mut bench = Bench::at(model, 0xC000, program);
+        let mut tracer = tracer;
+        let mut fetches = 0;
+        loop {
+            bench.tick();
+            // the trace starts with the first fetch after the reset
+            if bench.pins.sync {
+                fetches += 1;
+                if fetches > instructions {
+                    break;
+                }
+            }
+            if fetches > 0 {
+                tracer.trace(&bench.cpu, &bench.pins).unwrap();
+            }
+        }
+        String::from_utf8(tracer.into_inner().unwrap()).unwrap()
+    }
+
+    const PROGRAM: [u8; 10] = [
+        0xA2, 0x05, // LDX #$05
+        0xCA, // DEX
+        0xD0, 0xFD, // BNE $C002
+        0x8D, 0x00, 0x02, // STA $0200
+        0x04, 0x10, // NOP $10
+    ];
+
+    #[test]
+    fn nestest_layout() {
+        let log = run(
+            CpuModel::Nmos6502,
+            &PROGRAM,
+            5,
+            Tracer::new(vec![]).with_cycle_offset(1),
+        );
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines[0],
+            "C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+        );
+        assert_eq!(
+            lines[1],
+            "C002  CA        DEX                             A:00 X:05 Y:00 P:24 SP:FD CYC:9"
+        );
+        assert_eq!(
+            lines[2],
+            "C003  D0 FD     BNE $C002                       A:00 X:04 Y:00 P:24 SP:FD CYC:11"
+        );
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn undocumented() {
+        let log = run(CpuModel::Nmos6502, &PROGRAM, 13, Tracer::new(vec![]));
+        let last = log.lines().last().unwrap();
+        assert!(
+            last.starts_with("C008  04 10    *NOP $10      "),
+            "{}",
+            last
+        );
+    }
+
+    #[test]
+    fn range() {
+        let log = run(
+            CpuModel::Nmos6502,
+            &PROGRAM,
+            13,
+            Tracer::new(vec![]).with_range(0xC002..=0xC004),
+        );
+        let addresses: Vec<&str> = log.lines().map(|l| &l[..4]).collect();
+        assert_eq!(
+            addresses,
+            ["C002", "C003", "C002", "C003", "C002", "C003", "C002", "C003", "C002", "C003"]
+        );
+    }
+
+    #[test]
+    fn wdc65c02() {
+        let program = [
+            0xB2, 0x20, // LDA ($20)
+            0x80, 0x00, // BRA $C004
+            0xEA, // NOP
+        ];
+        let log = run(CpuModel::Wdc65C02, &program, 3, Tracer::new(vec![]));
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(
+            lines[0].starts_with("C000  B2 20     LDA ($20)  "),
+            "{}",
+            log
+        );
+        assert!(
+            lines[1].starts_with("C002  80 00     BRA $C004  "),
+            "{}",
+            log
+        );
+        assert!(lines[2].starts_with("C004  EA        NOP  "), "{}", log);
+    }
+}
@@ -1,34 +1,498 @@
 use super::*;
+use crate::cpu::ReadWrite;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
 
+/// The most faults kept until they're taken, the rest are dropped.
+const MAX_FAULTS: usize = 256;
+
+/// A device and the addresses it was mapped to.
+struct Mapping {
+    name: String,
+    range: RangeInclusive<u16>,
+    priority: u8,
+    device: Box<dyn Device>,
+}
+
+/// Which devices own the addresses of a 256 byte page.
+#[derive(Clone)]
+enum Page {
+    Empty,
+    /// one device owns the whole page
+    Whole(usize),
+    /// the owner of every address in the page
+    Split(Box<[Option<usize>; 256]>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FaultKind {
+    /// a write to a device that can't be written, like a ROM
+    ReadOnly,
+    /// an access to an address no device answers
+    Unmapped,
+    /// an access to an address that several devices are mapped to, the one
+    /// with the highest priority got it
+    Contested,
+}
+
+/// What a read returns when no device answers it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum OpenBus {
+    /// the last value on the data bus, which the capacitance of the bus
+    /// holds on most machines, it's usually the last byte of the instruction
+    #[default]
+    LastValue,
+    /// a fixed value, like the $FF of pull-up resistors
+    Floating(u8),
+}
+
+/// A bus access that went wrong, or may have.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BusFault {
+    pub kind: FaultKind,
+    pub address: u16,
+    pub rw: ReadWrite,
+    /// the value written, or the value read if a device answered
+    pub data: Option<u8>,
+}
+
+impl Display for BusFault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.rw {
+            ReadWrite::Read => write!(f, "read from")?,
+            ReadWrite::Write => write!(f, "write of ${:02X} to", self.data.unwrap_or(0))?,
+        }
+        match self.kind {
+            FaultKind::ReadOnly => write!(f, " read-only ${:04X}", self.address),
+            FaultKind::Unmapped => write!(f, " unmapped ${:04X}", self.address),
+            FaultKind::Contested => write!(
+                f,
+                " ${:04X}, which several devices are mapped to",
+                self.address
+            ),
+        }
+    }
+}
+
+/// The bus, it sends every access to the device that owns the address. The
+/// owners are looked up in a page table, so the number of devices doesn't
+/// slow it down.
 pub struct DeviceMap {
-    devices: Vec<Box<dyn Device>>,
+    devices: Vec<Mapping>,
+    /// devices that aren't on the bus, they're only ticked and reset
+    attached: Vec<Box<dyn Device>>,
+    pages: Vec<Page>,
+    /// the pages with addresses that several devices are mapped to
+    contested: Vec<bool>,
+    faults: Vec<BusFault>,
+    open_bus: OpenBus,
+    /// the last value read or written
+    last: u8,
 }
 
-impl<'t> DeviceMap {
+impl Default for DeviceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceMap {
     pub fn new() -> Self {
-        Self { devices: vec![] }
+        Self {
+            devices: vec![],
+            attached: vec![],
+            pages: vec![Page::Empty; 256],
+            contested: vec![false; 256],
+            faults: vec![],
+            open_bus: OpenBus::default(),
+            last: 0,
+        }
+    }
+
+    /// Sets what reads from addresses no device answers return.
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    /// Maps `device` to `range`, which may not overlap with other devices of
+    /// the same priority. The device is passed the full address, not the
+    /// offset into the range.
+    pub fn map<T: 'static + Device>(
+        &mut self,
+        name: impl Into<String>,
+        range: RangeInclusive<u16>,
+        device: T,
+    ) -> Result<(), CreateError> {
+        self.map_with_priority(name, range, 0, device)
+    }
+
+    /// Maps `device` to `range`. Where it overlaps with other devices, the
+    /// one with the highest priority owns the address, overlapping devices
+    /// with the same priority are an error.
+    pub fn map_with_priority<T: 'static + Device>(
+        &mut self,
+        name: impl Into<String>,
+        range: RangeInclusive<u16>,
+        priority: u8,
+        device: T,
+    ) -> Result<(), CreateError> {
+        let name = name.into();
+        // the owner of an address may have a higher priority and hide the
+        // device of the same priority below it, so look at all of them
+        let clash = self
+            .devices
+            .iter()
+            .filter(|other| other.priority == priority)
+            .filter_map(|other| {
+                let start = *other.range.start().max(range.start());
+                let end = *other.range.end().min(range.end());
+                (start <= end).then_some((start, other))
+            })
+            .min_by_key(|(address, _)| *address);
+        if let Some((address, other)) = clash {
+            return Err(CreateError::OverlapError {
+                first: other.name.clone(),
+                second: name,
+                address,
+            });
+        }
+
+        let mut owned = vec![];
+        let mut contested = vec![];
+        for address in range.clone() {
+            let owner = self.owner(address).map(|i| &self.devices[i]);
+            if owner.is_some() {
+                contested.push(address);
+            }
+            match owner {
+                Some(other) if other.priority > priority => {}
+                _ => owned.push(address),
+            }
+        }
+
+        let index = self.devices.len();
+        for address in owned {
+            self.set_owner(address, index);
+        }
+        for address in contested {
+            self.contested[(address >> 8) as usize] = true;
+        }
+        self.devices.push(Mapping {
+            name,
+            range,
+            priority,
+            device: Box::new(device),
+        });
+        Ok(())
+    }
+
+    /// Adds a device without any addresses, for things that only need the
+    /// ticks and resets, like the key script.
+    pub fn attach<T: 'static + Device>(&mut self, device: T) {
+        self.attached.push(Box::new(device));
+    }
+
+    /// The index of the device that owns `address`.
+    fn owner(&self, address: u16) -> Option<usize> {
+        match &self.pages[(address >> 8) as usize] {
+            Page::Empty => None,
+            Page::Whole(index) => Some(*index),
+            Page::Split(owners) => owners[(address & 0xFF) as usize],
+        }
+    }
+
+    fn set_owner(&mut self, address: u16, index: usize) {
+        let page = &mut self.pages[(address >> 8) as usize];
+        let mut owners = match std::mem::replace(page, Page::Empty) {
+            Page::Empty => Box::new([None; 256]),
+            Page::Whole(owner) => Box::new([Some(owner); 256]),
+            Page::Split(owners) => owners,
+        };
+        owners[(address & 0xFF) as usize] = Some(index);
+        *page = if owners.iter().all(|o| *o == Some(index)) {
+            Page::Whole(index)
+        } else {
+            Page::Split(owners)
+        };
+    }
+
+    /// Whether more than one device is mapped to `address`.
+    fn is_contested(&self, address: u16) -> bool {
+        self.contested[(address >> 8) as usize]
+            && self
+                .devices
+                .iter()
+                .filter(|d| d.range.contains(&address))
+                .nth(1)
+                .is_some()
+    }
+
+    fn fault(&mut self, kind: FaultKind, address: u16, rw: ReadWrite, data: Option<u8>) {
+        if self.faults.len() < MAX_FAULTS {
+            self.faults.push(BusFault {
+                kind,
+                address,
+                rw,
+                data,
+            });
+        }
+    }
+
+    /// The faults since the last call, oldest first.
+    pub fn take_faults(&mut self) -> Vec<BusFault> {
+        std::mem::take(&mut self.faults)
     }
 
-    pub fn add<T: 'static + Device>(&mut self, device: T) {
-        self.devices.push(Box::new(device));
+    /// The name and range of the device that owns `address`.
+    pub fn device_at(&self, address: u16) -> Option<(&str, RangeInclusive<u16>)> {
+        self.owner(address)
+            .map(|i| (self.devices[i].name.as_str(), self.devices[i].range.clone()))
     }
 }
 
-impl<'t> Device for DeviceMap {
+impl Device for DeviceMap {
     fn read(&mut self, address: u16) -> Option<u8> {
-        for dev in &mut self.devices {
-            match dev.read(address) {
-                None => {}
-                Some(x) => return x.into(),
-            }
+        let data = match self.owner(address) {
+            Some(index) => self.devices[index].device.read(address),
+            None => None,
+        };
+        if data.is_none() {
+            self.fault(FaultKind::Unmapped, address, ReadWrite::Read, None);
+        } else if self.is_contested(address) {
+            self.fault(FaultKind::Contested, address, ReadWrite::Read, data);
         }
-        None
+        self.last = match (data, self.open_bus) {
+            (Some(data), _) => data,
+            (None, OpenBus::LastValue) => self.last,
+            (None, OpenBus::Floating(value)) => value,
+        };
+        Some(self.last)
     }
 
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
-        for dev in &mut self.devices {
-            let _ = dev.write(address, data);
+        self.last = data;
+        let result = match self.owner(address) {
+            Some(index) => self.devices[index].device.write(address, data),
+            None => Err(WriteError::InvalidAddress),
+        };
+        let kind = match result {
+            Err(WriteError::NotWritable) => Some(FaultKind::ReadOnly),
+            Err(WriteError::InvalidAddress) => Some(FaultKind::Unmapped),
+            Ok(()) if self.is_contested(address) => Some(FaultKind::Contested),
+            Ok(()) => None,
+        };
+        if let Some(kind) = kind {
+            self.fault(kind, address, ReadWrite::Write, Some(data));
         }
-        Ok(())
+        result
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        match (self.owner(address), self.open_bus) {
+            (Some(index), _) => self.devices[index].device.peek(address),
+            (None, OpenBus::LastValue) => Some(self.last),
+            (None, OpenBus::Floating(value)) => Some(value),
+        }
+    }
+
+    /// Ticks every device, in the order they were mapped.
+    fn tick(&mut self, cycles: u64) {
+        for mapping in &mut self.devices {
+            mapping.device.tick(cycles);
+        }
+        for device in &mut self.attached {
+            device.tick(cycles);
+        }
+    }
+
+    fn reset(&mut self) {
+        for mapping in &mut self.devices {
+            mapping.device.reset();
+        }
+        for device in &mut self.attached {
+            device.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatch() {
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        map.map(
+            "rom",
+            0xE000..=0xE003,
+            Rom::from_vec(0xE000, vec![1, 2, 3, 4]),
+        )
+        .unwrap();
+        map.map("io", 0x0210..=0x0210, Ram::new(0x0210, 1)).unwrap();
+
+        map.write(0x01FF, 42).unwrap();
+        assert_eq!(map.read(0x01FF), Some(42));
+        assert_eq!(map.read(0xE003), Some(4));
+        map.write(0x0210, 7).unwrap();
+        assert_eq!(map.read(0x0210), Some(7));
+        assert_eq!(map.device_at(0x0211), None);
+        assert_eq!(map.device_at(0xE004), None);
+        assert_eq!(map.device_at(0x0210).unwrap().0, "io");
+
+        // writes only go to the owner, and aren't lost without one
+        assert_eq!(map.write(0xE000, 0), Err(WriteError::NotWritable));
+        assert_eq!(map.write(0x0300, 0), Err(WriteError::InvalidAddress));
+    }
+
+    #[test]
+    fn overlap() {
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        let error = map
+            .map("stack", 0x01F0..=0x02FF, Ram::new(0x01F0, 0x110))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "ram and stack overlap at $01F0");
+        // nothing was mapped
+        assert_eq!(map.device_at(0x0200), None);
+
+        // a register in the middle of the RAM
+        map.map_with_priority("io", 0x0010..=0x0010, 1, Ram::new(0x0010, 1))
+            .unwrap();
+        map.write(0x0010, 5).unwrap();
+        assert_eq!(map.device_at(0x0010).unwrap().0, "io");
+        assert_eq!(map.device_at(0x0011).unwrap().0, "ram");
+        map.write(0x0011, 6).unwrap();
+        assert_eq!(map.read(0x0010), Some(5));
+        // the RAM is still there below it
+        let error = map
+            .map("rom", 0x0010..=0x0010, Rom::from_vec(0x0010, vec![0]))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "ram and rom overlap at $0010");
+        let error = map
+            .map_with_priority("timer", 0x000F..=0x0010, 1, Ram::new(0x000F, 2))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "io and timer overlap at $0010");
+        assert_eq!(map.device_at(0x000F).unwrap().0, "ram");
+
+        // a lower priority only gets what's left
+        let mut map = DeviceMap::new();
+        map.map_with_priority("ram", 0x0000..=0x01FF, 1, Ram::new(0, 0x200))
+            .unwrap();
+        map.map("mirror", 0x0000..=0x03FF, Ram::new(0, 0x400))
+            .unwrap();
+        assert_eq!(map.device_at(0x01FF).unwrap().0, "ram");
+        assert_eq!(map.device_at(0x0200).unwrap().0, "mirror");
+    }
+
+    #[test]
+    fn faults() {
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        map.map("rom", 0xE000..=0xE0FF, Rom::from_vec(0xE000, vec![1, 2]))
+            .unwrap();
+        map.map_with_priority("io", 0x0010..=0x0010, 1, Ram::new(0x0010, 1))
+            .unwrap();
+
+        map.write(0x0011, 1).unwrap();
+        map.read(0x0100);
+        assert_eq!(map.take_faults(), vec![]);
+
+        let _ = map.write(0xE000, 3);
+        map.read(0xE002);
+        let _ = map.write(0x0300, 4);
+        map.read(0x0010);
+        let faults = map.take_faults();
+        let text: Vec<String> = faults.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "write of $03 to read-only $E000",
+                "read from unmapped $E002",
+                "write of $04 to unmapped $0300",
+                "read from $0010, which several devices are mapped to",
+            ]
+        );
+        assert_eq!(faults[3].data, Some(0));
+        assert_eq!(map.take_faults(), vec![]);
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x00FF, Ram::new(0, 0x100)).unwrap();
+        map.write(0x0010, 0x42).unwrap();
+        assert_eq!(map.read(0x0300), Some(0x42));
+        map.read(0x0000);
+        assert_eq!(map.read(0x0300), Some(0x00));
+        // a write drives the bus as well, even when nothing takes it
+        let _ = map.write(0x0400, 0x17);
+        assert_eq!(map.read(0x0300), Some(0x17));
+
+        map.set_open_bus(OpenBus::Floating(0xFF));
+        assert_eq!(map.read(0x0300), Some(0xFF));
+        assert_eq!(map.read(0x0010), Some(0x42));
+        assert_eq!(map.read(0x0300), Some(0xFF));
+    }
+
+    #[test]
+    fn peek() {
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x00FF, Ram::new(0, 0x100)).unwrap();
+        let keys = Arc::new(Mutex::new(Ram::new(0x0200, 1)));
+        map.map("shared", 0x0200..=0x0200, Arc::clone(&keys))
+            .unwrap();
+        map.write(0x0010, 0x42).unwrap();
+        keys.lock().unwrap().write(0x0200, 0x17).unwrap();
+
+        assert_eq!(map.peek(0x0010), Some(0x42));
+        assert_eq!(map.peek(0x0200), Some(0x17));
+        // neither a fault nor a new value on the bus
+        map.read(0x0000);
+        assert_eq!(map.peek(0x0300), Some(0x00));
+        map.peek(0x0010);
+        assert_eq!(map.peek(0x0300), Some(0x00));
+        assert_eq!(map.take_faults(), vec![]);
+    }
+
+    #[test]
+    fn tick_and_reset() {
+        /// Counts cycles, reads return the low byte of the count.
+        #[derive(Default)]
+        struct Counter(u64);
+        impl Device for Counter {
+            fn read(&mut self, _: u16) -> Option<u8> {
+                Some(self.0 as u8)
+            }
+            fn write(&mut self, _: u16, _: u8) -> Result<(), WriteError> {
+                Err(WriteError::NotWritable)
+            }
+            fn tick(&mut self, cycles: u64) {
+                self.0 += cycles;
+            }
+            fn reset(&mut self) {
+                self.0 = 0;
+            }
+        }
+
+        let mut map = DeviceMap::new();
+        map.map("ram", 0x0000..=0x00FF, Ram::new(0, 0x100)).unwrap();
+        map.map("first", 0x0100..=0x0100, Counter::default())
+            .unwrap();
+        let shared = Arc::new(Mutex::new(Counter::default()));
+        map.map("second", 0x0101..=0x0101, Arc::clone(&shared))
+            .unwrap();
+        let attached = Arc::new(Mutex::new(Counter::default()));
+        map.attach(Arc::clone(&attached));
+        map.tick(3);
+        map.tick(4);
+        assert_eq!(map.read(0x0100), Some(7));
+        assert_eq!(shared.lock().unwrap().0, 7);
+        assert_eq!(attached.lock().unwrap().0, 7);
+        assert_eq!(map.device_at(0x0102), None);
+        map.reset();
+        assert_eq!(map.read(0x0100), Some(0));
+        assert_eq!(map.read(0x0101), Some(0));
+        assert_eq!(attached.lock().unwrap().0, 0);
     }
 }
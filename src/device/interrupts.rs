@@ -0,0 +1,165 @@
+//! The interrupt lines of the CPU, which devices pull to ask for attention.
+//!
+//! Both lines are wired-OR, like the open collector outputs on a real board:
+//! a line is active while at least one device pulls it. IRQ is level
+//! triggered, the CPU keeps taking interrupts while it's active and the I flag
+//! is clear, so a device has to let go once it has been served. NMI is edge
+//! triggered, the CPU takes one interrupt every time the line becomes active.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, Eq, PartialEq, Copy, Clone)]
+#[error("more than 64 devices on an interrupt line")]
+pub struct LineError;
+
+/// A line with a bit for every device that can pull it.
+#[derive(Default)]
+struct Wire {
+    pulled: AtomicU64,
+    /// the bits of the connected devices
+    used: AtomicU64,
+}
+
+impl Wire {
+    /// Connects to the lowest free bit, the ones of dropped lines are reused.
+    fn line(self: &Arc<Self>) -> Result<Line, LineError> {
+        let mut bit = 0;
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let free = !used;
+                if free == 0 {
+                    return None;
+                }
+                bit = free & free.wrapping_neg();
+                Some(used | bit)
+            })
+            .map_err(|_| LineError)?;
+        Ok(Line {
+            wire: Arc::clone(self),
+            bit,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        self.pulled.load(Ordering::Acquire) != 0
+    }
+}
+
+/// The IRQ and NMI lines of a machine. Clones share the lines, so devices
+/// can pull them from any thread.
+#[derive(Clone, Default)]
+pub struct Interrupts {
+    irq: Arc<Wire>,
+    nmi: Arc<Wire>,
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A connection to the IRQ line for a device.
+    pub fn irq_line(&self) -> Result<Line, LineError> {
+        self.irq.line()
+    }
+
+    /// A connection to the NMI line for a device.
+    pub fn nmi_line(&self) -> Result<Line, LineError> {
+        self.nmi.line()
+    }
+
+    /// Whether any device pulls the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.irq.is_active()
+    }
+
+    /// Whether any device pulls the NMI line.
+    pub fn nmi(&self) -> bool {
+        self.nmi.is_active()
+    }
+}
+
+/// The connection of a single device to an interrupt line. Dropping it lets
+/// go of the line and frees the connection for another device.
+pub struct Line {
+    wire: Arc<Wire>,
+    bit: u64,
+}
+
+impl Line {
+    /// Pulls the line or lets go of it.
+    pub fn set(&self, active: bool) {
+        if active {
+            self.wire.pulled.fetch_or(self.bit, Ordering::AcqRel);
+        } else {
+            self.wire.pulled.fetch_and(!self.bit, Ordering::AcqRel);
+        }
+    }
+
+    /// Whether this device pulls the line.
+    pub fn is_active(&self) -> bool {
+        self.wire.pulled.load(Ordering::Acquire) & self.bit != 0
+    }
+}
+
+impl Drop for Line {
+    fn drop(&mut self) {
+        self.set(false);
+        self.wire.used.fetch_and(!self.bit, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wired_or() {
+        let interrupts = Interrupts::new();
+        let timer = interrupts.irq_line().unwrap();
+        let keyboard = interrupts.irq_line().unwrap();
+        let button = interrupts.nmi_line().unwrap();
+        assert!(!interrupts.irq());
+
+        timer.set(true);
+        keyboard.set(true);
+        assert!(interrupts.irq());
+        assert!(!interrupts.nmi());
+        timer.set(false);
+        assert!(interrupts.irq());
+        assert!(!timer.is_active() && keyboard.is_active());
+        drop(keyboard);
+        assert!(!interrupts.irq());
+
+        button.set(true);
+        assert!(interrupts.clone().nmi());
+    }
+
+    #[test]
+    fn reuses_dropped_lines() {
+        let interrupts = Interrupts::new();
+        let mut lines: Vec<_> = (0..64).map(|_| interrupts.irq_line().unwrap()).collect();
+        assert_eq!(interrupts.irq_line().err(), Some(LineError));
+        // the NMI line has its own connections
+        assert!(interrupts.nmi_line().is_ok());
+
+        let keyboard = lines.remove(5);
+        keyboard.set(true);
+        drop(keyboard);
+        assert!(!interrupts.irq());
+        let timer = interrupts.irq_line().unwrap();
+        assert_eq!(timer.bit, 1 << 5);
+        assert!(!timer.is_active());
+        assert!(interrupts.irq_line().is_err());
+
+        lines.clear();
+        drop(timer);
+        // attaching and detaching doesn't use the line up
+        for _ in 0..200 {
+            assert_eq!(interrupts.irq_line().unwrap().bit, 1);
+        }
+    }
+}
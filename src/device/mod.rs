@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use thiserror::Error;
@@ -6,6 +7,7 @@ pub use ram::Ram;
 pub use rom::Rom;
 
 pub mod device_map;
+pub mod interrupts;
 pub mod ram;
 pub mod rom;
 pub mod vga;
@@ -22,11 +24,44 @@ pub enum WriteError {
 pub enum CreateError {
     #[error("filesystem error: {0}")]
     FsError(#[from] std::io::Error),
+    #[error("{}: {source}", path.display())]
+    FileError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: invalid font ({reason})", path.display())]
+    FontError { path: PathBuf, reason: String },
+    #[error("invalid machine config: {0}")]
+    ConfigError(String),
+    #[error("{0}")]
+    LineError(#[from] interrupts::LineError),
+    #[error("{name} doesn't fit below $10000")]
+    RangeError { name: String },
+    #[error("{first} and {second} overlap at ${address:04X}")]
+    OverlapError {
+        first: String,
+        second: String,
+        address: u16,
+    },
 }
 
 pub trait Device {
     fn read(&mut self, address: u16) -> Option<u8>;
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError>;
+
+    /// What `read` would return, without its side effects, so debuggers and
+    /// traces can look at memory without changing the machine. Devices that
+    /// can't tell return `None`.
+    fn peek(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// Lets `cycles` CPU cycles pass, for timers and other devices that do
+    /// things on their own.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Called when the machine is reset.
+    fn reset(&mut self) {}
 }
 
 impl<const N: usize> Device for [u8; N] {
@@ -34,6 +69,10 @@ impl<const N: usize> Device for [u8; N] {
         self.get(address as usize).copied()
     }
 
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.get(address as usize).copied()
+    }
+
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
         let d = self
             .get_mut(address as usize)
@@ -53,4 +92,16 @@ impl<T: Device> Device for Arc<Mutex<T>> {
         let mut s = self.lock().unwrap();
         s.write(address, data)
     }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.lock().unwrap().peek(address)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.lock().unwrap().tick(cycles)
+    }
+
+    fn reset(&mut self) {
+        self.lock().unwrap().reset()
+    }
 }
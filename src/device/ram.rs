@@ -1,5 +1,4 @@
 use super::{Device, WriteError};
-use std::cmp::min;
 
 pub struct Ram {
     start: u16,
@@ -13,24 +12,52 @@ impl Ram {
             data: vec![0; size as usize],
         }
     }
+
+    /// RAM that starts out with `data`, which can cover the whole 64K address
+    /// space.
+    pub fn from_vec(start: u16, data: Vec<u8>) -> Self {
+        Self { start, data }
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        (address as usize)
+            .checked_sub(self.start as usize)
+            .filter(|offset| *offset < self.data.len())
+    }
 }
 
 impl Device for Ram {
     fn read(&mut self, address: u16) -> Option<u8> {
-        let cc = self.start + min(self.data.len() as u16, 0xFFFF);
-        if address < self.start || address >= cc {
-            None
-        } else {
-            self.data.get((address - self.start) as usize).copied()
-        }
+        self.offset(address).map(|offset| self.data[offset])
     }
 
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
-        if address < self.start || address >= self.start + (self.data.len() as u16) {
-            Err(WriteError::InvalidAddress)
-        } else {
-            self.data[(address - self.start) as usize] = data;
-            Ok(())
-        }
+        let offset = self.offset(address).ok_or(WriteError::InvalidAddress)?;
+        self.data[offset] = data;
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.offset(address).map(|offset| self.data[offset])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::{Device, Ram, WriteError};
+
+    #[test]
+    fn bounds() {
+        let mut ram = Ram::new(0x0100, 0x0400);
+        assert_eq!(ram.read(0x00FF), None);
+        assert_eq!(ram.write(0x0500, 1), Err(WriteError::InvalidAddress));
+        ram.write(0x04FF, 0x42).unwrap();
+        assert_eq!(ram.read(0x04FF), Some(0x42));
+        assert_eq!(ram.peek(0x04FF), Some(0x42));
+        assert_eq!(ram.peek(0x0500), None);
+
+        let mut full = Ram::from_vec(0, vec![0; 0x10000]);
+        full.write(0xFFFF, 0x12).unwrap();
+        assert_eq!(full.read(0xFFFF), Some(0x12));
     }
 }
@@ -1,7 +1,7 @@
 use std::fs;
+use std::path::Path;
 
 use super::{CreateError, Device, WriteError};
-use std::cmp::min;
 
 pub struct Rom {
     start: u16,
@@ -9,8 +9,12 @@ pub struct Rom {
 }
 
 impl Rom {
-    pub fn new_file(start: u16, file: &str) -> Result<Self, CreateError> {
-        let data = fs::read(file)?;
+    pub fn new_file(start: u16, file: impl AsRef<Path>) -> Result<Self, CreateError> {
+        let path = file.as_ref();
+        let data = fs::read(path).map_err(|source| CreateError::FileError {
+            path: path.to_owned(),
+            source,
+        })?;
         Ok(Self { start, data })
     }
     pub fn from_vec(start: u16, data: Vec<u8>) -> Self {
@@ -33,19 +37,17 @@ impl Rom {
 
 impl Device for Rom {
     fn read(&mut self, address: u16) -> Option<u8> {
-        let cc = min(self.start as usize + self.data.len(), 0xFFFF) as u16;
-        // println!(
-        //     "min: {:#06X}, max: {:#06X}, r = {:#06X}",
-        //     self.start, cc, address
-        // );
-        if address < self.start || address >= cc {
-            None
-        } else {
-            Some(self.data[(address - self.start) as usize])
-        }
+        self.peek(address)
     }
 
     fn write(&mut self, _: u16, _: u8) -> Result<(), WriteError> {
         Err(WriteError::NotWritable)
     }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        (address as usize)
+            .checked_sub(self.start as usize)
+            .and_then(|offset| self.data.get(offset))
+            .copied()
+    }
 }
@@ -1,52 +1,47 @@
-use crate::device::{Device, WriteError};
+use crate::machine::Frontend;
 use olc_pixel_game_engine as olc;
 use std::collections::VecDeque;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
+pub mod keyboard;
+pub mod script;
 pub mod vecs;
 
-pub struct KeyUpdate(olc::Key, bool);
-
-pub struct Keyboard {
-    keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
-    addr: u16,
-}
-
-impl Keyboard {
-    pub fn new(addr: u16, keys: Arc<Mutex<VecDeque<KeyUpdate>>>) -> Self {
-        Self { keys, addr }
-    }
-}
-
-impl Device for Keyboard {
-    fn read(&mut self, address: u16) -> Option<u8> {
-        if address == self.addr {
-            let mut k = self.keys.lock().unwrap();
-            match k.pop_front() {
-                None => Some(0),
-                Some(kv) => {
-                    if kv.1 {
-                        k.push_front(KeyUpdate(kv.0, false));
-                        Some(0xE0)
-                    } else {
-                        Some(vecs::key_to_scancode(kv.0))
-                    }
-                }
-            }
-        } else {
-            None
-        }
-    }
-
-    fn write(&mut self, _: u16, _: u8) -> Result<(), WriteError> {
-        Err(WriteError::NotWritable)
-    }
-}
+pub use keyboard::{KeyUpdate, Keyboard, KeyboardMode, Typematic};
+pub use script::KeyScript;
 
 pub struct Vga {
     font: psf::Font,
     keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
     mem: Arc<Mutex<super::Ram>>,
+    /// what shift and insert pastes into
+    script: Option<Arc<Mutex<KeyScript>>>,
+    /// insert is down because of a paste
+    pasting: bool,
+    /// a paste types its own shift, the user's is passed on once it's done
+    paste_shift: bool,
+}
+
+/// The text on the host clipboard, from whichever clipboard tool is there.
+fn clipboard() -> Option<String> {
+    const TOOLS: &[&[&str]] = &[
+        &["wl-paste", "--no-newline"],
+        &["xclip", "-selection", "clipboard", "-out"],
+        &["xsel", "--clipboard", "--output"],
+        &["pbpaste"],
+        &["powershell", "-NoProfile", "-Command", "Get-Clipboard"],
+    ];
+    TOOLS.iter().find_map(|tool| {
+        let output = Command::new(tool[0]).args(&tool[1..]).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let text = String::from_utf8(output.stdout).ok()?;
+        Some(text.replace("\r\n", "\n"))
+    })
 }
 
 impl Vga {
@@ -55,7 +50,39 @@ impl Vga {
         keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
         mem: Arc<Mutex<super::Ram>>,
     ) -> Self {
-        Self { font, keys, mem }
+        Self {
+            font,
+            keys,
+            mem,
+            script: None,
+            pasting: false,
+            paste_shift: false,
+        }
+    }
+
+    /// Lets shift and insert type the clipboard through `script`.
+    pub fn with_script(mut self, script: Arc<Mutex<KeyScript>>) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// The characters on the screen, a line per row without the trailing
+    /// spaces, for when there's no window to look at.
+    pub fn text(&self) -> String {
+        let mem = self.mem.lock().unwrap();
+        let mut text = String::new();
+        for y in 0..25 {
+            let line: String = (0..80)
+                .map(|x| match mem.data.get((x + y * 80) * 2) {
+                    Some(c @ 0x20..=0x7E) => char::from(*c),
+                    Some(0) | None => ' ',
+                    Some(_) => '.',
+                })
+                .collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
     }
 
     fn draw(&self, x: i32, y: i32, char: u8, colors: u8) {
@@ -118,15 +145,46 @@ impl olc::Application for Vga {
                 self.draw(x, y, mem.data[i as usize], mem.data[i as usize + 1])
             }
         }
+        let shift = olc::get_key(olc::Key::SHIFT).held;
+        if self.paste_shift {
+            let pasting = match &self.script {
+                Some(script) => script.lock().unwrap().is_pasting(),
+                None => false,
+            };
+            // the paste let go of shift, press it again if it's still held
+            if !pasting {
+                self.paste_shift = false;
+                if shift {
+                    let mut ksr = self.keys.lock().unwrap();
+                    ksr.push_back(KeyUpdate::press(olc::Key::SHIFT))
+                }
+            }
+        }
         for k in vecs::KEYS {
             let state = olc::get_key(k);
+            // the insert of a paste doesn't reach the keyboard
+            if k == olc::Key::INS && state.pressed && shift {
+                if let (Some(script), Some(text)) = (&self.script, clipboard()) {
+                    script.lock().unwrap().paste(&text);
+                    self.pasting = true;
+                    self.paste_shift = true;
+                    continue;
+                }
+            }
+            if k == olc::Key::SHIFT && self.paste_shift {
+                continue;
+            }
+            if k == olc::Key::INS && self.pasting {
+                self.pasting = !state.released;
+                continue;
+            }
             if state.pressed {
                 let mut ksr = self.keys.lock().unwrap();
-                ksr.push_back(KeyUpdate(k, false))
+                ksr.push_back(KeyUpdate::press(k))
             }
             if state.released {
                 let mut ksr = self.keys.lock().unwrap();
-                ksr.push_back(KeyUpdate(k, true))
+                ksr.push_back(KeyUpdate::release(k))
             }
         }
         Ok(())
@@ -136,3 +194,31 @@ impl olc::Application for Vga {
         Ok(())
     }
 }
+
+/// The window that shows a [`Vga`], in its own thread.
+pub struct Window {
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Window {
+    pub fn spawn(mut vga: Vga) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = Arc::clone(&running);
+        let handle = thread::spawn(move || {
+            olc::start("rust6502", &mut vga, 8 * 80, 14 * 25, 4, 4).unwrap();
+            running_clone.store(false, Ordering::Release);
+        });
+        Self { running, handle }
+    }
+}
+
+impl Frontend for Window {
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    fn join(self: Box<Self>) {
+        self.handle.join().unwrap();
+    }
+}
@@ -0,0 +1,531 @@
+//! The keyboard controller. It turns the key updates from the window into
+//! bytes and has three registers:
+//!
+//! - `start`: data, reading it takes the next byte, 0 if there's none
+//! - `start + 1`: status, bit 0 is set while there are bytes to read and bit 7
+//!   while an interrupt is pending. Writing anything acknowledges the
+//!   interrupt.
+//! - `start + 2`: control, setting bit 0 enables the interrupt and bit 1 turns
+//!   on caps lock
+//!
+//! What the keys turn into depends on the [`KeyboardMode`]. With the interrupt
+//! enabled the controller pulls IRQ when bytes come in and keeps pulling it
+//! until it's acknowledged. If there are bytes left after that it interrupts
+//! again.
+
+use super::vecs;
+use crate::device::interrupts::Line;
+use crate::device::{Device, WriteError};
+use olc_pixel_game_engine as olc;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const CONTROL: u16 = 2;
+
+/// status: there are bytes to read
+pub const AVAILABLE: u8 = 0x01;
+/// status: the controller is interrupting
+pub const PENDING: u8 = 0x80;
+/// control: interrupt when bytes come in
+pub const IRQ_ENABLE: u8 = 0x01;
+/// control: caps lock is on, the window can't tell us
+pub const CAPS_LOCK: u8 = 0x02;
+
+/// Held keys stop repeating while this many bytes are waiting to be read.
+const REPEAT_LIMIT: usize = 16;
+
+/// The bytes the keyboard sends for the keys.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardMode {
+    /// The scancodes from [`vecs::key_to_scancode`], a press is the scancode
+    /// and a release is $E0 followed by the scancode.
+    #[default]
+    Raw,
+    /// PS/2 scancode set 2, like a real keyboard sends them. A release is $F0
+    /// followed by the make code, extended keys have an $E0 in front of both.
+    Ps2,
+    /// The characters the keys type, with shift, ctrl and caps lock applied.
+    /// Releases and the keys that don't type anything send nothing, held keys
+    /// repeat.
+    Ascii,
+}
+
+/// How a held key repeats in the ascii mode. Both are in cycles, so it's
+/// slower in real time when the machine runs slower.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Typematic {
+    /// from the press to the first repeat
+    pub delay: u64,
+    /// between the repeats
+    pub interval: u64,
+}
+
+impl Default for Typematic {
+    /// Half a second and 30 characters a second at 1 MHz.
+    fn default() -> Self {
+        Self {
+            delay: 500_000,
+            interval: 33_333,
+        }
+    }
+}
+
+/// A key that was pressed or released in the window.
+pub struct KeyUpdate(olc::Key, bool);
+
+impl KeyUpdate {
+    pub fn press(key: olc::Key) -> Self {
+        Self(key, false)
+    }
+
+    pub fn release(key: olc::Key) -> Self {
+        Self(key, true)
+    }
+}
+
+/// Turns key updates into bytes, and keeps the state the ascii mode needs.
+#[derive(Copy, Clone)]
+struct Encoder {
+    mode: KeyboardMode,
+    shift: bool,
+    ctrl: bool,
+    caps: bool,
+}
+
+impl Encoder {
+    fn encode(&mut self, update: &KeyUpdate, bytes: &mut VecDeque<u8>) {
+        let KeyUpdate(key, released) = *update;
+        match self.mode {
+            KeyboardMode::Raw => {
+                if released {
+                    bytes.push_back(0xE0);
+                }
+                bytes.push_back(vecs::key_to_scancode(key));
+            }
+            KeyboardMode::Ps2 => match vecs::key_to_set2(key) {
+                [0xE1, ..] if released => {}
+                [0xE0, code] if released => bytes.extend(&[0xE0, 0xF0, *code]),
+                [code] if released => bytes.extend(&[0xF0, *code]),
+                make => bytes.extend(make),
+            },
+            KeyboardMode::Ascii => match key {
+                olc::Key::SHIFT => self.shift = !released,
+                olc::Key::CTRL => self.ctrl = !released,
+                _ if released => {}
+                _ => bytes.extend(self.ascii(key)),
+            },
+        }
+    }
+
+    fn ascii(&self, key: olc::Key) -> Option<u8> {
+        let (plain, shifted) = vecs::key_to_ascii(key)?;
+        if !plain.is_ascii_alphabetic() {
+            return Some(if self.shift { shifted } else { plain });
+        }
+        if self.ctrl {
+            Some(plain & 0x1F)
+        } else if self.shift != self.caps {
+            Some(shifted)
+        } else {
+            Some(plain)
+        }
+    }
+}
+
+pub struct Keyboard {
+    keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
+    addr: u16,
+    encoder: Encoder,
+    typematic: Typematic,
+    /// the bytes the keys turned into that haven't been read yet
+    bytes: VecDeque<u8>,
+    control: u8,
+    pending: bool,
+    irq: Option<Line>,
+    /// cycles since the keyboard was created
+    now: u64,
+    /// the key that repeats and when it does that next
+    held: Option<(olc::Key, u64)>,
+}
+
+impl Keyboard {
+    pub fn new(addr: u16, keys: Arc<Mutex<VecDeque<KeyUpdate>>>) -> Self {
+        Self {
+            keys,
+            addr,
+            encoder: Encoder {
+                mode: KeyboardMode::Raw,
+                shift: false,
+                ctrl: false,
+                caps: false,
+            },
+            typematic: Typematic::default(),
+            bytes: VecDeque::new(),
+            control: 0,
+            pending: false,
+            irq: None,
+            now: 0,
+            held: None,
+        }
+    }
+
+    /// Connects the interrupt output to a line.
+    pub fn with_irq(mut self, line: Line) -> Self {
+        self.irq = Some(line);
+        self
+    }
+
+    pub fn with_mode(mut self, mode: KeyboardMode) -> Self {
+        self.encoder.mode = mode;
+        self
+    }
+
+    pub fn with_typematic(mut self, typematic: Typematic) -> Self {
+        self.typematic = typematic;
+        self
+    }
+
+    /// Takes the key updates out of the queue.
+    fn fill(&mut self) {
+        let mut keys = self.keys.lock().unwrap();
+        for update in keys.drain(..) {
+            let before = self.bytes.len();
+            self.encoder.encode(&update, &mut self.bytes);
+            if self.encoder.mode != KeyboardMode::Ascii {
+                continue;
+            }
+            // the last key that typed something repeats until it's released
+            match (update, self.held) {
+                (KeyUpdate(key, true), Some((held, _))) if key == held => self.held = None,
+                (KeyUpdate(key, false), _) if self.bytes.len() > before => {
+                    self.held = Some((key, self.now + self.typematic.delay))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn repeat(&mut self) {
+        let (key, mut next) = match self.held {
+            Some(held) => held,
+            None => return,
+        };
+        while next <= self.now {
+            if self.bytes.len() < REPEAT_LIMIT {
+                self.encoder.encode(&KeyUpdate::press(key), &mut self.bytes);
+            }
+            next += self.typematic.interval.max(1);
+        }
+        self.held = Some((key, next));
+    }
+
+    /// The byte a read of the data register would return, without taking
+    /// anything out of the queue.
+    fn next_byte(&self) -> Option<u8> {
+        if let Some(byte) = self.bytes.front() {
+            return Some(*byte);
+        }
+        // the first key in the queue that turns into something
+        let mut encoder = self.encoder;
+        let mut bytes = VecDeque::new();
+        for update in self.keys.lock().unwrap().iter() {
+            encoder.encode(update, &mut bytes);
+            if let Some(byte) = bytes.front() {
+                return Some(*byte);
+            }
+        }
+        None
+    }
+
+    fn status(&self, available: bool) -> u8 {
+        let mut status = 0;
+        if available {
+            status |= AVAILABLE;
+        }
+        if self.pending {
+            status |= PENDING;
+        }
+        status
+    }
+
+    fn update_irq(&self) {
+        if let Some(line) = &self.irq {
+            line.set(self.pending && self.control & IRQ_ENABLE != 0);
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address.wrapping_sub(self.addr) {
+            DATA => {
+                self.fill();
+                Some(self.bytes.pop_front().unwrap_or(0))
+            }
+            STATUS => {
+                self.fill();
+                Some(self.status(!self.bytes.is_empty()))
+            }
+            CONTROL => Some(self.control),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        match address.wrapping_sub(self.addr) {
+            STATUS => self.pending = false,
+            CONTROL => {
+                // keys that are still queued were typed with the old caps lock
+                self.fill();
+                self.control = data & (IRQ_ENABLE | CAPS_LOCK);
+                self.encoder.caps = self.control & CAPS_LOCK != 0;
+            }
+            _ => return Err(WriteError::NotWritable),
+        }
+        self.update_irq();
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        match address.wrapping_sub(self.addr) {
+            DATA => Some(self.next_byte().unwrap_or(0)),
+            STATUS => Some(self.status(self.next_byte().is_some())),
+            CONTROL => Some(self.control),
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.fill();
+        self.now += cycles;
+        self.repeat();
+        if !self.bytes.is_empty() {
+            self.pending = true;
+        }
+        self.update_irq();
+    }
+
+    fn reset(&mut self) {
+        self.bytes.clear();
+        self.held = None;
+        self.control = 0;
+        self.encoder.caps = false;
+        self.pending = false;
+        self.update_irq();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::interrupts::Interrupts;
+
+    /// Queues the updates and reads everything the keyboard has, then
+    /// acknowledges.
+    fn typed(keyboard: &mut Keyboard, updates: Vec<KeyUpdate>) -> Vec<u8> {
+        keyboard.keys.lock().unwrap().extend(updates);
+        let mut bytes = vec![];
+        while keyboard.read(0x11).unwrap() & AVAILABLE != 0 {
+            bytes.push(keyboard.read(0x10).unwrap());
+        }
+        keyboard.write(0x11, 0).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn peek() {
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let mut keyboard = Keyboard::new(0x10, Arc::clone(&keys));
+        assert_eq!(keyboard.peek(0x10), Some(0));
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::release(olc::Key::A));
+
+        // looking doesn't take the key out of the queue
+        assert_eq!(keyboard.peek(0x10), Some(0xE0));
+        assert_eq!(keyboard.peek(0x10), Some(0xE0));
+        assert_eq!(keyboard.read(0x10), Some(0xE0));
+        let code = keyboard.peek(0x10);
+        assert_eq!(code, Some(vecs::key_to_scancode(olc::Key::A)));
+        assert_eq!(keyboard.read(0x10), code);
+        assert_eq!(keyboard.peek(0x10), Some(0));
+        assert_eq!(keyboard.peek(0x13), None);
+    }
+
+    #[test]
+    fn interrupt() {
+        let interrupts = Interrupts::new();
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let mut keyboard =
+            Keyboard::new(0x10, Arc::clone(&keys)).with_irq(interrupts.irq_line().unwrap());
+
+        // nothing happens until the interrupt is enabled
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::press(olc::Key::A));
+        keyboard.tick(1);
+        assert!(!interrupts.irq());
+        assert_eq!(keyboard.read(0x11), Some(AVAILABLE | PENDING));
+        keyboard.write(0x12, IRQ_ENABLE).unwrap();
+        assert!(interrupts.irq());
+        assert_eq!(keyboard.read(0x12), Some(IRQ_ENABLE));
+
+        // the line stays active until the handler acknowledges
+        let code = vecs::key_to_scancode(olc::Key::A);
+        assert_eq!(keyboard.read(0x10), Some(code));
+        keyboard.tick(1);
+        assert!(interrupts.irq());
+        keyboard.write(0x11, 0).unwrap();
+        assert!(!interrupts.irq());
+        keyboard.tick(1);
+        assert!(!interrupts.irq());
+        assert_eq!(keyboard.read(0x11), Some(0));
+
+        // a release is two bytes, one interrupt
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::release(olc::Key::A));
+        keyboard.tick(1);
+        assert!(interrupts.irq());
+        assert_eq!(keyboard.read(0x10), Some(0xE0));
+        assert_eq!(keyboard.read(0x10), Some(code));
+        keyboard.write(0x11, 0).unwrap();
+        keyboard.tick(1);
+        assert!(!interrupts.irq());
+
+        // bytes that are left over interrupt again
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::press(olc::Key::B));
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::press(olc::Key::C));
+        keyboard.tick(1);
+        keyboard.read(0x10);
+        keyboard.write(0x11, 0).unwrap();
+        assert!(!interrupts.irq());
+        keyboard.tick(1);
+        assert!(interrupts.irq());
+
+        keyboard.reset();
+        assert!(!interrupts.irq());
+        assert_eq!(keyboard.read(0x11), Some(0));
+        assert_eq!(keyboard.write(0x10, 0), Err(WriteError::NotWritable));
+    }
+
+    #[test]
+    fn ps2() {
+        use olc::Key;
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let mut keyboard = Keyboard::new(0x10, Arc::clone(&keys)).with_mode(KeyboardMode::Ps2);
+
+        let shift_a = vec![
+            KeyUpdate::press(Key::SHIFT),
+            KeyUpdate::press(Key::A),
+            KeyUpdate::release(Key::A),
+            KeyUpdate::release(Key::SHIFT),
+        ];
+        assert_eq!(
+            typed(&mut keyboard, shift_a),
+            [0x12, 0x1C, 0xF0, 0x1C, 0xF0, 0x12]
+        );
+        let up = vec![KeyUpdate::press(Key::UP), KeyUpdate::release(Key::UP)];
+        assert_eq!(typed(&mut keyboard, up), [0xE0, 0x75, 0xE0, 0xF0, 0x75]);
+        let pause = vec![KeyUpdate::press(Key::PAUSE), KeyUpdate::release(Key::PAUSE)];
+        let codes = [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77];
+        assert_eq!(typed(&mut keyboard, pause), codes);
+
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::release(Key::ENTER));
+        assert_eq!(keyboard.peek(0x10), Some(0xE0));
+    }
+
+    #[test]
+    fn ascii() {
+        use olc::Key;
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let mut keyboard = Keyboard::new(0x10, Arc::clone(&keys)).with_mode(KeyboardMode::Ascii);
+
+        let press = |keys: &[Key]| keys.iter().map(|k| KeyUpdate::press(*k)).collect();
+        assert_eq!(
+            typed(
+                &mut keyboard,
+                press(&[Key::H, Key::K1, Key::UP, Key::SPACE])
+            ),
+            b"h1 "
+        );
+        let shift = vec![
+            KeyUpdate::press(Key::SHIFT),
+            KeyUpdate::press(Key::H),
+            KeyUpdate::press(Key::K1),
+            KeyUpdate::release(Key::SHIFT),
+            KeyUpdate::release(Key::H),
+            KeyUpdate::press(Key::H),
+        ];
+        assert_eq!(typed(&mut keyboard, shift), b"H!h");
+        let ctrl = vec![KeyUpdate::press(Key::CTRL), KeyUpdate::press(Key::C)];
+        assert_eq!(typed(&mut keyboard, ctrl), [0x03]);
+        let ctrl = vec![KeyUpdate::release(Key::CTRL), KeyUpdate::press(Key::RETURN)];
+        assert_eq!(typed(&mut keyboard, ctrl), b"\r");
+
+        keyboard.write(0x12, CAPS_LOCK).unwrap();
+        let caps = vec![
+            KeyUpdate::press(Key::A),
+            KeyUpdate::press(Key::K2),
+            KeyUpdate::press(Key::SHIFT),
+            KeyUpdate::press(Key::A),
+            KeyUpdate::press(Key::K2),
+        ];
+        assert_eq!(typed(&mut keyboard, caps), b"A2a@");
+
+        // modifiers don't type anything, the peek skips them
+        keys.lock()
+            .unwrap()
+            .push_back(KeyUpdate::release(Key::SHIFT));
+        keys.lock().unwrap().push_back(KeyUpdate::press(Key::B));
+        assert_eq!(keyboard.peek(0x11), Some(AVAILABLE));
+        assert_eq!(keyboard.peek(0x10), Some(b'B'));
+    }
+
+    #[test]
+    fn typematic() {
+        use olc::Key;
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let typematic = Typematic {
+            delay: 100,
+            interval: 10,
+        };
+        let mut keyboard = Keyboard::new(0x10, Arc::clone(&keys))
+            .with_mode(KeyboardMode::Ascii)
+            .with_typematic(typematic);
+
+        keys.lock().unwrap().push_back(KeyUpdate::press(Key::A));
+        keyboard.tick(99);
+        assert_eq!(typed(&mut keyboard, vec![]), b"a");
+        keyboard.tick(1);
+        assert_eq!(typed(&mut keyboard, vec![]), b"a");
+        keyboard.tick(35);
+        assert_eq!(typed(&mut keyboard, vec![]), b"aaa");
+
+        // the last key repeats, releasing the other one doesn't stop it
+        keys.lock().unwrap().push_back(KeyUpdate::press(Key::B));
+        keys.lock().unwrap().push_back(KeyUpdate::release(Key::A));
+        keyboard.tick(100);
+        assert_eq!(typed(&mut keyboard, vec![]), b"bb");
+        keys.lock().unwrap().push_back(KeyUpdate::press(Key::SHIFT));
+        keyboard.tick(10);
+        assert_eq!(typed(&mut keyboard, vec![]), b"B");
+
+        // a program that doesn't read doesn't get flooded
+        keyboard.tick(10_000);
+        assert_eq!(typed(&mut keyboard, vec![]).len(), REPEAT_LIMIT);
+        keys.lock().unwrap().push_back(KeyUpdate::release(Key::B));
+        keyboard.tick(10_000);
+        assert_eq!(typed(&mut keyboard, vec![]), b"");
+    }
+}
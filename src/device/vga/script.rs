@@ -0,0 +1,368 @@
+//! Key presses on a schedule, so programs can be driven without anyone at the
+//! window. A script has a line per event, the cycle and what happens then:
+//!
+//! ```text
+//! # comments start with a hash
+//! 100000 type "HELLO\n"
+//! 200000 press SHIFT
+//! +5000 tap A
+//! +5000 release SHIFT
+//! ```
+//!
+//! `type` types the text a key at a time, `press`, `release` and `tap` (press
+//! and release) take the name of a key. A cycle with a `+` in front counts from
+//! the end of the line before.
+
+use super::vecs;
+use super::KeyUpdate;
+use crate::device::{Device, WriteError};
+use olc_pixel_game_engine as olc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// The cycles from one typed key to the next, half of it is spent holding it
+/// down.
+pub const KEYSTROKE: u64 = 10_000;
+
+#[derive(Debug, Error, Eq, PartialEq, Clone)]
+pub enum ScriptError {
+    #[error("line {line}: {reason}")]
+    Syntax { line: usize, reason: String },
+    #[error("there's no key for {0:?}")]
+    Untypeable(char),
+    #[error("the keys don't fit before cycle {}", u64::MAX)]
+    TooLate,
+}
+
+/// Pushes key updates into the keyboard queue when their cycle comes. It's
+/// ticked with the devices, so the cycles are the ones the machine has run.
+pub struct KeyScript {
+    keys: Arc<Mutex<VecDeque<KeyUpdate>>>,
+    /// oldest first, updates for the same cycle stay in the order they were
+    /// added in
+    events: VecDeque<(u64, KeyUpdate)>,
+    now: u64,
+    /// the cycle the last paste is done at
+    pasted: u64,
+}
+
+/// The key that types `c` and whether it needs shift.
+fn stroke(c: char) -> Option<(olc::Key, bool)> {
+    let c = match c {
+        '\n' => b'\r',
+        c if c.is_ascii() => c as u8,
+        _ => return None,
+    };
+    // the main keys before the ones on the keypad
+    let (keypad, main): (Vec<_>, Vec<_>) = vecs::KEYS
+        .iter()
+        .zip(vecs::KEY_NAMES)
+        .partition(|(_, name)| name.starts_with("NP"));
+    main.into_iter().chain(keypad).find_map(|(key, _)| {
+        let (plain, shifted) = vecs::key_to_ascii(*key)?;
+        if plain == c {
+            Some((*key, false))
+        } else if shifted == c {
+            Some((*key, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// The key with this name, like `A`, `K1` or `RETURN`.
+fn key_named(name: &str) -> Option<olc::Key> {
+    vecs::KEYS
+        .iter()
+        .zip(vecs::KEY_NAMES)
+        // not NONE
+        .skip(1)
+        .find(|(_, key)| key.eq_ignore_ascii_case(name))
+        .map(|(key, _)| *key)
+}
+
+/// A quoted string with `\n`, `\r`, `\t`, `\\` and `\"` escapes.
+fn unquote(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                c @ ('\\' | '"') => c,
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+impl KeyScript {
+    pub fn new(keys: Arc<Mutex<VecDeque<KeyUpdate>>>) -> Self {
+        Self {
+            keys,
+            events: VecDeque::new(),
+            now: 0,
+            pasted: 0,
+        }
+    }
+
+    /// Whether there are updates that haven't been pushed yet.
+    pub fn is_done(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Pushes `update` at `cycle`, or with the next tick if that has passed.
+    pub fn key(&mut self, cycle: u64, update: KeyUpdate) {
+        let index = self.events.partition_point(|(at, _)| *at <= cycle);
+        self.events.insert(index, (cycle, update));
+    }
+
+    /// Types `text` starting at `cycle`, with shift for the characters that
+    /// need it. Returns the cycle after the last key, nothing is typed if
+    /// that's past the last cycle there is.
+    pub fn type_text(&mut self, cycle: u64, text: &str) -> Result<u64, ScriptError> {
+        let strokes = text
+            .chars()
+            .map(|c| stroke(c).ok_or(ScriptError::Untypeable(c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        KEYSTROKE
+            .checked_mul(strokes.len() as u64)
+            .and_then(|length| cycle.checked_add(length))
+            .ok_or(ScriptError::TooLate)?;
+        let mut at = cycle;
+        for (key, shift) in strokes {
+            if shift {
+                self.key(at, KeyUpdate::press(olc::Key::SHIFT));
+            }
+            self.key(at, KeyUpdate::press(key));
+            self.key(at + KEYSTROKE / 2, KeyUpdate::release(key));
+            if shift {
+                self.key(at + KEYSTROKE / 2, KeyUpdate::release(olc::Key::SHIFT));
+            }
+            at += KEYSTROKE;
+        }
+        Ok(at)
+    }
+
+    /// Types `text` after everything that's already scheduled, leaving out
+    /// the characters there's no key for. Shift is let go of first, the paste
+    /// hotkey holds it down, and it's up at the end.
+    pub fn paste(&mut self, text: &str) {
+        let start = match self.events.back() {
+            Some((at, _)) => self.now.max(at.saturating_add(1)),
+            None => self.now,
+        };
+        self.key(start, KeyUpdate::release(olc::Key::SHIFT));
+        let text: String = text.chars().filter(|c| stroke(*c).is_some()).collect();
+        // after a script that goes on forever there's no time left for it
+        let end = self.type_text(start, &text).unwrap_or(start);
+        self.pasted = self.pasted.max(end.max(start.saturating_add(1)));
+    }
+
+    /// Whether a paste still has keys to push. Shift is the paste's until
+    /// then, the window holds back the user's.
+    pub fn is_pasting(&self) -> bool {
+        self.now < self.pasted
+    }
+
+    /// Schedules the events of a script, see the module docs.
+    pub fn load(&mut self, script: &str) -> Result<(), ScriptError> {
+        let mut end: u64 = 0;
+        for (index, line) in script.lines().enumerate() {
+            let error = |reason: &str| ScriptError::Syntax {
+                line: index + 1,
+                reason: reason.to_owned(),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (cycle, rest) = line
+                .split_once(' ')
+                .ok_or_else(|| error("expected an event"))?;
+            let (action, argument) = rest.trim().split_once(' ').unwrap_or((rest, ""));
+            let argument = argument.trim();
+            let cycle = match cycle.strip_prefix('+') {
+                Some(offset) => offset
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|offset| end.checked_add(offset)),
+                None => cycle.parse().ok(),
+            }
+            .ok_or_else(|| error(&format!("invalid cycle `{}`", cycle)))?;
+            let key =
+                || key_named(argument).ok_or_else(|| error(&format!("no key `{}`", argument)));
+            end = match action {
+                "type" => {
+                    let text =
+                        unquote(argument).ok_or_else(|| error("expected a quoted string"))?;
+                    self.type_text(cycle, &text)
+                        .map_err(|e| error(&e.to_string()))?
+                }
+                "press" => {
+                    self.key(cycle, KeyUpdate::press(key()?));
+                    cycle
+                }
+                "release" => {
+                    self.key(cycle, KeyUpdate::release(key()?));
+                    cycle
+                }
+                "tap" => {
+                    let key = key()?;
+                    let end = cycle
+                        .checked_add(KEYSTROKE)
+                        .ok_or_else(|| error(&ScriptError::TooLate.to_string()))?;
+                    self.key(cycle, KeyUpdate::press(key));
+                    self.key(cycle + KEYSTROKE / 2, KeyUpdate::release(key));
+                    end
+                }
+                _ => return Err(error(&format!("unknown event `{}`", action))),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Device for KeyScript {
+    fn read(&mut self, _: u16) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _: u16, _: u8) -> Result<(), WriteError> {
+        Err(WriteError::InvalidAddress)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.now += cycles;
+        while matches!(self.events.front(), Some((at, _)) if *at <= self.now) {
+            let (_, update) = self.events.pop_front().unwrap();
+            self.keys.lock().unwrap().push_back(update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::vga::{Keyboard, KeyboardMode};
+    use olc::Key;
+
+    fn script() -> (KeyScript, Keyboard) {
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let keyboard = Keyboard::new(0x10, Arc::clone(&keys)).with_mode(KeyboardMode::Ascii);
+        (KeyScript::new(keys), keyboard)
+    }
+
+    /// Everything the keyboard has.
+    fn typed(keyboard: &mut Keyboard) -> String {
+        let mut text = String::new();
+        while keyboard.read(0x11).unwrap() & 1 != 0 {
+            text.push(char::from(keyboard.read(0x10).unwrap()));
+        }
+        text
+    }
+
+    #[test]
+    fn schedule() {
+        let (mut script, mut keyboard) = script();
+        script.key(200, KeyUpdate::press(Key::B));
+        script.key(100, KeyUpdate::press(Key::A));
+        script.key(200, KeyUpdate::press(Key::C));
+        script.tick(99);
+        assert_eq!(typed(&mut keyboard), "");
+        script.tick(1);
+        assert_eq!(typed(&mut keyboard), "a");
+        script.tick(150);
+        assert_eq!(typed(&mut keyboard), "bc");
+        assert!(script.is_done());
+
+        // text takes a keystroke per character
+        let end = script.type_text(1000, "Hi!\n").unwrap();
+        assert_eq!(end, 1000 + 4 * KEYSTROKE);
+        script.tick(1000);
+        assert_eq!(typed(&mut keyboard), "H");
+        script.tick(KEYSTROKE);
+        assert_eq!(typed(&mut keyboard), "i");
+        script.tick(2 * KEYSTROKE);
+        assert_eq!(typed(&mut keyboard), "!\r");
+        assert_eq!(
+            script.type_text(0, "a,b"),
+            Err(ScriptError::Untypeable(','))
+        );
+    }
+
+    #[test]
+    fn load() {
+        let (mut script, mut keyboard) = script();
+        let text = r#"
+            # a comment
+            100 type "AB"
+            +0 press SHIFT
+            +10 tap k1
+            +10 release shift
+            50000 type "a\tb\n"
+        "#;
+        script.load(text).unwrap();
+        script.tick(100);
+        assert_eq!(typed(&mut keyboard), "A");
+        script.tick(KEYSTROKE);
+        assert_eq!(typed(&mut keyboard), "B");
+        script.tick(KEYSTROKE + 10);
+        assert_eq!(typed(&mut keyboard), "!");
+        script.tick(50_000 - script.now);
+        assert_eq!(typed(&mut keyboard), "a");
+        script.tick(3 * KEYSTROKE);
+        assert_eq!(typed(&mut keyboard), "\tb\r");
+        assert!(!script.is_done());
+        script.tick(KEYSTROKE / 2);
+        assert!(script.is_done());
+
+        let mut error = |text: &str| script.load(text).unwrap_err().to_string();
+        assert_eq!(error("\n10 press"), "line 2: no key ``");
+        assert_eq!(error("x tap A"), "line 1: invalid cycle `x`");
+        assert_eq!(error("10 jump A"), "line 1: unknown event `jump`");
+        assert_eq!(error("10 type HELLO"), "line 1: expected a quoted string");
+        assert_eq!(error("10 type \"a;\""), "line 1: there's no key for ';'");
+        // nothing wraps around
+        let max = u64::MAX;
+        assert_eq!(
+            error(&format!("{} tap A", max)),
+            format!("line 1: the keys don't fit before cycle {}", max)
+        );
+        assert_eq!(
+            error(&format!("{} press A\n+{} press B", max - 1, max)),
+            format!("line 2: invalid cycle `+{}`", max)
+        );
+        assert_eq!(
+            error(&format!("{} type \"ab\"", max - KEYSTROKE)),
+            format!("line 1: the keys don't fit before cycle {}", max)
+        );
+    }
+
+    #[test]
+    fn paste() {
+        let (mut script, mut keyboard) = script();
+        script.tick(500);
+        // the hotkey is shift and insert
+        script
+            .keys
+            .lock()
+            .unwrap()
+            .push_back(KeyUpdate::press(Key::SHIFT));
+        script.paste("a,b");
+        script.paste("C");
+        assert!(script.is_pasting());
+        script.tick(2 * KEYSTROKE);
+        assert!(script.is_pasting());
+        script.tick(KEYSTROKE);
+        assert!(!script.is_pasting());
+        assert_eq!(typed(&mut keyboard), "abC");
+    }
+}
@@ -28,6 +28,19 @@ pub const KEYS: [olc::Key; 0x55] = [
     NP_SUB, NP_DECIMAL, PERIOD,
 ];
 
+/// The names of [`KEYS`], in the same order, for the key script. The keypad
+/// keys start with `NP`.
+#[rustfmt::skip]
+pub const KEY_NAMES: [&str; 0x55] = [
+    "NONE", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q",
+    "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "K0", "K1", "K2", "K3", "K4", "K5", "K6", "K7",
+    "K8", "K9", "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12", "UP",
+    "DOWN", "LEFT", "RIGHT", "SPACE", "TAB", "SHIFT", "CTRL", "INS", "DEL", "HOME", "END", "PGUP",
+    "PGDN", "BACK", "ESCAPE", "RETURN", "ENTER", "PAUSE", "SCROLL", "NP0", "NP1", "NP2", "NP3",
+    "NP4", "NP5", "NP6", "NP7", "NP8", "NP9", "NP_MUL", "NP_DIV", "NP_ADD", "NP_SUB", "NP_DECIMAL",
+    "PERIOD",
+];
+
 pub fn key_to_scancode(key: olc::Key) -> u8 {
     match key {
         NONE => 0,
@@ -117,3 +130,165 @@ pub fn key_to_scancode(key: olc::Key) -> u8 {
         PERIOD => 84,
     }
 }
+
+/// The PS/2 scancode set 2 make code of a key, with the $E0 prefix for the
+/// extended keys. The keys that have a left and a right version are the left
+/// one, ENTER is the one on the keypad.
+pub fn key_to_set2(key: olc::Key) -> &'static [u8] {
+    match key {
+        NONE => &[],
+        A => &[0x1C],
+        B => &[0x32],
+        C => &[0x21],
+        D => &[0x23],
+        E => &[0x24],
+        F => &[0x2B],
+        G => &[0x34],
+        H => &[0x33],
+        I => &[0x43],
+        J => &[0x3B],
+        K => &[0x42],
+        L => &[0x4B],
+        M => &[0x3A],
+        N => &[0x31],
+        O => &[0x44],
+        P => &[0x4D],
+        Q => &[0x15],
+        R => &[0x2D],
+        S => &[0x1B],
+        T => &[0x2C],
+        U => &[0x3C],
+        V => &[0x2A],
+        W => &[0x1D],
+        X => &[0x22],
+        Y => &[0x35],
+        Z => &[0x1A],
+        K0 => &[0x45],
+        K1 => &[0x16],
+        K2 => &[0x1E],
+        K3 => &[0x26],
+        K4 => &[0x25],
+        K5 => &[0x2E],
+        K6 => &[0x36],
+        K7 => &[0x3D],
+        K8 => &[0x3E],
+        K9 => &[0x46],
+        F1 => &[0x05],
+        F2 => &[0x06],
+        F3 => &[0x04],
+        F4 => &[0x0C],
+        F5 => &[0x03],
+        F6 => &[0x0B],
+        F7 => &[0x83],
+        F8 => &[0x0A],
+        F9 => &[0x01],
+        F10 => &[0x09],
+        F11 => &[0x78],
+        F12 => &[0x07],
+        UP => &[0xE0, 0x75],
+        DOWN => &[0xE0, 0x72],
+        LEFT => &[0xE0, 0x6B],
+        RIGHT => &[0xE0, 0x74],
+        SPACE => &[0x29],
+        TAB => &[0x0D],
+        SHIFT => &[0x12],
+        CTRL => &[0x14],
+        INS => &[0xE0, 0x70],
+        DEL => &[0xE0, 0x71],
+        HOME => &[0xE0, 0x6C],
+        END => &[0xE0, 0x69],
+        PGUP => &[0xE0, 0x7D],
+        PGDN => &[0xE0, 0x7A],
+        BACK => &[0x66],
+        ESCAPE => &[0x76],
+        RETURN => &[0x5A],
+        ENTER => &[0xE0, 0x5A],
+        // pause sends its make and break codes together when it's pressed,
+        // and nothing when it's released
+        PAUSE => &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77],
+        SCROLL => &[0x7E],
+        NP0 => &[0x70],
+        NP1 => &[0x69],
+        NP2 => &[0x72],
+        NP3 => &[0x7A],
+        NP4 => &[0x6B],
+        NP5 => &[0x73],
+        NP6 => &[0x74],
+        NP7 => &[0x6C],
+        NP8 => &[0x75],
+        NP9 => &[0x7D],
+        NP_MUL => &[0x7C],
+        NP_DIV => &[0xE0, 0x4A],
+        NP_ADD => &[0x79],
+        NP_SUB => &[0x7B],
+        NP_DECIMAL => &[0x71],
+        PERIOD => &[0x49],
+    }
+}
+
+/// The character a key types without and with shift on a US layout, `None`
+/// for the keys that don't type anything.
+pub fn key_to_ascii(key: olc::Key) -> Option<(u8, u8)> {
+    let chars = match key {
+        A => b"aA",
+        B => b"bB",
+        C => b"cC",
+        D => b"dD",
+        E => b"eE",
+        F => b"fF",
+        G => b"gG",
+        H => b"hH",
+        I => b"iI",
+        J => b"jJ",
+        K => b"kK",
+        L => b"lL",
+        M => b"mM",
+        N => b"nN",
+        O => b"oO",
+        P => b"pP",
+        Q => b"qQ",
+        R => b"rR",
+        S => b"sS",
+        T => b"tT",
+        U => b"uU",
+        V => b"vV",
+        W => b"wW",
+        X => b"xX",
+        Y => b"yY",
+        Z => b"zZ",
+        K0 => b"0)",
+        K1 => b"1!",
+        K2 => b"2@",
+        K3 => b"3#",
+        K4 => b"4$",
+        K5 => b"5%",
+        K6 => b"6^",
+        K7 => b"7&",
+        K8 => b"8*",
+        K9 => b"9(",
+        SPACE => b"  ",
+        TAB => b"\t\t",
+        BACK => b"\x08\x08",
+        ESCAPE => b"\x1B\x1B",
+        RETURN | ENTER => b"\r\r",
+        DEL => b"\x7F\x7F",
+        NP0 => b"00",
+        NP1 => b"11",
+        NP2 => b"22",
+        NP3 => b"33",
+        NP4 => b"44",
+        NP5 => b"55",
+        NP6 => b"66",
+        NP7 => b"77",
+        NP8 => b"88",
+        NP9 => b"99",
+        NP_MUL => b"**",
+        NP_DIV => b"//",
+        NP_ADD => b"++",
+        NP_SUB => b"--",
+        NP_DECIMAL => b"..",
+        PERIOD => b".>",
+        _ => return None,
+    };
+    Some((chars[0], chars[1]))
+}
@@ -0,0 +1,6 @@
+pub mod asm;
+pub mod config;
+pub mod cpu;
+pub mod device;
+pub mod machine;
+pub mod testsuite;
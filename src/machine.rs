@@ -0,0 +1,753 @@
+//! A CPU wired to a bus, ready to run.
+
+use crate::cpu::disasm::{decode_for, disassemble_device_for, Disassembly, SymbolTable};
+use crate::cpu::model::CpuModel;
+use crate::cpu::opcodes::Instruction;
+use crate::cpu::trace::Tracer;
+use crate::cpu::{Pins, ReadWrite, StepResult, CPU};
+use crate::device::device_map::{BusFault, DeviceMap, FaultKind};
+use crate::device::interrupts::{Interrupts, Line, LineError};
+use crate::device::{Device, WriteError};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+pub mod throttle;
+
+/// Something that shows the machine to the user, like the VGA window. It runs
+/// in its own thread and only shares devices with the machine.
+pub trait Frontend {
+    /// Turns false once the user closes it.
+    fn is_running(&self) -> bool;
+
+    /// Waits for the frontend to shut down.
+    fn join(self: Box<Self>);
+}
+
+/// Checked before every instruction by [`Machine::run`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExitCondition {
+    /// the opcode at this address is about to run
+    Pc(u16),
+    /// a `BRK` is about to run
+    Brk,
+}
+
+/// When [`Machine::run`] stops, besides a halted CPU or a closed frontend.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Exit {
+    pub condition: Option<ExitCondition>,
+    /// a limit on the total cycle count of the CPU, it's checked between
+    /// instructions (and every cycle of a `WAI`), so the last one may run a
+    /// few cycles past it
+    pub cycles: Option<u64>,
+    /// stop at an instruction that jumps or branches to itself, the way test
+    /// programs end
+    pub traps: bool,
+}
+
+/// What to do about a kind of [`BusFault`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum FaultPolicy {
+    #[default]
+    Ignore,
+    /// print it to stderr and carry on
+    Log,
+    /// stop the machine after the instruction that caused it
+    Break,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BusPolicy {
+    /// writes to ROM and other devices that can't be written
+    pub read_only: FaultPolicy,
+    /// accesses no device answers
+    pub unmapped: FaultPolicy,
+    /// accesses to addresses several devices are mapped to
+    pub contested: FaultPolicy,
+}
+
+impl BusPolicy {
+    /// The same policy for every kind of fault.
+    pub fn all(policy: FaultPolicy) -> Self {
+        Self {
+            read_only: policy,
+            unmapped: policy,
+            contested: policy,
+        }
+    }
+
+    pub fn get(&self, kind: FaultKind) -> FaultPolicy {
+        match kind {
+            FaultKind::ReadOnly => self.read_only,
+            FaultKind::Unmapped => self.unmapped,
+            FaultKind::Contested => self.contested,
+        }
+    }
+}
+
+/// A bus fault and the instruction that caused it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Fault {
+    pub bus: BusFault,
+    /// where the instruction starts
+    pub pc: u16,
+    /// the disassembled instruction
+    pub instruction: String,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} by `{}` at ${:04X}",
+            self.bus, self.instruction, self.pc
+        )
+    }
+}
+
+/// Why [`Machine::run`] stopped.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Stop {
+    /// the exit condition was met
+    Exit,
+    /// the instruction at this address jumped to itself
+    Trap(u16),
+    /// the CPU halted on the instruction at this address
+    Halted(u16),
+    /// the cycle budget ran out
+    CycleLimit,
+    /// a frontend was closed
+    Closed,
+    /// a bus fault with the [`FaultPolicy::Break`] policy
+    Fault(Fault),
+}
+
+/// The bytes of an instruction, as the CPU reads them.
+#[derive(Default)]
+struct Fetched {
+    address: u16,
+    bytes: Vec<u8>,
+    length: usize,
+}
+
+/// The instruction that's running and the one before it.
+#[derive(Default)]
+struct History {
+    current: Fetched,
+    previous: Fetched,
+}
+
+impl History {
+    fn update(&mut self, model: CpuModel, pins: &Pins) {
+        let current = &mut self.current;
+        if pins.sync {
+            std::mem::swap(current, &mut self.previous);
+            current.address = pins.address;
+            current.bytes.clear();
+            current.bytes.push(pins.data);
+            current.length = Instruction::for_model(model, pins.data).length as usize;
+        } else if pins.rw == ReadWrite::Read
+            && current.bytes.len() < current.length
+            && pins.address == current.address.wrapping_add(current.bytes.len() as u16)
+        {
+            current.bytes.push(pins.data);
+        }
+    }
+}
+
+pub struct Machine {
+    pub cpu: CPU,
+    pub bus: DeviceMap,
+    /// what to do about bus faults, they're ignored by default
+    pub policy: BusPolicy,
+    /// the lines the devices pull, fed into the CPU before every cycle
+    pub interrupts: Interrupts,
+    /// the IRQ line of [`irq`](Self::irq), connected the first time it's used
+    irq_line: Option<Line>,
+    frontends: Vec<Box<dyn Frontend>>,
+    tracer: Option<Tracer<Box<dyn Write>>>,
+    history: History,
+    /// the first fault that breaks, until `run` stops on it
+    fault: Option<Fault>,
+}
+
+/// Sets the interrupt inputs of the CPU for the next cycle.
+fn feed(cpu: &mut CPU, interrupts: &Interrupts) {
+    cpu.pins.irq = interrupts.irq();
+    cpu.pins.nmi = interrupts.nmi();
+}
+
+/// The bus as the CPU sees it in `step_instruction`, the devices are ticked
+/// right after every access, so they can pull the interrupt lines in time
+/// for the next cycle.
+struct Clocked<'a>(&'a mut DeviceMap);
+
+impl Device for Clocked<'_> {
+    fn read(&mut self, address: u16) -> Option<u8> {
+        let data = self.0.read(address);
+        self.0.tick(1);
+        data
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        let result = self.0.write(address, data);
+        self.0.tick(1);
+        result
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.0.peek(address)
+    }
+}
+
+/// Feeds a cycle to the tracer, a tracer that fails to write is dropped.
+fn trace(tracer: &mut Option<Tracer<Box<dyn Write>>>, cpu: &CPU, pins: &Pins) {
+    if let Some(t) = tracer {
+        if let Err(e) = t.trace(cpu, pins) {
+            eprintln!("tracing stopped: {}", e);
+            *tracer = None;
+        }
+    }
+}
+
+impl Machine {
+    pub fn new(cpu: CPU, bus: DeviceMap) -> Self {
+        Self::with_interrupts(cpu, bus, Interrupts::new())
+    }
+
+    /// A machine whose devices were connected to `interrupts`.
+    pub fn with_interrupts(cpu: CPU, bus: DeviceMap, interrupts: Interrupts) -> Self {
+        Self {
+            cpu,
+            bus,
+            policy: BusPolicy::default(),
+            irq_line: None,
+            interrupts,
+            frontends: vec![],
+            tracer: None,
+            history: History::default(),
+            fault: None,
+        }
+    }
+
+    pub fn add_frontend<T: 'static + Frontend>(&mut self, frontend: T) {
+        self.frontends.push(Box::new(frontend));
+    }
+
+    /// False once the CPU halted or a frontend was closed.
+    pub fn is_running(&self) -> bool {
+        !self.cpu.is_halted() && self.frontends.iter().all(|f| f.is_running())
+    }
+
+    /// The instructions in `range` of the bus, decoded for the CPU's model.
+    /// The bytes are peeked, so it doesn't disturb the devices.
+    pub fn disassemble(
+        &self,
+        range: RangeInclusive<u16>,
+        symbols: Option<&SymbolTable>,
+    ) -> Vec<Disassembly> {
+        disassemble_device_for(self.cpu.model(), &self.bus, range, symbols)
+    }
+
+    /// Traces every instruction from now on.
+    pub fn set_tracer(&mut self, tracer: Tracer<Box<dyn Write>>) {
+        self.tracer = Some(tracer);
+        // between instructions the next one has already been fetched
+        if self.cpu.pins.sync {
+            let pins = self.cpu.pins;
+            trace(&mut self.tracer, &self.cpu, &pins);
+        }
+    }
+
+    /// Stops tracing, flush the tracer to get the last instruction.
+    pub fn take_tracer(&mut self) -> Option<Tracer<Box<dyn Write>>> {
+        self.tracer.take()
+    }
+
+    /// Runs single cycles, the devices are ticked after every one.
+    pub fn run_cycles(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            feed(&mut self.cpu, &self.interrupts);
+            let pins = self.cpu.cycle(&mut self.bus);
+            self.bus.tick(1);
+            self.history.update(self.cpu.model(), &pins);
+            trace(&mut self.tracer, &self.cpu, &pins);
+            self.check_faults();
+        }
+    }
+
+    /// Runs the next instruction, the devices are ticked after every cycle.
+    /// A 65C02 in a `WAI` only runs a single cycle, until it's woken up.
+    pub fn step_instruction(&mut self) -> StepResult {
+        let tracer = &mut self.tracer;
+        let history = &mut self.history;
+        let interrupts = &self.interrupts;
+        feed(&mut self.cpu, interrupts);
+        let step = self
+            .cpu
+            .step_instruction_with(&mut Clocked(&mut self.bus), |cpu, pins| {
+                history.update(cpu.model(), pins);
+                trace(tracer, cpu, pins);
+                feed(cpu, interrupts);
+            });
+        self.check_faults();
+        step
+    }
+
+    /// Applies the policy to the faults of the last cycles, they all belong
+    /// to the instruction that's running. Once the next opcode has been
+    /// fetched they belong to the one before, even a fault in the fetch
+    /// itself, since that instruction jumped there.
+    fn check_faults(&mut self) {
+        let model = self.cpu.model();
+        let instruction = if self.cpu.pins.sync {
+            &self.history.previous
+        } else {
+            &self.history.current
+        };
+        // the dummy reads of the reset sequence after power on, the program
+        // counter points nowhere yet
+        if instruction.bytes.is_empty() {
+            self.bus.take_faults();
+            return;
+        }
+        for bus in self.bus.take_faults() {
+            let policy = self.policy.get(bus.kind);
+            if policy == FaultPolicy::Ignore {
+                continue;
+            }
+            let fault = Fault {
+                bus,
+                pc: instruction.address,
+                instruction: decode_for(model, &instruction.bytes, instruction.address, None).text,
+            };
+            if policy == FaultPolicy::Log {
+                eprintln!("bus fault: {}", fault);
+            } else if self.fault.is_none() {
+                self.fault = Some(fault);
+            }
+        }
+    }
+
+    /// Runs whole instructions until `done` returns true or the CPU halts.
+    /// Returns the number of cycles that ran.
+    pub fn run_until(&mut self, mut done: impl FnMut(&CPU) -> bool) -> u64 {
+        let start = self.cpu.cycles();
+        while !self.cpu.is_halted() && !done(&self.cpu) {
+            self.step_instruction();
+        }
+        self.cpu.cycles() - start
+    }
+
+    /// Runs whole instructions for at least `cycles` cycles, unless `exit`
+    /// stops the machine first. A `WAI` is checked every cycle while it
+    /// waits. Returns why it stopped, or `None` when the
+    /// cycles ran out and the machine can keep going.
+    pub fn run(&mut self, cycles: u64, exit: &Exit) -> Option<Stop> {
+        let end = self.cpu.cycles().saturating_add(cycles);
+        loop {
+            if let Some(stop) = self.stopped(exit) {
+                return Some(stop);
+            }
+            if self.cpu.cycles() >= end {
+                return None;
+            }
+            let step = self.step_instruction();
+            let pins = self.cpu.pins;
+            if exit.traps && !step.interrupt && pins.sync && pins.address == step.address {
+                return Some(Stop::Trap(step.address));
+            }
+        }
+    }
+
+    fn stopped(&mut self, exit: &Exit) -> Option<Stop> {
+        if let Some(fault) = self.fault.take() {
+            return Some(Stop::Fault(fault));
+        }
+        let pins = self.cpu.pins;
+        let condition = match exit.condition {
+            Some(ExitCondition::Pc(address)) => pins.sync && pins.address == address,
+            Some(ExitCondition::Brk) => pins.sync && !pins.res && pins.data == 0x00,
+            None => false,
+        };
+        if condition {
+            Some(Stop::Exit)
+        } else if self.cpu.is_halted() {
+            Some(Stop::Halted(self.cpu.instruction_address()))
+        } else if exit.cycles.is_some_and(|c| self.cpu.cycles() >= c) {
+            Some(Stop::CycleLimit)
+        } else if !self.frontends.iter().all(|f| f.is_running()) {
+            Some(Stop::Closed)
+        } else {
+            None
+        }
+    }
+
+    /// Resets the devices and pulls the reset line, the CPU runs the reset
+    /// sequence after the current instruction.
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.cpu.pins.res = true;
+    }
+
+    /// Triggers a non-maskable interrupt.
+    pub fn nmi(&mut self) {
+        self.cpu.nmi();
+    }
+
+    /// Pulls the IRQ line or lets go of it, on top of the devices. The CPU
+    /// keeps taking interrupts while it's held and the I flag is clear.
+    pub fn irq(&mut self, active: bool) -> Result<(), LineError> {
+        if self.irq_line.is_none() {
+            self.irq_line = Some(self.interrupts.irq_line()?);
+        }
+        if let Some(line) = &self.irq_line {
+            line.set(active);
+        }
+        Ok(())
+    }
+
+    /// Waits for all frontends to shut down, and flushes the trace.
+    pub fn join(self) {
+        if let Some(mut tracer) = self.tracer {
+            if let Err(e) = tracer.flush() {
+                eprintln!("tracing stopped: {}", e);
+            }
+        }
+        for frontend in self.frontends {
+            frontend.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::StatusRegister;
+    use crate::device::device_map::OpenBus;
+    use crate::device::{Ram, Rom};
+
+    /// Counts NMIs in $10 and IRQs in $11, the main loop counts in X.
+    fn machine() -> Machine {
+        let mut bus = DeviceMap::new();
+        bus.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        let program = vec![
+            0xE8, 0x4C, 0x00, 0xE0, // main: INX, JMP main
+            0xE6, 0x10, 0x40, // nmi: INC $10, RTI
+            0xE6, 0x11, 0x58, 0x40, // irq: INC $11, CLI, RTI
+        ];
+        bus.map("rom", 0xE000..=0xE00A, Rom::from_vec(0xE000, program))
+            .unwrap();
+        let vectors = Rom::interrupts(0xE004, 0xE000, 0xE007);
+        bus.map("vectors", 0xFFFA..=0xFFFF, vectors).unwrap();
+        Machine::new(CPU::new(), bus)
+    }
+
+    /// Runs `program` from $E000, with RAM below $0200.
+    fn program_machine(program: Vec<u8>) -> Machine {
+        program_machine_for(CpuModel::Nmos6502, program)
+    }
+
+    fn program_machine_for(model: CpuModel, program: Vec<u8>) -> Machine {
+        let mut bus = DeviceMap::new();
+        bus.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        let end = 0xE000 + program.len() as u16 - 1;
+        bus.map("rom", 0xE000..=end, Rom::from_vec(0xE000, program))
+            .unwrap();
+        let vectors = Rom::interrupts(0xE000, 0xE000, 0xE000);
+        bus.map("vectors", 0xFFFA..=0xFFFF, vectors).unwrap();
+        Machine::new(CPU::with_model(model), bus)
+    }
+
+    #[test]
+    fn run() {
+        let mut machine = machine();
+        assert!(machine.is_running());
+        machine.run_cycles(7 + 5 * 10);
+        assert_eq!(machine.cpu.x, 10);
+        assert_eq!(machine.cpu.pc, 0xE000);
+        let step = machine.step_instruction();
+        assert_eq!((step.address, step.opcode), (0xE000, 0xE8));
+    }
+
+    #[test]
+    fn tracing() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        /// Lets the test look at what the machine wrote.
+        #[derive(Clone, Default)]
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut machine = machine();
+        machine.step_instruction();
+        let out = Shared::default();
+        machine.set_tracer(Tracer::new(Box::new(out.clone())));
+        let cycles = machine.run_until(|cpu| cpu.x == 2);
+        assert_eq!(cycles, 7);
+        machine.take_tracer().unwrap().flush().unwrap();
+        let log = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let addresses: Vec<&str> = log.lines().map(|l| &l[..4]).collect();
+        assert_eq!(addresses, ["E000", "E001", "E000"]);
+    }
+
+    #[test]
+    fn exit() {
+        let mut machine = machine();
+        let mut exit = Exit {
+            condition: Some(ExitCondition::Pc(0xE001)),
+            ..Exit::default()
+        };
+        assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::Exit));
+        assert_eq!(machine.cpu.x, 1);
+
+        exit.condition = None;
+        assert_eq!(machine.run(10, &exit), None);
+        assert!(machine.cpu.cycles() >= 10);
+        exit.cycles = Some(1000);
+        assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::CycleLimit));
+        // only whole instructions run
+        assert!((1000..1007).contains(&machine.cpu.cycles()));
+    }
+
+    #[test]
+    fn traps() {
+        let machine = program_machine;
+        // NOP, JMP $E001
+        let program = vec![0xEA, 0x4C, 0x01, 0xE0];
+        let mut exit = Exit {
+            cycles: Some(100),
+            ..Exit::default()
+        };
+        assert_eq!(
+            machine(program.clone()).run(u64::MAX, &exit),
+            Some(Stop::CycleLimit)
+        );
+        exit.traps = true;
+        assert_eq!(
+            machine(program).run(u64::MAX, &exit),
+            Some(Stop::Trap(0xE001))
+        );
+        // JAM
+        assert_eq!(
+            machine(vec![0xEA, 0x02]).run(u64::MAX, &exit),
+            Some(Stop::Halted(0xE001))
+        );
+    }
+
+    #[test]
+    fn faults() {
+        // STA $E000, STA $0300, JMP $E006
+        let program = vec![0x8D, 0x00, 0xE0, 0x8D, 0x00, 0x03, 0x4C, 0x06, 0xE0];
+        let exit = Exit {
+            cycles: Some(100),
+            ..Exit::default()
+        };
+        let mut machine = program_machine(program.clone());
+        assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::CycleLimit));
+
+        let mut machine = program_machine(program.clone());
+        machine.policy.read_only = FaultPolicy::Break;
+        let fault = match machine.run(u64::MAX, &exit) {
+            Some(Stop::Fault(fault)) => fault,
+            stop => panic!("{:?}", stop),
+        };
+        assert_eq!(fault.bus.kind, FaultKind::ReadOnly);
+        assert_eq!(
+            fault.to_string(),
+            "write of $00 to read-only $E000 by `STA $E000` at $E000"
+        );
+        // it stops after the instruction
+        assert_eq!(machine.cpu.pins.address, 0xE003);
+
+        let mut machine = program_machine(program);
+        machine.policy = BusPolicy::all(FaultPolicy::Break);
+        machine.policy.read_only = FaultPolicy::Log;
+        match machine.run(u64::MAX, &exit) {
+            Some(Stop::Fault(fault)) => assert_eq!(fault.pc, 0xE003),
+            stop => panic!("{:?}", stop),
+        }
+
+        // LDA #$E0, STA $21, STA ($20), JMP $E006 on the 65C02
+        let program = vec![0xA9, 0xE0, 0x85, 0x21, 0x92, 0x20, 0x4C, 0x06, 0xE0];
+        let mut machine = program_machine_for(CpuModel::Wdc65C02, program);
+        machine.policy.read_only = FaultPolicy::Break;
+        match machine.run(u64::MAX, &exit) {
+            Some(Stop::Fault(fault)) => assert_eq!(
+                fault.to_string(),
+                "write of $E0 to read-only $E000 by `STA ($20)` at $E004"
+            ),
+            stop => panic!("{:?}", stop),
+        }
+    }
+
+    #[test]
+    fn open_bus() {
+        // LDA $0300, JMP $E003
+        let program = vec![0xAD, 0x00, 0x03, 0x4C, 0x03, 0xE0];
+        let mut machine = program_machine(program.clone());
+        machine.step_instruction();
+        machine.step_instruction();
+        // the high byte of the address was the last thing on the bus
+        assert_eq!(machine.cpu.ac, 0x03);
+
+        let mut machine = program_machine(program);
+        machine.bus.set_open_bus(OpenBus::Floating(0xFF));
+        machine.step_instruction();
+        machine.step_instruction();
+        assert_eq!(machine.cpu.ac, 0xFF);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut machine = machine();
+        machine.run_cycles(100);
+        machine.nmi();
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x10), Some(1));
+
+        // the reset sequence sets I, so the IRQ waits until CLI
+        machine.irq(true).unwrap();
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x11), Some(0));
+        machine.cpu.sr.remove(StatusRegister::I);
+        machine.run_cycles(20);
+        machine.irq(false).unwrap();
+        machine.run_cycles(100);
+        assert!(matches!(machine.bus.read(0x11), Some(n) if n > 0));
+        let irqs = machine.bus.read(0x11);
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x11), irqs);
+        assert_eq!(machine.bus.read(0x10), Some(1));
+    }
+
+    #[test]
+    fn interrupt_lines() {
+        let mut machine = machine();
+        let timer = machine.interrupts.irq_line().unwrap();
+        let button = machine.interrupts.nmi_line().unwrap();
+        machine.run_cycles(100);
+
+        // held down, the NMI still only fires once
+        button.set(true);
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x10), Some(1));
+        button.set(false);
+        machine.run_cycles(10);
+        button.set(true);
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x10), Some(2));
+
+        machine.cpu.sr.remove(StatusRegister::I);
+        timer.set(true);
+        machine.irq(true).unwrap();
+        machine.run_cycles(50);
+        timer.set(false);
+        let irqs = machine.bus.read(0x11).unwrap();
+        assert!(irqs > 0);
+        // the machine's own connection still holds the line
+        machine.run_cycles(50);
+        assert!(machine.bus.read(0x11).unwrap() > irqs);
+        machine.irq(false).unwrap();
+        // one that already started still finishes
+        machine.run_cycles(100);
+        let irqs = machine.bus.read(0x11);
+        machine.run_cycles(100);
+        assert_eq!(machine.bus.read(0x11), irqs);
+        // every interrupt returned to the main loop
+        let step = machine.step_instruction();
+        assert!(step.address <= 0xE001, "{:04X}", step.address);
+    }
+
+    #[test]
+    fn reset() {
+        let mut machine = machine();
+        machine.run_cycles(100);
+        machine.reset();
+        machine.step_instruction();
+        let reset = machine.step_instruction();
+        assert!(reset.interrupt);
+        assert_eq!(machine.cpu.pc, 0xE000);
+    }
+
+    #[test]
+    fn wait_for_keyboard() {
+        use crate::device::vga::{KeyScript, KeyUpdate, Keyboard, KeyboardMode};
+        use olc_pixel_game_engine::Key;
+        use std::collections::VecDeque;
+        use std::sync::{Arc, Mutex};
+
+        let program = vec![
+            0x58, 0xA9, 0x01, // CLI, LDA #IRQ_ENABLE
+            0x8D, 0x02, 0x03, 0xCB, // STA $0302, WAI
+            0xE8, 0x4C, 0x08, 0xE0, // INX, JMP *
+            0xAD, 0x00, 0x03, 0x85, 0x20, // irq: LDA $0300, STA $20
+            0x8D, 0x01, 0x03, 0x40, // STA $0301, RTI
+        ];
+        let mut bus = DeviceMap::new();
+        bus.map("ram", 0x0000..=0x01FF, Ram::new(0, 0x200)).unwrap();
+        bus.map("rom", 0xE000..=0xE013, Rom::from_vec(0xE000, program))
+            .unwrap();
+        let vectors = Rom::interrupts(0xE000, 0xE000, 0xE00B);
+        bus.map("vectors", 0xFFFA..=0xFFFF, vectors).unwrap();
+        let interrupts = Interrupts::new();
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let keyboard = Keyboard::new(0x0300, Arc::clone(&keys))
+            .with_mode(KeyboardMode::Ascii)
+            .with_irq(interrupts.irq_line().unwrap());
+        bus.map("keyboard", 0x0300..=0x0302, keyboard).unwrap();
+        let mut script = KeyScript::new(keys);
+        script.key(500, KeyUpdate::press(Key::A));
+        bus.attach(script);
+        let cpu = CPU::with_model(CpuModel::Wdc65C02);
+        let mut machine = Machine::with_interrupts(cpu, bus, interrupts);
+
+        let exit = Exit {
+            cycles: Some(10_000),
+            traps: true,
+            ..Exit::default()
+        };
+        assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::Trap(0xE008)));
+        assert!((500..1000).contains(&machine.cpu.cycles()));
+        assert_eq!(machine.cpu.x, 1);
+        assert_eq!(machine.bus.read(0x20), Some(b'a'));
+    }
+
+    #[test]
+    fn disassemble() {
+        // STZ $10, BRA *
+        let machine = program_machine_for(CpuModel::Wdc65C02, vec![0x64, 0x10, 0x80, 0xFE]);
+        let text: Vec<_> = machine
+            .disassemble(0xE000..=0xE003, None)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(text, ["STZ $10", "BRA $E002"]);
+    }
+
+    #[test]
+    fn wait_forever() {
+        let mut machine = program_machine_for(CpuModel::Wdc65C02, vec![0xCB]);
+        let exit = Exit {
+            cycles: Some(1000),
+            traps: true,
+            ..Exit::default()
+        };
+        assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::CycleLimit));
+        assert_eq!(machine.cpu.cycles(), 1000);
+        assert!(machine.cpu.is_waiting());
+
+        // and `run` gives up when its own cycles run out
+        assert_eq!(machine.run(50, &Exit::default()), None);
+        assert_eq!(machine.cpu.cycles(), 1050);
+    }
+}
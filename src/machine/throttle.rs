@@ -0,0 +1,125 @@
+//! Keeps the emulated clock in step with the wall clock.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How far the emulator may fall behind before it stops trying to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Runs a machine at the speed of a real one. Instead of sleeping after every
+/// cycle, the machine runs a batch of cycles and then sleeps until the wall
+/// clock has caught up, so the sleeps are long enough to be accurate and the
+/// time spent outside of the CPU doesn't add up.
+pub struct Throttle {
+    /// `None` runs as fast as possible
+    hz: Option<f64>,
+    batch: u64,
+    /// the time at which the CPU was at `base_cycles`, moved forward when
+    /// the emulator falls too far behind
+    base: Instant,
+    base_cycles: u64,
+    start: Instant,
+    start_cycles: u64,
+    /// how far behind the emulator was each time it gave up catching up
+    lost: Duration,
+}
+
+impl Throttle {
+    /// Starts counting from now, with the CPU at `cycles`.
+    pub fn new(hz: Option<f64>, cycles: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            hz,
+            // a millisecond's worth, unlimited still stops now and then to
+            // check on the frontends
+            batch: hz.map_or(10_000, |hz| (hz / 1000.0).ceil() as u64).max(1),
+            base: now,
+            base_cycles: cycles,
+            start: now,
+            start_cycles: cycles,
+            lost: Duration::ZERO,
+        }
+    }
+
+    /// The number of cycles to run before the next [`sync`](Self::sync).
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    /// When a real machine would have reached `cycles`.
+    fn target(&self, hz: f64, cycles: u64) -> Instant {
+        self.base + Duration::from_secs_f64((cycles - self.base_cycles) as f64 / hz)
+    }
+
+    /// Sleeps until a real machine would have run up to `cycles`. When the
+    /// host can't keep up, or was suspended, the emulator carries on from
+    /// where it is instead of running flat out until it caught up.
+    pub fn sync(&mut self, cycles: u64) {
+        let hz = match self.hz {
+            Some(hz) => hz,
+            None => return,
+        };
+        let target = self.target(hz, cycles);
+        let now = Instant::now();
+        if target > now {
+            sleep(target - now);
+        } else if now - target > MAX_LAG {
+            self.lost += now - target;
+            self.base = now;
+            self.base_cycles = cycles;
+        }
+    }
+
+    /// How far the emulator is behind the wall clock at `cycles`, `None`
+    /// when it runs unlimited.
+    pub fn drift(&self, cycles: u64) -> Option<Duration> {
+        let target = self.target(self.hz?, cycles);
+        Some(self.lost + Instant::now().saturating_duration_since(target))
+    }
+
+    /// The clock speed the emulator actually ran at since it started, in Hz.
+    pub fn speed(&self, cycles: u64) -> f64 {
+        (cycles - self.start_cycles) as f64 / self.start.elapsed().as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches() {
+        assert_eq!(Throttle::new(Some(1e6), 0).batch(), 1000);
+        assert_eq!(Throttle::new(Some(2e6), 0).batch(), 2000);
+        assert_eq!(Throttle::new(Some(10.0), 0).batch(), 1);
+        let mut unlimited = Throttle::new(None, 0);
+        unlimited.sync(1_000_000_000);
+        assert_eq!(unlimited.drift(1_000_000_000), None);
+        assert!(unlimited.speed(1_000_000_000) > 1e6);
+    }
+
+    #[test]
+    fn sync() {
+        let mut throttle = Throttle::new(Some(1e6), 100);
+        let start = Instant::now();
+        for cycles in (1100..=20_100).step_by(1000) {
+            throttle.sync(cycles);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(throttle.speed(20_100) <= 1e6);
+    }
+
+    #[test]
+    fn falling_behind() {
+        let mut throttle = Throttle::new(Some(1e6), 0);
+        sleep(MAX_LAG * 2);
+        throttle.sync(1000);
+        let drift = throttle.drift(1000).unwrap();
+        assert!(drift >= MAX_LAG * 2 - Duration::from_millis(1));
+        // it doesn't try to make up for it, the next batch waits again
+        let start = Instant::now();
+        throttle.sync(11_000);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(throttle.drift(11_000).unwrap() >= drift);
+    }
+}
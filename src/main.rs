@@ -1,90 +1,403 @@
-use crate::device::{CreateError, Device, WriteError};
-use olc_pixel_game_engine as olc;
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::thread::sleep;
-use std::time::{Duration, SystemTime};
-
-pub mod cpu;
-mod device;
-
-fn main() -> Result<(), CreateError> {
-    println!("rust6502");
-
-    let font = psf::Font::new("./assets/koi8-14.psf").unwrap();
-    let keys = Arc::new(Mutex::new(VecDeque::new()));
-    let keys_clone = Arc::clone(&keys);
-    let vram = Arc::new(Mutex::new(device::Ram::new(0x500, 0x1000)));
-    let vram_clone = Arc::clone(&vram);
-    let mut vga = device::vga::Vga::new(font, keys_clone, vram_clone);
-    let running = Arc::new(AtomicBool::new(true));
-    let running_clone = Arc::clone(&running);
-
-    let jh = thread::spawn(move || {
-        olc::start("rust6502", &mut vga, 8 * 80, 14 * 25, 4, 4).unwrap();
-        running_clone.store(false, Ordering::Release);
-    });
-    let keyboard = device::vga::Keyboard::new(0x10, keys);
-    let ram = device::Ram::new(0x0100, 0x0400);
-    let rom = device::Rom::new_file(0x8000, "./code/bin/example")?;
-    let kernel = device::Rom::new_file(0xE000, "./code/bin/kernel")?;
-    let interrupts = device::Rom::interrupts(0, 0xE000, 0);
-
-    let mapp = &mut device::device_map::DeviceMap::new();
-    mapp.add(ram);
-    mapp.add(rom);
-    mapp.add(vram);
-    mapp.add(keyboard);
-    mapp.add(interrupts);
-    mapp.add(kernel);
-
-    let mut cpu = cpu::CPU::new();
-    let mut pins = cpu.pins;
-    while running.load(Ordering::Acquire) {
-        let now = SystemTime::now();
-        pins = cpu.tick(pins);
-        let addr = pins.address;
-        if pins.rw == cpu::ReadWrite::Read {
-            if let Some(e) = mapp.read(addr) {
-                pins.data = e
+use rust6502::config::{DeviceKind, MachineConfig};
+use rust6502::cpu::model::CpuModel;
+use rust6502::cpu::trace::Tracer;
+use rust6502::device;
+use rust6502::device::device_map::OpenBus;
+use rust6502::machine::throttle::Throttle;
+use rust6502::machine::{BusPolicy, Exit, ExitCondition, FaultPolicy, Stop};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: rust6502 [run] [options]
+       rust6502 check [--config file]
+
+options:
+  --config file       the machine description, machine.toml by default
+  --kernel file       load the device named `kernel` from this file
+  --program file      load the device named `program` from this file
+  --font file         the font of the VGA window
+  --model model       6502, 2a03, 6510 or 65c02, instead of the one in the config
+  --clock speed       like 1MHz, 500kHz or unlimited, 1MHz by default and
+                      unlimited with --headless
+  --stats             print the speed the emulator ran at, and how far behind
+                      the clock it fell
+  --headless          don't open a window, and stop at an instruction that jumps
+                      to itself once the script has typed everything
+  --screen            with --headless, print the text on the screen at the end
+  --script file       type the keys in this file at the cycles it gives them
+  --trace file        write an instruction trace, - for stdout
+  --trace-range a-b   only trace the instructions between these addresses
+  --cycles n          stop after this many cycles
+  --open-bus value    what reads from unmapped addresses return, last for the last
+                      value on the bus, or a value like $FF
+  --faults policy     ignore, log or break on bus faults, for all of them or per
+                      kind, like read-only=break,unmapped=log,contested=ignore
+  --until condition   stop at pc=addr or brk, the emulator always stops when the CPU halts
+
+The exit status is 0 when the emulator stops normally. When there's an --until
+condition, stopping anywhere else, at a trap or halt or after --cycles, is a
+failure with exit status 1. Breaking on a bus fault is always a failure. Errors
+exit with 2.";
+
+struct Options {
+    config: PathBuf,
+    kernel: Option<PathBuf>,
+    program: Option<PathBuf>,
+    font: Option<PathBuf>,
+    model: Option<CpuModel>,
+    /// in Hz, `None` runs as fast as possible
+    clock: Option<f64>,
+    stats: bool,
+    headless: bool,
+    screen: bool,
+    script: Option<PathBuf>,
+    trace: Option<String>,
+    trace_range: Option<(u16, u16)>,
+    cycles: Option<u64>,
+    until: Option<ExitCondition>,
+    policy: BusPolicy,
+    open_bus: Option<OpenBus>,
+}
+
+enum Command {
+    Run(Options),
+    Check(PathBuf),
+    Help,
+}
+
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_clock(value: &str) -> Option<Option<f64>> {
+    let value = value.to_ascii_lowercase();
+    if value == "unlimited" {
+        return Some(None);
+    }
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => value.split_at(i),
+        None => (value.as_str(), "hz"),
+    };
+    let unit = match unit {
+        "hz" => 1.0,
+        "khz" => 1e3,
+        "mhz" => 1e6,
+        _ => return None,
+    };
+    let hz = number.trim().parse::<f64>().ok()? * unit;
+    if hz > 0.0 {
+        Some(Some(hz))
+    } else {
+        None
+    }
+}
+
+fn parse_policy(value: &str) -> Option<BusPolicy> {
+    let policy = |name: &str| match name {
+        "ignore" => Some(FaultPolicy::Ignore),
+        "log" => Some(FaultPolicy::Log),
+        "break" => Some(FaultPolicy::Break),
+        _ => None,
+    };
+    if let Some(all) = policy(value) {
+        return Some(BusPolicy::all(all));
+    }
+    let mut bus = BusPolicy::default();
+    for part in value.split(',') {
+        let (kind, name) = part.split_once('=')?;
+        let field = match kind {
+            "read-only" => &mut bus.read_only,
+            "unmapped" => &mut bus.unmapped,
+            "contested" => &mut bus.contested,
+            _ => return None,
+        };
+        *field = policy(name)?;
+    }
+    Some(bus)
+}
+
+fn parse_args() -> Result<Command, String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let check = match args.first().map(String::as_str) {
+        Some("run") => {
+            args.remove(0);
+            false
+        }
+        Some("check") => {
+            args.remove(0);
+            true
+        }
+        Some("help") => return Ok(Command::Help),
+        _ => false,
+    };
+
+    let mut options = Options {
+        config: PathBuf::from("machine.toml"),
+        kernel: None,
+        program: None,
+        font: None,
+        model: None,
+        clock: Some(1e6),
+        stats: false,
+        headless: false,
+        screen: false,
+        script: None,
+        trace: None,
+        trace_range: None,
+        cycles: None,
+        until: None,
+        policy: BusPolicy::default(),
+        open_bus: None,
+    };
+    let mut clock = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--config" => options.config = value()?.into(),
+            "--kernel" => options.kernel = Some(value()?.into()),
+            "--program" => options.program = Some(value()?.into()),
+            "--font" => options.font = Some(value()?.into()),
+            "--model" => options.model = Some(value()?.parse().map_err(|e| format!("{}", e))?),
+            "--clock" => {
+                let v = value()?;
+                clock = Some(parse_clock(&v).ok_or_else(|| format!("invalid clock `{}`", v))?);
             }
-            // println!(
-            //     "Reading {:#06X}, data is now: {:#04X}",
-            //     pins.address, pins.data
-            // );
-        } else {
-            let res = mapp.write(addr, pins.data);
-            // println!(
-            //     "Writing {:#06X}, data will be: {:#04X}",
-            //     pins.address, pins.data
-            // );
-            match res {
-                Ok(_) => {}
-                Err(WriteError::NotWritable) => {}
-                Err(WriteError::InvalidAddress) => {}
+            "--stats" => options.stats = true,
+            "--headless" => options.headless = true,
+            "--screen" => options.screen = true,
+            "--script" => options.script = Some(value()?.into()),
+            "--trace" => options.trace = Some(value()?),
+            "--trace-range" => {
+                let v = value()?;
+                let range = v
+                    .split_once('-')
+                    .and_then(|(a, b)| Some((parse_address(a)?, parse_address(b)?)));
+                options.trace_range =
+                    Some(range.ok_or_else(|| format!("invalid address range `{}`", v))?);
+            }
+            "--cycles" => {
+                let v = value()?;
+                options.cycles = Some(
+                    v.parse()
+                        .map_err(|_| format!("invalid cycle count `{}`", v))?,
+                );
             }
+            "--until" => {
+                let v = value()?;
+                let until = match v.as_str() {
+                    "brk" => Some(ExitCondition::Brk),
+                    _ => v
+                        .strip_prefix("pc=")
+                        .and_then(parse_address)
+                        .map(ExitCondition::Pc),
+                };
+                options.until = Some(until.ok_or_else(|| format!("invalid condition `{}`", v))?);
+            }
+            "--open-bus" => {
+                let v = value()?;
+                let open_bus = match v.as_str() {
+                    "last" => Some(OpenBus::LastValue),
+                    _ => parse_address(&v)
+                        .filter(|value| *value <= 0xFF)
+                        .map(|value| OpenBus::Floating(value as u8)),
+                };
+                options.open_bus =
+                    Some(open_bus.ok_or_else(|| format!("invalid open bus value `{}`", v))?);
+            }
+            "--faults" => {
+                let v = value()?;
+                options.policy =
+                    parse_policy(&v).ok_or_else(|| format!("invalid fault policy `{}`", v))?;
+            }
+            "-h" | "--help" => return Ok(Command::Help),
+            _ => return Err(format!("unknown option `{}`\n{}", arg, USAGE)),
         }
-        if pins.address == 0xFFFF && pins.data == 0xFF {
-            break;
+    }
+    // there's nobody watching a headless run in real time
+    if let Some(clock) = clock {
+        options.clock = clock;
+    } else if options.headless {
+        options.clock = None;
+    }
+    if check {
+        Ok(Command::Check(options.config))
+    } else {
+        Ok(Command::Run(options))
+    }
+}
+
+/// Points the rom called `name` at another file.
+fn set_file(config: &mut MachineConfig, name: &str, path: PathBuf) -> Result<(), String> {
+    let device = config
+        .devices
+        .iter_mut()
+        .find(|d| d.name.as_deref() == Some(name));
+    match device.map(|d| &mut d.kind) {
+        Some(DeviceKind::Rom { file, .. }) => {
+            // relative to the working directory, not to the config
+            *file = std::env::current_dir()
+                .map_err(|e| e.to_string())?
+                .join(path);
+            Ok(())
         }
-        match now.elapsed() {
-            Ok(elapsed) => {
-                // it prints '2'
-                let elapsed = elapsed.as_nanos();
-                println!("execution took {}ns!", elapsed);
-                if elapsed < 1000 {
-                    sleep(Duration::from_nanos((1000 - elapsed) as u64))
-                }
-            }
-            Err(e) => {
-                // an error occurred!
-                println!("Error: {:?}", e);
+        _ => Err(format!("the config has no rom named `{}`", name)),
+    }
+}
+
+/// Returns the exit status.
+fn run(options: Options) -> Result<i32, String> {
+    let mut config = MachineConfig::from_file(&options.config).map_err(|e| e.to_string())?;
+    if let Some(kernel) = options.kernel {
+        set_file(&mut config, "kernel", kernel)?;
+    }
+    if let Some(program) = options.program {
+        set_file(&mut config, "program", program)?;
+    }
+    if let Some(path) = options.font {
+        let path = std::env::current_dir()
+            .map_err(|e| e.to_string())?
+            .join(path);
+        for device in &mut config.devices {
+            if let DeviceKind::Vga { font, .. } = &mut device.kind {
+                *font = path.clone();
             }
         }
     }
+    if let Some(model) = options.model {
+        config.cpu.model = model;
+    }
+    match options.open_bus {
+        Some(OpenBus::LastValue) => config.bus.floating = None,
+        Some(OpenBus::Floating(value)) => config.bus.floating = Some(value),
+        None => {}
+    }
+
+    let mut board = config.build().map_err(|e| e.to_string())?;
+    if let Some(path) = options.script {
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        board
+            .script
+            .lock()
+            .unwrap()
+            .load(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    let vga = board.vga.take();
+    let script = Arc::clone(&board.script);
+    let mut machine = board.machine();
+    machine.policy = options.policy;
+    // without a window the screen and keyboard are still on the bus, there's
+    // just nobody looking or typing
+    let mut screen = None;
+    if let Some(vga) = vga {
+        if options.headless {
+            screen = Some(vga);
+        } else {
+            machine.add_frontend(device::vga::Window::spawn(vga));
+        }
+    }
+    if let Some(path) = options.trace {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            let file = File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+            Box::new(BufWriter::new(file))
+        };
+        let mut tracer = Tracer::new(out);
+        if let Some((start, end)) = options.trace_range {
+            tracer = tracer.with_range(start..=end);
+        }
+        machine.set_tracer(tracer);
+    }
 
-    jh.join().unwrap();
+    let mut exit = Exit {
+        condition: options.until,
+        cycles: options.cycles,
+        traps: false,
+    };
+    let mut throttle = Throttle::new(options.clock, machine.cpu.cycles());
+    let stop = loop {
+        // a program that idles until the script types something isn't stuck
+        exit.traps = options.headless && script.lock().unwrap().is_done();
+        if let Some(stop) = machine.run(throttle.batch(), &exit) {
+            break stop;
+        }
+        throttle.sync(machine.cpu.cycles());
+    };
+
+    let cycles = machine.cpu.cycles();
+    if options.stats {
+        let mhz = throttle.speed(cycles) / 1e6;
+        match throttle.drift(cycles) {
+            Some(drift) => eprintln!(
+                "rust6502: ran at {:.3} MHz, {:.1} ms behind the clock",
+                mhz,
+                drift.as_secs_f64() * 1e3
+            ),
+            None => eprintln!("rust6502: ran at {:.3} MHz", mhz),
+        }
+    }
+    machine.join();
+    match screen {
+        Some(vga) if options.screen => print!("{}", vga.text()),
+        _ => {}
+    }
+    let reason = match stop {
+        Stop::Exit => Some("reached the exit condition".to_string()),
+        Stop::Trap(address) => Some(format!("trapped at ${:04X}", address)),
+        Stop::Halted(address) => Some(format!("halted at ${:04X}", address)),
+        Stop::CycleLimit => Some("ran out of cycles".to_string()),
+        Stop::Closed => None,
+        Stop::Fault(ref fault) => Some(format!("bus fault: {}", fault)),
+    };
+    match reason {
+        Some(reason) if options.headless || matches!(stop, Stop::Fault(_)) => {
+            eprintln!("rust6502: {} after {} cycles", reason, cycles)
+        }
+        _ => {}
+    }
+    let failed = match stop {
+        Stop::Fault(_) => true,
+        Stop::Exit | Stop::Closed => false,
+        _ => options.until.is_some(),
+    };
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn check(path: PathBuf) -> Result<(), String> {
+    let config = MachineConfig::from_file(&path).map_err(|e| e.to_string())?;
+    config.build().map_err(|e| e.to_string())?;
+    println!("{}: {:?}", path.display(), config.cpu.model);
+    for device in &config.devices {
+        println!("  {}", device.name());
+    }
     Ok(())
 }
+
+fn main() {
+    let result = parse_args().and_then(|command| match command {
+        Command::Run(options) => run(options),
+        Command::Check(path) => check(path).map(|_| 0),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+    });
+    match result {
+        Ok(status) => exit(status),
+        Err(e) => {
+            eprintln!("rust6502: {}", e);
+            exit(2);
+        }
+    }
+}
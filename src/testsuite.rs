@@ -0,0 +1,5 @@
+//! Runners for the third party CPU test suites, shared by the integration
+//! tests and the `rust6502-test` binary.
+
+pub mod klaus;
+pub mod processor_tests;
@@ -0,0 +1,215 @@
+//! Klaus Dormann's functional and decimal tests. They run from a flat 64K
+//! RAM and end in a trap, a jump or branch to itself. Which trap they end in
+//! tells whether they passed.
+
+use crate::cpu::disasm::{decode_for, Disassembly};
+use crate::cpu::model::CpuModel;
+use crate::cpu::{StatusRegister, CPU};
+use crate::device::{Device, Ram};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+/// How many of the last executed instructions are kept for the report.
+const HISTORY: usize = 16;
+
+/// Where a test image is loaded and how it reports success.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TrapTest {
+    pub load: u16,
+    pub start: u16,
+    /// the trap the test ends in when every test passed
+    pub success: Option<u16>,
+    /// a byte that is zero at the final trap when every test passed
+    pub error_flag: Option<u16>,
+    pub max_cycles: u64,
+}
+
+impl TrapTest {
+    /// `6502_functional_test.bin` assembled with the default configuration.
+    pub fn functional() -> Self {
+        Self {
+            load: 0x0000,
+            start: 0x0400,
+            success: Some(0x3469),
+            error_flag: None,
+            max_cycles: 100_000_000,
+        }
+    }
+
+    /// `6502_decimal_test.bin`, which keeps its result in `ERROR` at $000B.
+    pub fn decimal() -> Self {
+        Self {
+            load: 0x0200,
+            start: 0x0200,
+            success: None,
+            error_flag: Some(0x000B),
+            max_cycles: 100_000_000,
+        }
+    }
+
+    /// Runs `image` until it traps or runs out of cycles.
+    pub fn run(&self, model: CpuModel, image: &[u8]) -> TrapReport {
+        let mut data = vec![0; 0x10000];
+        let end = (self.load as usize + image.len()).min(data.len());
+        data[self.load as usize..end].copy_from_slice(&image[..end - self.load as usize]);
+        let mut ram = Ram::from_vec(0, data);
+
+        // skip the reset sequence, the tests point the reset vector at a trap
+        let mut cpu = CPU::with_model(model);
+        cpu.pc = self.start;
+        cpu.pins.res = false;
+        cpu.pins.address = self.start;
+        cpu.pins.data = ram.read(self.start).unwrap_or(0);
+
+        let mut history = VecDeque::with_capacity(HISTORY);
+        history.push_back(self.start);
+        let mut trap = None;
+        while cpu.cycles() < self.max_cycles && !cpu.is_halted() {
+            let step = cpu.step_instruction(&mut ram);
+            if !cpu.pins.sync {
+                // waiting in a WAI
+                continue;
+            }
+            let next = cpu.pins.address;
+            if step.address == next {
+                trap = Some(next);
+                break;
+            }
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(next);
+        }
+        let passed = trap.is_some()
+            && self.success.is_none_or(|s| trap == Some(s))
+            && self.error_flag.is_none_or(|a| ram.read(a) == Some(0));
+        let history = history
+            .iter()
+            .map(|&address| {
+                let bytes: Vec<u8> = (0..3)
+                    .map(|i| ram.read(address.wrapping_add(i)).unwrap_or(0))
+                    .collect();
+                decode_for(model, &bytes, address, None)
+            })
+            .collect();
+        TrapReport {
+            trap,
+            passed,
+            cycles: cpu.cycles(),
+            instructions: cpu.instructions(),
+            history,
+            cpu,
+        }
+    }
+}
+
+/// The outcome of a [`TrapTest`].
+#[derive(Debug)]
+pub struct TrapReport {
+    /// `None` if the cycle limit was reached first
+    pub trap: Option<u16>,
+    pub passed: bool,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// the last instructions before the trap, the trap itself comes last
+    pub history: Vec<Disassembly>,
+    pub cpu: CPU,
+}
+
+impl Display for TrapReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.trap, self.passed) {
+            (Some(trap), true) => writeln!(f, "passed, trapped at ${:04X}", trap)?,
+            (Some(trap), false) => writeln!(f, "failed, trapped at ${:04X}", trap)?,
+            (None, _) => writeln!(f, "failed, no trap within {} cycles", self.cycles)?,
+        }
+        writeln!(
+            f,
+            "{} instructions in {} cycles, A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P={}",
+            self.instructions,
+            self.cycles,
+            self.cpu.ac,
+            self.cpu.x,
+            self.cpu.y,
+            self.cpu.sp,
+            flags(self.cpu.sr),
+        )?;
+        for line in &self.history {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn flags(sr: StatusRegister) -> String {
+    "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if sr.bits() & (0x80 >> i) != 0 {
+                c
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::model::CpuModel;
+    use crate::testsuite::klaus::TrapTest;
+
+    fn test(success: Option<u16>, error_flag: Option<u16>) -> TrapTest {
+        TrapTest {
+            load: 0x0400,
+            start: 0x0400,
+            success,
+            error_flag,
+            max_cycles: 1000,
+        }
+    }
+
+    // LDA #$01; CMP #$01; BNE fail; success: JMP success; fail: BNE fail
+    const PROGRAM: [u8; 11] = [
+        0xA9, 0x01, 0xC9, 0x01, 0xD0, 0x03, 0x4C, 0x06, 0x04, 0xD0, 0xFE,
+    ];
+
+    #[test]
+    fn success_trap() {
+        let report = test(Some(0x0406), None).run(CpuModel::Nmos6502, &PROGRAM);
+        assert_eq!(report.trap, Some(0x0406));
+        assert!(report.passed);
+        assert_eq!(report.instructions, 4);
+        let text = report.to_string();
+        assert!(text.starts_with("passed, trapped at $0406\n"), "{}", text);
+        assert!(text.ends_with("0406  4C 06 04  JMP $0406\n"), "{}", text);
+    }
+
+    #[test]
+    fn failure_trap() {
+        let mut program = PROGRAM;
+        program[3] = 0x02;
+        let report = test(Some(0x0406), None).run(CpuModel::Nmos6502, &program);
+        assert_eq!(report.trap, Some(0x0409));
+        assert!(!report.passed);
+        assert!(report.to_string().contains("0402  C9 02     CMP #$02\n"));
+    }
+
+    #[test]
+    fn error_flag() {
+        let report = test(None, Some(0x0000)).run(CpuModel::Nmos6502, &PROGRAM);
+        assert!(report.passed);
+        let report = test(None, Some(0x0400)).run(CpuModel::Nmos6502, &PROGRAM);
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn cycle_limit() {
+        // CLC; BCC -3
+        let report = test(None, None).run(CpuModel::Nmos6502, &[0x18, 0x90, 0xFD]);
+        assert_eq!(report.trap, None);
+        assert!(!report.passed);
+        assert_eq!(report.cycles, 1000);
+    }
+}
@@ -0,0 +1,437 @@
+//! The single step tests from <https://github.com/SingleStepTests/ProcessorTests>.
+//! There's one JSON file per opcode (`6502/v1/a9.json`, `wdc65c02/v1/a9.json`,
+//! ...) with thousands of cases each. A case lists the registers and memory
+//! before and after the instruction, and every bus cycle in between, so the
+//! cycles of the generated microcode are checked as well as its results.
+
+use crate::cpu::model::CpuModel;
+use crate::cpu::opcodes::Instruction;
+use crate::cpu::{Pins, ReadWrite, StatusRegister, CPU};
+use crate::device::{Device, WriteError};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::{fs, io};
+use thiserror::Error;
+
+/// How many cycles past the expected ones we keep ticking before giving up,
+/// `JAM` never fetches the next opcode.
+const EXTRA_CYCLES: usize = 8;
+
+/// B and the unused bit aren't part of the register, they only appear on the
+/// stack, which is checked through the bus cycles instead.
+const PHANTOM_FLAGS: u8 = StatusRegister::B.bits() | StatusRegister::X.bits();
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: State,
+    #[serde(rename = "final")]
+    pub expected: State,
+    pub cycles: Vec<Cycle>,
+}
+
+/// The registers and the memory that an instruction touches.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct State {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+/// One bus cycle, stored as `[address, value, "read"]` in the JSON.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+pub struct Cycle(pub u16, pub u8, pub ReadWrite);
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let rw = match self.2 {
+            ReadWrite::Read => "read",
+            ReadWrite::Write => "write",
+        };
+        write!(f, "{} ${:02X} at ${:04X}", rw, self.1, self.0)
+    }
+}
+
+/// 64K of memory that remembers every access.
+pub struct RecordingBus {
+    memory: Box<[u8; 0x10000]>,
+    pub cycles: Vec<Cycle>,
+}
+
+impl RecordingBus {
+    pub fn new(ram: &[(u16, u8)]) -> Self {
+        let mut memory = Box::new([0; 0x10000]);
+        for &(address, value) in ram {
+            memory[address as usize] = value;
+        }
+        Self {
+            memory,
+            cycles: Vec::new(),
+        }
+    }
+
+    /// Looks at memory without recording a cycle.
+    pub fn get(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
+
+impl Device for RecordingBus {
+    fn read(&mut self, address: u16) -> Option<u8> {
+        let value = self.memory[address as usize];
+        self.cycles.push(Cycle(address, value, ReadWrite::Read));
+        Some(value)
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        self.memory[address as usize] = data;
+        self.cycles.push(Cycle(address, data, ReadWrite::Write));
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(self.get(address))
+    }
+}
+
+/// Something that came out different from what the test expected.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Difference {
+    Register {
+        name: &'static str,
+        expected: u16,
+        actual: u16,
+    },
+    Memory {
+        address: u16,
+        expected: u8,
+        actual: u8,
+    },
+    Cycle {
+        index: usize,
+        expected: Option<Cycle>,
+        actual: Option<Cycle>,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Register {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{} is ${:02X}, expected ${:02X}", name, actual, expected),
+            Difference::Memory {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "${:04X} is ${:02X}, expected ${:02X}",
+                address, actual, expected
+            ),
+            Difference::Cycle {
+                index,
+                expected,
+                actual,
+            } => {
+                write!(f, "cycle {}: ", index)?;
+                match actual {
+                    Some(c) => write!(f, "{}", c)?,
+                    None => write!(f, "nothing")?,
+                }
+                match expected {
+                    Some(c) => write!(f, ", expected {}", c),
+                    None => write!(f, ", expected the next opcode fetch"),
+                }
+            }
+        }
+    }
+}
+
+/// A case that didn't pass.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Failure {
+    pub name: String,
+    pub differences: Vec<Difference>,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for d in &self.differences {
+            write!(f, "\n    {}", d)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a single case, starting with the opcode fetch.
+pub fn run_case(model: CpuModel, case: &TestCase) -> Result<(), Failure> {
+    let start = &case.initial;
+    let mut bus = RecordingBus::new(&start.ram);
+    let mut cpu = CPU::with_model(model);
+    cpu.pc = start.pc;
+    cpu.sp = start.s;
+    cpu.ac = start.a;
+    cpu.x = start.x;
+    cpu.y = start.y;
+    cpu.sr = StatusRegister::from_bits_truncate(start.p);
+
+    // the opcode fetch is the last cycle of the previous instruction, so we do
+    // it here instead of running a reset
+    let mut pins = Pins::new();
+    pins.res = false;
+    pins.address = start.pc;
+    pins.data = bus.read(start.pc).unwrap_or(0);
+
+    let limit = case.cycles.len() + EXTRA_CYCLES;
+    while bus.cycles.len() < limit {
+        pins = cpu.tick(pins);
+        if pins.sync {
+            break;
+        }
+        if pins.rw == ReadWrite::Read {
+            pins.data = bus.read(pins.address).unwrap_or(0);
+        } else {
+            let _ = bus.write(pins.address, pins.data);
+        }
+    }
+
+    let end = &case.expected;
+    let mut differences = Vec::new();
+    let registers = [
+        ("PC", end.pc, cpu.pc),
+        ("S", end.s as u16, cpu.sp as u16),
+        ("A", end.a as u16, cpu.ac as u16),
+        ("X", end.x as u16, cpu.x as u16),
+        ("Y", end.y as u16, cpu.y as u16),
+        (
+            "P",
+            (end.p | PHANTOM_FLAGS) as u16,
+            (cpu.sr.bits() | PHANTOM_FLAGS) as u16,
+        ),
+    ];
+    for (name, expected, actual) in registers {
+        if expected != actual {
+            differences.push(Difference::Register {
+                name,
+                expected,
+                actual,
+            });
+        }
+    }
+    for &(address, expected) in &end.ram {
+        let actual = bus.get(address);
+        if actual != expected {
+            differences.push(Difference::Memory {
+                address,
+                expected,
+                actual,
+            });
+        }
+    }
+    // only the first cycle that differs, the rest usually follows from it
+    let count = case.cycles.len().max(bus.cycles.len());
+    if let Some(index) = (0..count).find(|&i| case.cycles.get(i) != bus.cycles.get(i)) {
+        differences.push(Difference::Cycle {
+            index,
+            expected: case.cycles.get(index).copied(),
+            actual: bus.cycles.get(index).copied(),
+        });
+    }
+
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure {
+            name: case.name.clone(),
+            differences,
+        })
+    }
+}
+
+/// The results for all the cases of one opcode.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpcodeReport {
+    pub model: CpuModel,
+    pub opcode: u8,
+    pub cases: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl OpcodeReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for OpcodeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let instruction = Instruction::for_model(self.model, self.opcode);
+        write!(f, "${:02X} {}", self.opcode, instruction.name(self.opcode))?;
+        if self.passed() {
+            write!(f, ": {} cases passed", self.cases)
+        } else {
+            write!(
+                f,
+                ": {} of {} cases failed, first {}",
+                self.failures.len(),
+                self.cases,
+                self.failures[0]
+            )
+        }
+    }
+}
+
+pub fn run_opcode(model: CpuModel, opcode: u8, cases: &[TestCase]) -> OpcodeReport {
+    let failures = cases
+        .iter()
+        .filter_map(|case| run_case(model, case).err())
+        .collect();
+    OpcodeReport {
+        model,
+        opcode,
+        cases: cases.len(),
+        failures,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("filesystem error: {0}")]
+    FsError(#[from] io::Error),
+    #[error("invalid test file: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<TestCase>, LoadError> {
+    let text = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Runs every `xx.json` in `dir`, opcodes without a file are skipped.
+pub fn run_directory(
+    model: CpuModel,
+    dir: impl AsRef<Path>,
+) -> Result<Vec<OpcodeReport>, LoadError> {
+    let mut reports = Vec::new();
+    for opcode in 0..=255u8 {
+        let path = dir.as_ref().join(format!("{:02x}.json", opcode));
+        if path.exists() {
+            reports.push(run_opcode(model, opcode, &load(path)?));
+        }
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cases(json: &str) -> Vec<TestCase> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn lda_immediate() {
+        let cases = cases(
+            r#"[{
+                "name": "a9 80 00",
+                "initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+                            "ram": [[512, 169], [513, 128]]},
+                "final": {"pc": 514, "s": 253, "a": 128, "x": 0, "y": 0, "p": 164,
+                          "ram": [[512, 169], [513, 128]]},
+                "cycles": [[512, 169, "read"], [513, 128, "read"]]
+            }]"#,
+        );
+        let report = run_opcode(CpuModel::Nmos6502, 0xA9, &cases);
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.to_string(), "$A9 LDA: 1 cases passed");
+    }
+
+    #[test]
+    fn php_pushes_b() {
+        let cases = cases(
+            r#"[{
+                "name": "08",
+                "initial": {"pc": 4096, "s": 255, "a": 0, "x": 0, "y": 0, "p": 1,
+                            "ram": [[4096, 8], [4097, 0]]},
+                "final": {"pc": 4097, "s": 254, "a": 0, "x": 0, "y": 0, "p": 1,
+                          "ram": [[4096, 8], [4097, 0], [511, 49]]},
+                "cycles": [[4096, 8, "read"], [4097, 0, "read"], [511, 49, "write"]]
+            }]"#,
+        );
+        let report = run_opcode(CpuModel::Nmos6502, 0x08, &cases);
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn mismatches() {
+        // STA $1234 with the wrong value in memory and one cycle missing
+        let cases = cases(
+            r#"[{
+                "name": "8d 34 12",
+                "initial": {"pc": 0, "s": 255, "a": 66, "x": 0, "y": 0, "p": 0,
+                            "ram": [[0, 141], [1, 52], [2, 18]]},
+                "final": {"pc": 3, "s": 255, "a": 66, "x": 0, "y": 0, "p": 0,
+                          "ram": [[4660, 67]]},
+                "cycles": [[0, 141, "read"], [1, 52, "read"], [2, 18, "read"]]
+            }]"#,
+        );
+        let report = run_opcode(CpuModel::Nmos6502, 0x8D, &cases);
+        assert_eq!(
+            report.failures[0].differences,
+            vec![
+                Difference::Memory {
+                    address: 0x1234,
+                    expected: 0x43,
+                    actual: 0x42,
+                },
+                Difference::Cycle {
+                    index: 3,
+                    expected: None,
+                    actual: Some(Cycle(0x1234, 0x42, ReadWrite::Write)),
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "$8D STA: 1 of 1 cases failed, first 8d 34 12:\n    \
+             $1234 is $42, expected $43\n    \
+             cycle 3: write $42 at $1234, expected the next opcode fetch"
+        );
+    }
+
+    #[test]
+    fn undocumented_immediates() {
+        let run = |opcode: u8, a: u8, operand: u8, p: u8| {
+            let mut bus = RecordingBus::new(&[(0, opcode), (1, operand)]);
+            let mut cpu = CPU::new();
+            cpu.ac = a;
+            cpu.sr = StatusRegister::from_bits_truncate(p);
+            let mut pins = Pins::new();
+            pins.res = false;
+            pins.data = bus.read(0).unwrap();
+            loop {
+                pins = cpu.tick(pins);
+                if pins.sync {
+                    return (cpu.ac, cpu.sr.bits() & !PHANTOM_FLAGS);
+                }
+                pins.data = bus.read(pins.address).unwrap();
+            }
+        };
+        // ANC: AND, then C = N
+        assert_eq!(run(0x0B, 0xF0, 0x8F, 0x00), (0x80, 0x81));
+        // ASR: AND, then LSR
+        assert_eq!(run(0x4B, 0xF3, 0x3F, 0x00), (0x19, 0x01));
+        // ARR: AND, then ROR with C from bit 6 and V from bit 6 ^ bit 5
+        assert_eq!(run(0x6B, 0xFF, 0xC0, 0x01), (0xE0, 0x81));
+    }
+}
@@ -0,0 +1,69 @@
+//! Builds `code/example2` with the kernel, the way the README does, and types
+//! on the default board without a window.
+
+use olc_pixel_game_engine::Key;
+use rust6502::asm::{link, Assembler, LinkConfig};
+use rust6502::config::{DeviceKind, MachineConfig};
+use rust6502::device::vga::vecs::key_to_scancode;
+use rust6502::machine::{Exit, Stop};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn code(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("code")
+        .join(path)
+}
+
+/// The linked program, written where the config can load it from.
+fn build() -> PathBuf {
+    let mut asm = Assembler::new();
+    asm.include_dir(code("include"));
+    let sources = ["example2/example2.s", "kernel/kernel.s", "kernel/funcs.s"];
+    let objects: Vec<_> = sources
+        .iter()
+        .map(|source| asm.assemble_file(code(source)).unwrap())
+        .collect();
+    let config = LinkConfig::parse(&fs::read_to_string(code("ld/link.ld")).unwrap()).unwrap();
+    let linked = link(&objects, &config).unwrap();
+    // the kernel checks the version after the reset vector
+    assert_eq!(linked.image[2..6], [0x01, 0x00, 0x00, 0x00]);
+
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("example2");
+    fs::write(&path, &linked.image).unwrap();
+    path
+}
+
+#[test]
+fn example2() {
+    let program = build();
+    let mut config =
+        MachineConfig::from_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("machine.toml"))
+            .unwrap();
+    for device in &mut config.devices {
+        if let (Some("program"), DeviceKind::Rom { file, .. }) =
+            (device.name.as_deref(), &mut device.kind)
+        {
+            *file = program.clone();
+        }
+    }
+    let mut board = config.build().unwrap();
+    board
+        .script
+        .lock()
+        .unwrap()
+        .load("100000 type \"hi\"")
+        .unwrap();
+    let vga = board.vga.take().unwrap();
+    let mut machine = board.machine();
+
+    let exit = Exit {
+        cycles: Some(200_000),
+        traps: true,
+        ..Exit::default()
+    };
+    assert_eq!(machine.run(u64::MAX, &exit), Some(Stop::CycleLimit));
+    // the last byte is the scancode of the released I
+    let shown = char::from((key_to_scancode(Key::I) & 0x7F) + 0x40);
+    assert_eq!(vga.text().lines().next(), Some(shown.to_string().as_str()));
+}
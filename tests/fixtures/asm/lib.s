@@ -0,0 +1,20 @@
+.export print, count
+.include "defs.inc"
+
+.segment "HIGH"
+print:
+  ldx #$00
+@loop:
+  lda message,X
+  beq @done
+  sta SCREEN,X
+  inx
+  bne @loop
+@done:
+  rts
+
+.rodata
+message: .asciiz "HI"
+
+.bss
+count: .res 1
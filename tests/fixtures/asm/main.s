@@ -0,0 +1,12 @@
+; linked with lib.s through link.ld, the tests check every byte of the result
+.import print, count
+.include "defs.inc"
+
+.word start
+
+start:
+  lda #COLOR
+  sta SCREEN+1
+  inc count
+  jsr print
+: jmp :-
@@ -0,0 +1,199 @@
+; Checks ADC and SBC in decimal mode against a binary mode prediction, for all
+; 256 values of both operands and both values of the carry. It's the test from
+; Appendix B of Bruce Clark's "Decimal Mode" tutorial on 6502.org, which Klaus
+; Dormann's 6502_decimal_test is built on, with the NMOS 6502 predictions of
+; the accumulator and all of N, V, Z and C.
+;
+; It's loaded and started at $0200 and ends in the trap at `done`, with ERROR
+; at $000B cleared when every result was right. Otherwise N1, N2 and the carry
+; in Y are the inputs that failed.
+
+N1 = $00
+N2 = $01
+HA = $02        ; binary mode result
+HNVZC = $03     ; and its flags
+DA = $04        ; decimal mode result
+DNVZC = $05     ; and its flags
+AR = $06        ; predicted result
+NF = $07        ; predicted flags
+VF = $08
+ZF = $09
+CF = $0A
+ERROR = $0B
+N1L = $0C
+N1H = $0D
+N2L = $0E
+N2H = $0F       ; and N2H+1
+
+test:
+  ldy #1        ; the carry, 1 and then 0
+  sty ERROR
+  lda #0
+  sta N1
+  sta N2
+loop1:
+  lda N2
+  and #$0F
+  sta N2L
+  lda N2
+  and #$F0
+  sta N2H
+  ora #$0F
+  sta N2H+1
+loop2:
+  lda N1
+  and #$0F
+  sta N1L
+  lda N1
+  and #$F0
+  sta N1H
+  jsr add
+  jsr a6502
+  jsr compare
+  bne done
+  jsr sub
+  jsr s6502
+  jsr compare
+  bne done
+  inc N1
+  bne loop2
+  inc N2
+  bne loop1
+  dey
+  bpl loop1
+  lda #0
+  sta ERROR
+done:
+  jmp done
+
+; the decimal and binary results of N1 + N2, and the predicted result, carry
+; and V flag
+add:
+  sed
+  cpy #1        ; the carry is set when Y is 1
+  lda N1
+  adc N2
+  sta DA
+  php
+  pla
+  sta DNVZC
+  cld
+  cpy #1
+  lda N1
+  adc N2
+  sta HA
+  php
+  pla
+  sta HNVZC
+  cpy #1
+  lda N1L
+  adc N2L
+  cmp #$0A
+  ldx #0
+  bcc @low
+  inx
+  adc #5        ; 6, the carry is set
+  and #$0F
+  sec
+@low:
+  ora N1H
+  ; N2 & $F0, or (N2 & $F0) + $10 with the carry when the low digit carried
+  adc N2H,X
+  php
+  bcs @high
+  cmp #$A0
+  bcc @done
+@high:
+  adc #$5F      ; $60, the carry is set
+  sec
+@done:
+  sta AR
+  php
+  pla
+  sta CF
+  pla           ; all of P from after the high digit, for V and N
+  sta VF
+  rts
+
+; the decimal and binary results of N1 - N2
+sub:
+  sed
+  cpy #1
+  lda N1
+  sbc N2
+  sta DA
+  php
+  pla
+  sta DNVZC
+  cld
+  cpy #1
+  lda N1
+  sbc N2
+  sta HA
+  php
+  pla
+  sta HNVZC
+  rts
+
+; the predicted result of N1 - N2
+sub1:
+  cpy #1
+  lda N1L
+  sbc N2L
+  ldx #0
+  bcs @low
+  inx
+  sbc #5        ; 6, the carry is clear
+  and #$0F
+  clc
+@low:
+  ora N1H
+  ; N2 & $F0, or (N2 & $F0) + $10 without the carry when the low digit borrowed
+  sbc N2H,X
+  bcs @done
+  sbc #$5F      ; $60, the carry is clear
+@done:
+  sta AR
+  rts
+
+; Z is set when the decimal result and flags are the predicted ones
+compare:
+  lda DA
+  cmp AR
+  bne @done
+  lda DNVZC
+  eor NF
+  and #$80
+  bne @done
+  lda DNVZC
+  eor VF
+  and #$40
+  bne @done
+  lda DNVZC
+  eor ZF
+  and #$02
+  bne @done
+  lda DNVZC
+  eor CF
+  and #$01
+@done:
+  rts
+
+; on the NMOS 6502 N and V of ADC come from the high digit before it's
+; adjusted and Z from the binary result
+a6502:
+  lda VF
+  sta NF
+  lda HNVZC
+  sta ZF
+  rts
+
+; and the flags of SBC are the ones of the binary result
+s6502:
+  jsr sub1
+  lda HNVZC
+  sta NF
+  sta VF
+  sta ZF
+  sta CF
+  rts
@@ -0,0 +1,51 @@
+//! Klaus Dormann's images aren't in the repository, see
+//! `tests/fixtures/klaus/README.md` for how to get them and run their tests
+//! with `cargo test --release --test klaus -- --ignored`. The decimal test
+//! they're built on is assembled from source and always runs.
+
+use rust6502::asm::{link, Assembler, LinkConfig};
+use rust6502::cpu::model::CpuModel;
+use rust6502::testsuite::klaus::TrapTest;
+use std::fs;
+use std::path::Path;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/klaus")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {}, see tests/fixtures/klaus/README.md",
+            path.display(),
+            e
+        )
+    })
+}
+
+#[test]
+#[ignore]
+fn functional() {
+    let image = fixture("6502_functional_test.bin");
+    let report = TrapTest::functional().run(CpuModel::Nmos6502, &image);
+    assert!(report.passed, "{}", report);
+}
+
+#[test]
+#[ignore]
+fn decimal() {
+    let image = fixture("6502_decimal_test.bin");
+    let report = TrapTest::decimal().run(CpuModel::Nmos6502, &image);
+    assert!(report.passed, "{}", report);
+}
+
+/// The decimal test Klaus Dormann's is built on, assembled from
+/// `tests/fixtures/decimal`, so `cargo test` covers ADC and SBC in decimal
+/// mode without the images.
+#[test]
+fn decimal_from_source() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/decimal/decimal_test.s");
+    let object = Assembler::new().assemble_file(&path).unwrap();
+    let image = link(&[object], &LinkConfig::flat(0x0200)).unwrap().image;
+    let report = TrapTest::decimal().run(CpuModel::Nmos6502, &image);
+    assert!(report.passed, "{}", report);
+}
@@ -0,0 +1,43 @@
+//! The tests aren't in the repository, see
+//! `tests/fixtures/processor_tests/README.md` for how to get them and run these
+//! with `cargo test --release --test processor_tests -- --ignored`.
+
+use rust6502::cpu::model::CpuModel;
+use rust6502::testsuite::processor_tests::run_directory;
+use std::path::Path;
+
+fn run(model: CpuModel, dir: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/processor_tests")
+        .join(dir);
+    let reports = run_directory(model, &path).unwrap();
+    assert!(
+        !reports.is_empty(),
+        "there are no tests in {}, see tests/fixtures/processor_tests/README.md",
+        path.display()
+    );
+    let failed: Vec<String> = reports
+        .iter()
+        .filter(|r| !r.passed())
+        .map(|r| r.to_string())
+        .collect();
+    assert!(
+        failed.is_empty(),
+        "{} of {} opcodes failed:\n{}",
+        failed.len(),
+        reports.len(),
+        failed.join("\n")
+    );
+}
+
+#[test]
+#[ignore]
+fn nmos6502() {
+    run(CpuModel::Nmos6502, "6502/v1");
+}
+
+#[test]
+#[ignore]
+fn wdc65c02() {
+    run(CpuModel::Wdc65C02, "wdc65c02/v1");
+}